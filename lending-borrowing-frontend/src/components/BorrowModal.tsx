@@ -2,7 +2,15 @@ import { useState } from "react";
 import { useWallet } from "@solana/wallet-adapter-react";
 import { PublicKey, SystemProgram } from "@solana/web3.js";
 import { useProgram } from "../hooks/useProgram";
-import { getPDAs, getUserPositionPDA, getConfigPDA, getUserPoolPositionPDA } from "../utils/anchor-client";
+import {
+    getCollateralAccounts,
+    getEModeCategoryAccount,
+    getPDAs,
+    getSecondaryOracle,
+    getUserPositionPDA,
+    getConfigPDA,
+    getUserPoolPositionPDA,
+} from "../utils/anchor-client";
 import { TOKEN_PROGRAM_ID, getAssociatedTokenAddress } from "@solana/spl-token";
 import { BN } from "@coral-xyz/anchor";
 interface BorrowModalProps {
@@ -47,11 +55,15 @@ export const BorrowModal = ({ poolAddress, mintAddress, onClose, onSuccess }: Bo
                     userAta: userAta,
                     userPoolPosition: userPoolPosition,
                     userPosition: userPosition,
+                    emodeCategory: await getEModeCategoryAccount(program, publicKey),
                     vault: vault,
                     oracle: oracle,
+                    secondaryOracle: getSecondaryOracle(poolAccount),
                     tokenProgram: TOKEN_PROGRAM_ID,
                     systemProgram: SystemProgram.programId,
                 })
+                // every pool the user holds dTokens in, this one included
+                .remainingAccounts(await getCollateralAccounts(program, publicKey))
                 .rpc();
 
             console.log("Borrow tx:", tx);
@@ -2,8 +2,16 @@ import { useState } from "react";
 import { useWallet } from "@solana/wallet-adapter-react";
 import { PublicKey, SystemProgram } from "@solana/web3.js";
 import { useProgram } from "../hooks/useProgram";
-import { getPDAs, getUserPoolPositionPDA, getUserPositionPDA } from "../utils/anchor-client";
-import { ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, getAssociatedTokenAddress } from "@solana/spl-token";
+import {
+    getCollateralAccounts,
+    getEModeCategoryAccount,
+    getPDAs,
+    getSecondaryOracle,
+    getUserDtokenAta,
+    getUserPoolPositionPDA,
+    getUserPositionPDA,
+} from "../utils/anchor-client";
+import { ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID, getAssociatedTokenAddress } from "@solana/spl-token";
 import { BN, Wallet } from "@coral-xyz/anchor";
 
 
@@ -38,7 +46,7 @@ export const DepositModal = ({ poolAddress, mintAddress, onClose, onSuccess }: D
             const config = new PublicKey(poolAccount.config);
             const oracle = new PublicKey(poolAccount.oracle);
             const userTokenAta = await getAssociatedTokenAddress(mint, publicKey);
-            const userDtokenAta = await getAssociatedTokenAddress(dtokenMint, publicKey);
+            const userDtokenAta = getUserDtokenAta(dtokenMint, publicKey);
             const userPoolPosition = getUserPoolPositionPDA(publicKey, pool);
             const userPosition = getUserPositionPDA(publicKey);
             const depositAmount = new BN(Math.floor(parseFloat(amount) * Math.pow(10, 9)));
@@ -59,6 +67,7 @@ export const DepositModal = ({ poolAddress, mintAddress, onClose, onSuccess }: D
                     userPoolPosition: userPoolPosition,
                     systemProgram: SystemProgram.programId,
                     tokenProgram: TOKEN_PROGRAM_ID,
+                    dtokenProgram: TOKEN_2022_PROGRAM_ID,
                     associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
                 })
                 .rpc();
@@ -67,18 +76,24 @@ export const DepositModal = ({ poolAddress, mintAddress, onClose, onSuccess }: D
 
             // 2 update_deposit_position
             console.log("Step 2: Calling update_deposit_position...");
+            // collateral is read from the dTokens the user now holds, here and
+            // in every other pool they hold dTokens in
             const tx2 = await program.methods
-                .updateDepositPosition(depositAmount)
+                .updateDepositPosition()
                 .accountsPartial({
                     user: publicKey,
                     underlyingMint: mint,
                     config: config,
                     pool: pool,
                     userPosition: userPosition,
-                    userPoolPosition: userPoolPosition,
+                    emodeCategory: await getEModeCategoryAccount(program, publicKey),
+                    userDtokenAta: userDtokenAta,
                     oracle: oracle,
+                    secondaryOracle: getSecondaryOracle(poolAccount),
+                    dtokenProgram: TOKEN_2022_PROGRAM_ID,
                     systemProgram: SystemProgram.programId,
                 })
+                .remainingAccounts(await getCollateralAccounts(program, publicKey, pool))
                 .rpc();
 
             console.log("update_deposit_position tx:", tx2);
@@ -31,10 +31,15 @@ export const RepayModal = ({ poolAddress, mintAddress, onClose, onSuccess }: Rep
             const poolAccount = await (program.account as any).pool.fetch(pool);
             const vault = new PublicKey(poolAccount.vault);
             const config = new PublicKey(poolAccount.config);
-            const oracle = new PublicKey(poolAccount.oracle);
             const userMintAta = await getAssociatedTokenAddress(mint, publicKey);
             const userPoolPosition = getUserPoolPositionPDA(publicKey, pool);
             const userPosition = getUserPositionPDA(publicKey);
+            // isolated debt is released on the isolated pool, passed separately
+            // when it is not the pool being repaid
+            const userPositionAccount = await (program.account as any).userPosition.fetch(userPosition);
+            const isolationPool = new PublicKey(userPositionAccount.isolationPool);
+            const isolationPoolAccount =
+                isolationPool.equals(PublicKey.default) || isolationPool.equals(pool) ? null : isolationPool;
 
             const repayAmount = new BN(Math.floor(parseFloat(amount) * Math.pow(10, 9)));
 
@@ -49,7 +54,7 @@ export const RepayModal = ({ poolAddress, mintAddress, onClose, onSuccess }: Rep
                     vault: vault,
                     userPosition: userPosition,
                     userPoolPosition: userPoolPosition,
-                    oracle: oracle,
+                    isolationPool: isolationPoolAccount,
                     tokenProgram: TOKEN_PROGRAM_ID,
                 })
                 .rpc();
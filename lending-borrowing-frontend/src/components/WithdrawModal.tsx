@@ -2,8 +2,16 @@ import { useState } from "react";
 import { useWallet } from "@solana/wallet-adapter-react";
 import { PublicKey, SystemProgram } from "@solana/web3.js";
 import { useProgram } from "../hooks/useProgram";
-import { getPDAs, getUserPoolPositionPDA, getUserPositionPDA } from "../utils/anchor-client";
-import { TOKEN_PROGRAM_ID, getAssociatedTokenAddress, ASSOCIATED_TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import {
+    getCollateralAccounts,
+    getEModeCategoryAccount,
+    getPDAs,
+    getSecondaryOracle,
+    getUserDtokenAta,
+    getUserPoolPositionPDA,
+    getUserPositionPDA,
+} from "../utils/anchor-client";
+import { TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, getAssociatedTokenAddress, ASSOCIATED_TOKEN_PROGRAM_ID } from "@solana/spl-token";
 import { BN } from "@coral-xyz/anchor";
 interface WithdrawModalProps {
     poolAddress: string;
@@ -34,7 +42,7 @@ export const WithdrawModal = ({ poolAddress, mintAddress, onClose, onSuccess }:
             const config = new PublicKey(poolAccount.config);
             const oracle = new PublicKey(poolAccount.oracle);
             const userTokenAta = await getAssociatedTokenAddress(mint, publicKey);
-            const userDtokenAta = await getAssociatedTokenAddress(dtokenMint, publicKey);
+            const userDtokenAta = getUserDtokenAta(dtokenMint, publicKey);
             const userPoolPosition = getUserPoolPositionPDA(publicKey, pool);
             const userPosition = getUserPositionPDA(publicKey);
             const dtokenAmountBN = new BN(Math.floor(parseFloat(dtokenAmount) * Math.pow(10, 9)));
@@ -51,11 +59,16 @@ export const WithdrawModal = ({ poolAddress, mintAddress, onClose, onSuccess }:
                     userTokenAta: userTokenAta,
                     userPoolPosition: userPoolPosition,
                     userPosition: userPosition,
+                    emodeCategory: await getEModeCategoryAccount(program, publicKey),
                     oracle: oracle,
+                    secondaryOracle: getSecondaryOracle(poolAccount),
                     tokenProgram: TOKEN_PROGRAM_ID,
+                    dtokenProgram: TOKEN_2022_PROGRAM_ID,
                     associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
                     systemProgram: SystemProgram.programId,
                 })
+                // the user's collateral in every other pool
+                .remainingAccounts(await getCollateralAccounts(program, publicKey, pool))
                 .rpc();
 
             console.log("Withdraw tx:", tx);
@@ -1,8 +1,9 @@
 import { AnchorProvider, Program, Idl } from "@coral-xyz/anchor";
-import { Connection, PublicKey } from "@solana/web3.js";
+import { AccountMeta, Connection, PublicKey } from "@solana/web3.js";
 import type { AnchorWallet } from "@solana/wallet-adapter-react";
+import { TOKEN_2022_PROGRAM_ID, getAssociatedTokenAddressSync, unpackAccount } from "@solana/spl-token";
 import idl from "../idl/lending_borrowing.json";
-import { PROGRAM_ID, NETWORK, ADMIN_PUBKEY } from "./constants";
+import { PROGRAM_ID, NETWORK, ADMIN_PUBKEY, MARKET_ID } from "./constants";
 import { Buffer } from "buffer";
 
 export const getProgram = (wallet: AnchorWallet | undefined) => {
@@ -46,8 +47,10 @@ export const getPDAs = (mint: PublicKey) => {
 
 export const getConfigPDA = () => {
     const adminPubkey = new PublicKey(ADMIN_PUBKEY);
+    const marketId = Buffer.alloc(2);
+    marketId.writeUInt16LE(MARKET_ID);
     const [config] = PublicKey.findProgramAddressSync(
-        [Buffer.from("config"), adminPubkey.toBuffer()],
+        [Buffer.from("config"), adminPubkey.toBuffer(), marketId],
         PROGRAM_ID
     );
     return config;
@@ -55,7 +58,7 @@ export const getConfigPDA = () => {
 
 export const getUserPositionPDA = (user: PublicKey) => {
     const [userPosition] = PublicKey.findProgramAddressSync(
-        [Buffer.from("user-position"), user.toBuffer()],
+        [Buffer.from("user-position"), getConfigPDA().toBuffer(), user.toBuffer()],
         PROGRAM_ID
     );
     return userPosition;
@@ -66,4 +69,54 @@ export const getUserPoolPositionPDA = (user: PublicKey, pool: PublicKey) => {
         PROGRAM_ID,
     );
     return userPoolPosition;
-}
\ No newline at end of file
+}
+
+// dTokens are Token-2022 tokens
+export const getUserDtokenAta = (dtokenMint: PublicKey, user: PublicKey) =>
+    getAssociatedTokenAddressSync(dtokenMint, user, false, TOKEN_2022_PROGRAM_ID);
+
+// the pool's secondary oracle, or null when it has none
+export const getSecondaryOracle = (poolAccount: any) =>
+    poolAccount.secondaryOracleSource ? new PublicKey(poolAccount.secondaryOracle) : null;
+
+// the e-mode category account of `user`, or null when they are not in e-mode
+export const getEModeCategoryAccount = async (program: Program, user: PublicKey) => {
+    const position = await (program.account as any).userPosition.fetchNullable(getUserPositionPDA(user));
+    if (!position || position.emodeCategory === 0) return null;
+    const [emodeCategory] = PublicKey.findProgramAddressSync(
+        [Buffer.from("emode-category"), getConfigPDA().toBuffer(), Buffer.from([position.emodeCategory])],
+        PROGRAM_ID
+    );
+    return emodeCategory;
+};
+
+// [pool, user_dtoken_ata, oracle(, secondary_oracle)] groups for every pool of
+// the market where `user` holds dTokens, passed as remaining accounts to
+// borrow, withdraw and update_deposit_position. Leaving a pool out only
+// understates the user's collateral.
+export const getCollateralAccounts = async (
+    program: Program,
+    user: PublicKey,
+    excludePool?: PublicKey
+): Promise<AccountMeta[]> => {
+    const config = getConfigPDA();
+    const pools = ((await (program.account as any).pool.all()) as { publicKey: PublicKey; account: any }[]).filter(
+        ({ publicKey, account }) =>
+            new PublicKey(account.config).equals(config) && !(excludePool && publicKey.equals(excludePool))
+    );
+    const dtokenAtas = pools.map(({ account }) => getUserDtokenAta(new PublicKey(account.mintDtoken), user));
+    const ataInfos = await program.provider.connection.getMultipleAccountsInfo(dtokenAtas);
+
+    return pools.flatMap(({ publicKey, account }, i) => {
+        const info = ataInfos[i];
+        if (!info || unpackAccount(dtokenAtas[i], info, TOKEN_2022_PROGRAM_ID).amount === 0n) return [];
+        const group: AccountMeta[] = [
+            { pubkey: publicKey, isSigner: false, isWritable: true },
+            { pubkey: dtokenAtas[i], isSigner: false, isWritable: false },
+            { pubkey: new PublicKey(account.oracle), isSigner: false, isWritable: false },
+        ];
+        const secondaryOracle = getSecondaryOracle(account);
+        if (secondaryOracle) group.push({ pubkey: secondaryOracle, isSigner: false, isWritable: false });
+        return group;
+    });
+};
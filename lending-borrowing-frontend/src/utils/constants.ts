@@ -10,4 +10,7 @@ export const NETWORK =
 export const API_BASE_URL =
     import.meta.env.VITE_API_URL || "http://localhost:3000";
 
-export const ADMIN_PUBKEY = "DwUkSRrMWtcxsqVEJk7coMwpRVXDdxS2mxBPjMMgN1pY";
\ No newline at end of file
+export const ADMIN_PUBKEY = "DwUkSRrMWtcxsqVEJk7coMwpRVXDdxS2mxBPjMMgN1pY";
+
+// market of ADMIN_PUBKEY the app trades on
+export const MARKET_ID = Number(import.meta.env.VITE_MARKET_ID ?? 0);
\ No newline at end of file
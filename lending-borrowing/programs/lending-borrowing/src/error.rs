@@ -26,4 +26,94 @@ pub enum Errors {
     InvalidOraclePrice,
     #[msg("InvalidPrice")]
     InvalidPrice,
+    #[msg("Invalid dToken metadata")]
+    InvalidMetadata,
+    #[msg("Mint has an unsupported token extension")]
+    UnsupportedMintExtension,
+    #[msg("Invalid collateral accounts")]
+    InvalidCollateralAccounts,
+    #[msg("Pool does not hold native SOL")]
+    NotNativeMint,
+    #[msg("Invalid oracle configuration")]
+    InvalidOracleConfig,
+    #[msg("Oracle confidence interval too wide")]
+    OracleConfidenceTooWide,
+    #[msg("Oracle account does not match the pool's oracle source")]
+    InvalidOracleAccount,
+    #[msg("Oracle source not supported in this build")]
+    UnsupportedOracleSource,
+    #[msg("Pool has a secondary oracle that was not passed")]
+    MissingSecondaryOracle,
+    #[msg("Primary and secondary oracle prices deviate too much")]
+    OraclePriceDeviation,
+    #[msg("Pool TWAP is missing or stale")]
+    StaleTwap,
+    #[msg("Pyth price update is not fully verified")]
+    OracleNotFullyVerified,
+    #[msg("Pyth price update is for a different feed")]
+    OracleFeedMismatch,
+    #[msg("Invalid liquidation bonus configuration")]
+    InvalidLiquidationBonus,
+    #[msg("Invalid protocol liquidation fee")]
+    InvalidProtocolFee,
+    #[msg("Signer is not the fee authority")]
+    NotFeeAuthority,
+    #[msg("Not enough protocol reserves")]
+    InsufficientReserves,
+    #[msg("Liquidator dToken account is required to receive dTokens")]
+    MissingLiquidatorDTokenAccount,
+    #[msg("Invalid close factor configuration")]
+    InvalidCloseFactor,
+    #[msg("Invalid borrower accounts")]
+    InvalidBorrowerAccounts,
+    #[msg("Instruction cannot be invoked through CPI")]
+    CpiNotAllowed,
+    #[msg("Transaction is missing the instruction that settles this one")]
+    MissingSettlementInstruction,
+    #[msg("Pool already has an outstanding flash loan")]
+    FlashLoanActive,
+    #[msg("Pool has no outstanding flash loan")]
+    NoActiveFlashLoan,
+    #[msg("Flash loan was not repaid in full")]
+    FlashLoanNotRepaid,
+    #[msg("Invalid flash loan fee")]
+    InvalidFlashLoanFee,
+    #[msg("Deposit would exceed the pool's supply cap")]
+    SupplyCapExceeded,
+    #[msg("Borrow would exceed the pool's borrow cap")]
+    BorrowCapExceeded,
+    #[msg("Pool cannot be borrowed against isolated collateral")]
+    IsolationModeBorrowNotAllowed,
+    #[msg("Borrow would exceed the isolated collateral's debt ceiling")]
+    IsolationDebtCeilingExceeded,
+    #[msg("Only one isolated asset can back a user's debt")]
+    MultipleIsolatedCollateral,
+    #[msg("User has isolated debt but the isolated pool was not passed")]
+    MissingIsolationPool,
+    #[msg("Invalid e-mode category parameters")]
+    InvalidEModeCategory,
+    #[msg("User is in e-mode but its category account was not passed")]
+    MissingEModeCategory,
+    #[msg("Pool is outside the user's e-mode category")]
+    EModeBorrowNotAllowed,
+    #[msg("E-mode category can only change while nothing is borrowed")]
+    EModeChangeWithOpenBorrows,
+    #[msg("Borrowing is disabled for this pool")]
+    BorrowingDisabled,
+    #[msg("Pool is frozen or deprecated")]
+    PoolNotActive,
+    #[msg("Deprecated pools cannot change status")]
+    InvalidPoolStatusTransition,
+    #[msg("Liquidator collateral account is required to receive collateral tokens")]
+    MissingLiquidatorCollateralAccount,
+    #[msg("Pass only the liquidator account matching receive_dtokens")]
+    UnexpectedLiquidatorCollateralAccount,
+    #[msg("The isolated pool is already passed as another pool account")]
+    DuplicateIsolationPool,
+    #[msg("Borrower still has collateral backing the debt")]
+    BorrowerHasCollateral,
+    #[msg("dTokens cannot be transferred while their owner has open borrows")]
+    DTokenTransferWithOpenBorrows,
+    #[msg("Every pool of the market must be passed")]
+    MissingCollateralPools,
 }
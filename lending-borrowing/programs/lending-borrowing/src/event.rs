@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use crate::state::{PoolStatus, PriceMode};
 
 #[event]
 pub struct DepositEvent {
@@ -7,8 +8,15 @@ pub struct DepositEvent {
     pub mint: Pubkey,
     pub deposit_amount: u64,
     pub dtoken_minted: u64,
-    pub price_usd_1e6: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdatePositionEvent {
+    pub user: Pubkey,
     pub collateral_value_usd: u64,
+    pub debt_value_usd: u64,
+    pub health_factor: u64,
     pub timestamp: i64,
 }
 
@@ -39,6 +47,22 @@ pub struct CreatePoolEvent {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct UpdateDTokenMetadataEvent {
+    pub pool: Pubkey,
+    pub dtoken_mint: Pubkey,
+    pub uri: String,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateOracleConfigEvent {
+    pub pool: Pubkey,
+    pub max_price_age_secs: u64,
+    pub max_confidence_bps: u16,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct RepayEvent {
     pub user: Pubkey,
@@ -53,6 +77,7 @@ pub struct RepayEvent {
 #[event]
 pub struct InitConfigEvent {
     pub config: Pubkey,
+    pub market_id: u16,
 }
 
 #[event]
@@ -63,4 +88,168 @@ pub struct LiquidateEvent {
     pub collater_seized: u64,
     pub debt_pool: Pubkey,
     pub collateral_pool: Pubkey,
+    pub bonus_bps: u64,
+    pub protocol_fee: u64,
+    pub receive_dtokens: bool,
+}
+
+#[event]
+pub struct UpdateSecondaryOracleEvent {
+    pub pool: Pubkey,
+    pub secondary_oracle: Pubkey,
+    pub max_price_deviation_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OracleFallbackEvent {
+    pub pool: Pubkey,
+    pub oracle: Pubkey,
+    pub secondary_oracle: Pubkey,
+    pub price_usd_1e6: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdatePriceModeEvent {
+    pub pool: Pubkey,
+    pub price_mode: PriceMode,
+    pub twap_window_secs: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RefreshTwapEvent {
+    pub pool: Pubkey,
+    pub spot_price_usd_1e6: u64,
+    pub twap_price_usd_1e6: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateLiquidationBonusEvent {
+    pub pool: Pubkey,
+    pub liquidation_bonus_bps: u16,
+    pub max_liquidation_bonus_bps: u16,
+    pub max_bonus_health_factor_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateProtocolLiquidationFeeEvent {
+    pub pool: Pubkey,
+    pub protocol_liquidation_fee_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateCloseFactorEvent {
+    pub pool: Pubkey,
+    pub close_factor_bps: u16,
+    pub critical_health_factor_bps: u16,
+    pub full_liquidation_debt_usd: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawReservesEvent {
+    pub pool: Pubkey,
+    pub fee_authority: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub remaining_reserves: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BadDebtEvent {
+    pub borrower: Pubkey,
+    pub debt_pool: Pubkey,
+    pub bad_debt: u64,
+    pub covered_by_reserves: u64,
+    pub socialized: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FlashBorrowEvent {
+    pub user: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FlashRepayEvent {
+    pub user: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub protocol_fee: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateFlashLoanFeeEvent {
+    pub pool: Pubkey,
+    pub flash_loan_fee_bps: u16,
+    pub flash_loan_protocol_share_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdatePoolCapsEvent {
+    pub pool: Pubkey,
+    pub supply_cap: u64,
+    pub borrow_cap: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateIsolationModeEvent {
+    pub pool: Pubkey,
+    pub isolated: bool,
+    pub isolation_debt_ceiling_usd: u64,
+    pub borrowable_in_isolation: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateEModeCategoryEvent {
+    pub emode_category: Pubkey,
+    pub id: u8,
+    pub ltv_bps: u16,
+    pub liquidation_threshold_bps: u16,
+    pub liquidation_bonus_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SetPoolEModeCategoryEvent {
+    pub pool: Pubkey,
+    pub emode_category: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SetUserEModeEvent {
+    pub user: Pubkey,
+    pub emode_category: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdatePoolFlagsEvent {
+    pub pool: Pubkey,
+    pub borrowing_enabled: bool,
+    pub collateral_enabled: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdatePoolStatusEvent {
+    pub pool: Pubkey,
+    pub previous_status: PoolStatus,
+    pub status: PoolStatus,
+    pub timestamp: i64,
 }
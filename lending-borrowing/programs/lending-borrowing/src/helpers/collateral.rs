@@ -0,0 +1,211 @@
+use crate::{
+    error::Errors,
+    helpers::{
+        emode::shared_emode_category,
+        oracle::{get_price_usd_1e6, valuation_price_usd_1e6, PriceSide},
+    },
+    math::{calculate_borrowed_value_usd, calculate_underlying_amount},
+    state::{Config, Pool, UserPosition},
+};
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::get_associated_token_address_with_program_id,
+    token_2022::ID as TOKEN_2022_PROGRAM_ID,
+    token_interface::TokenAccount,
+};
+
+/// USD value (1e6 precision) of the underlying a dToken balance redeems for.
+pub fn dtoken_collateral_value_usd(
+    pool: &Pool,
+    dtoken_balance: u64,
+    price_usd_1e6: u64,
+) -> Result<u64> {
+    if dtoken_balance == 0 {
+        return Ok(0);
+    }
+    let underlying_amount = calculate_underlying_amount(
+        dtoken_balance,
+        pool.total_liquidity,
+        pool.total_dtoken_supplied,
+    )?;
+    calculate_borrowed_value_usd(underlying_amount, price_usd_1e6, pool.decimals)
+}
+
+/// Collateral found in `remaining_accounts` by `remaining_collateral`.
+pub struct RemainingCollateral<'info> {
+    pub value_usd: u64,
+    /// The isolated pools among those the user holds dTokens in.
+    pub isolated_pools: Vec<&'info AccountInfo<'info>>,
+    /// The e-mode category shared by those pools, `None` if there are none.
+    pub emode_category: Option<u8>,
+    /// Part of `value_usd` held in pools with collateral disabled, which
+    /// gives no borrowing power.
+    pub disabled_value_usd: u64,
+}
+
+/// Sums the collateral `user` holds in the pools passed through
+/// `remaining_accounts` as `[pool, user_dtoken_ata, oracle]` groups, followed
+/// by the pool's secondary oracle when it has one, and reports the isolated
+/// pools backing it.
+///
+/// Collateral always comes from the live dToken balance, so dTokens the user
+/// transferred away stop counting and dTokens they received start counting.
+/// Leaving a pool out can only understate the user's collateral.
+pub fn remaining_collateral<'info>(
+    remaining_accounts: &'info [AccountInfo<'info>],
+    user: Pubkey,
+    config: Pubkey,
+    exclude_pool: Option<Pubkey>,
+) -> Result<RemainingCollateral<'info>> {
+    let mut seen_pools: Vec<Pubkey> = Vec::with_capacity(remaining_accounts.len() / 3);
+    let mut total_collateral_usd: u64 = 0;
+    let mut isolated_pools: Vec<&'info AccountInfo<'info>> = Vec::new();
+    let mut emode_category: Option<u8> = None;
+    let mut disabled_value_usd: u64 = 0;
+    let mut accounts = remaining_accounts;
+    while !accounts.is_empty() {
+        require!(accounts.len() >= 3, Errors::InvalidCollateralAccounts);
+        let pool = Account::<Pool>::try_from(&accounts[0])?;
+        let pool_key = pool.key();
+        require_keys_eq!(pool.config, config, Errors::InvalidCollateralAccounts);
+        require!(
+            exclude_pool != Some(pool_key) && !seen_pools.contains(&pool_key),
+            Errors::InvalidCollateralAccounts
+        );
+
+        require_keys_eq!(
+            accounts[1].key(),
+            get_associated_token_address_with_program_id(
+                &user,
+                &pool.mint_dtoken,
+                &TOKEN_2022_PROGRAM_ID
+            ),
+            Errors::InvalidCollateralAccounts
+        );
+        let user_dtoken_ata = InterfaceAccount::<TokenAccount>::try_from(&accounts[1])?;
+
+        require_keys_eq!(accounts[2].key(), pool.oracle, Errors::InvalidCollateralAccounts);
+        let group_len = if pool.secondary_oracle_source.is_some() { 4 } else { 3 };
+        require!(accounts.len() >= group_len, Errors::InvalidCollateralAccounts);
+        let spot_price_usd_1e6 =
+            get_price_usd_1e6(&accounts[2], accounts[3..group_len].first(), &pool)?;
+        let price_usd_1e6 = valuation_price_usd_1e6(
+            spot_price_usd_1e6,
+            &accounts[2],
+            accounts[3..group_len].first(),
+            &pool,
+            PriceSide::Collateral,
+        )?;
+
+        if pool.isolated && user_dtoken_ata.amount > 0 {
+            isolated_pools.push(&accounts[0]);
+        }
+        if user_dtoken_ata.amount > 0 {
+            emode_category = shared_emode_category(emode_category, pool.emode_category);
+        }
+
+        let value_usd = dtoken_collateral_value_usd(&pool, user_dtoken_ata.amount, price_usd_1e6)?;
+        total_collateral_usd = total_collateral_usd
+            .checked_add(value_usd)
+            .ok_or(Errors::MathOverflow)?;
+        if !pool.collateral_enabled {
+            disabled_value_usd = disabled_value_usd
+                .checked_add(value_usd)
+                .ok_or(Errors::MathOverflow)?;
+        }
+        seen_pools.push(pool_key);
+        accounts = &accounts[group_len..];
+    }
+    Ok(RemainingCollateral {
+        value_usd: total_collateral_usd,
+        isolated_pools,
+        emode_category,
+        disabled_value_usd,
+    })
+}
+
+/// Checks that `user` holds no dTokens in any pool of `config`. Unlike the
+/// collateral groups, `remaining_accounts` must cover the whole market: a
+/// `[pool, user_dtoken_ata]` pair for every pool `config` has created. A
+/// dToken ATA that was never created counts as empty.
+pub fn require_no_collateral<'info>(
+    remaining_accounts: &'info [AccountInfo<'info>],
+    user: Pubkey,
+    config: &Account<Config>,
+) -> Result<()> {
+    let groups = remaining_accounts.chunks_exact(2);
+    require!(groups.remainder().is_empty(), Errors::InvalidCollateralAccounts);
+    let mut seen_pools: Vec<Pubkey> = Vec::with_capacity(remaining_accounts.len() / 2);
+    for group in groups {
+        let pool = Account::<Pool>::try_from(&group[0])?;
+        let pool_key = pool.key();
+        require_keys_eq!(pool.config, config.key(), Errors::InvalidCollateralAccounts);
+        require!(!seen_pools.contains(&pool_key), Errors::InvalidCollateralAccounts);
+        require_keys_eq!(
+            group[1].key(),
+            get_associated_token_address_with_program_id(
+                &user,
+                &pool.mint_dtoken,
+                &TOKEN_2022_PROGRAM_ID
+            ),
+            Errors::InvalidCollateralAccounts
+        );
+        if !group[1].data_is_empty() {
+            let user_dtoken_ata = InterfaceAccount::<TokenAccount>::try_from(&group[1])?;
+            require!(user_dtoken_ata.amount == 0, Errors::BorrowerHasCollateral);
+        }
+        seen_pools.push(pool_key);
+    }
+    require!(
+        seen_pools.len() as u64 == config.pool_count,
+        Errors::MissingCollateralPools
+    );
+    Ok(())
+}
+
+/// Books `debt_usd` of a user's debt against the debt ceiling of the isolated
+/// pool at `isolation_pool_key`, which becomes the pool the user is isolated
+/// in.
+pub fn book_isolated_debt(
+    isolation_pool_key: Pubkey,
+    isolation_pool: &mut Pool,
+    user_position: &mut UserPosition,
+    debt_usd: u64,
+) -> Result<()> {
+    require!(
+        user_position.isolation_pool == Pubkey::default()
+            || user_position.isolation_pool == isolation_pool_key,
+        Errors::MultipleIsolatedCollateral
+    );
+    let isolated_debt_usd = isolation_pool
+        .isolated_debt_usd
+        .checked_add(debt_usd)
+        .ok_or(Errors::MathOverflow)?;
+    require!(
+        isolated_debt_usd <= isolation_pool.isolation_debt_ceiling_usd,
+        Errors::IsolationDebtCeilingExceeded
+    );
+    isolation_pool.isolated_debt_usd = isolated_debt_usd;
+
+    user_position.isolation_pool = isolation_pool_key;
+    user_position.isolated_debt_usd = user_position
+        .isolated_debt_usd
+        .checked_add(debt_usd)
+        .ok_or(Errors::MathOverflow)?;
+    Ok(())
+}
+
+/// Gives `repaid_usd` of a user's isolated debt back to the isolated pool's
+/// debt ceiling, leaving isolation once none of it is left.
+pub fn release_isolated_debt(
+    isolation_pool: &mut Pool,
+    user_position: &mut UserPosition,
+    repaid_usd: u64,
+) {
+    let released = repaid_usd.min(user_position.isolated_debt_usd);
+    isolation_pool.isolated_debt_usd = isolation_pool.isolated_debt_usd.saturating_sub(released);
+    user_position.isolated_debt_usd -= released;
+    if user_position.isolated_debt_usd == 0 {
+        user_position.isolation_pool = Pubkey::default();
+    }
+}
@@ -0,0 +1,32 @@
+use crate::{
+    error::Errors,
+    state::{EModeCategory, UserPosition},
+};
+use anchor_lang::prelude::*;
+
+/// Folds `pool_category` into the e-mode category shared by the pools seen
+/// so far, `None` before the first one. Pools in different categories share
+/// category 0, i.e. none.
+pub fn shared_emode_category(shared: Option<u8>, pool_category: u8) -> Option<u8> {
+    Some(match shared {
+        Some(category) if category != pool_category => 0,
+        _ => pool_category,
+    })
+}
+
+/// Returns the e-mode category whose parameters value `user_position`: the
+/// one the user opted into, as long as every pool involved belongs to it.
+/// Errors when the user is in e-mode and the category was not passed.
+pub fn active_emode_category<'a>(
+    user_position: &UserPosition,
+    emode_category: Option<&'a EModeCategory>,
+    pools_category: Option<u8>,
+) -> Result<Option<&'a EModeCategory>> {
+    if user_position.emode_category == 0 {
+        return Ok(None);
+    }
+    let category = emode_category
+        .filter(|category| category.id == user_position.emode_category)
+        .ok_or(Errors::MissingEModeCategory)?;
+    Ok((pools_category == Some(category.id)).then_some(category))
+}
@@ -1,5 +1,5 @@
 use crate::error::Errors;
-use crate::state::{Pool, UserPoolPosition};
+use crate::state::{Pool, PoolStatus, UserPoolPosition, UserPosition};
 use anchor_lang::prelude::*;
 
 pub fn update_user_borrow_state(user_position: &mut UserPoolPosition, pool: &Pool) -> Result<()> {
@@ -45,6 +45,60 @@ pub fn update_user_borrow_state(user_position: &mut UserPoolPosition, pool: &Poo
     Ok(())
 }
 
+/// Releases the share of `user_position.borrowed_value_usd` that paying
+/// `repaid` off a debt of `debt` covers, so debt leaves
+/// `UserPosition.debt_value_usd` at the value it was booked at without
+/// needing a price.
+pub fn release_borrowed_value_usd(
+    user_position: &mut UserPoolPosition,
+    debt: u64,
+    repaid: u64,
+) -> Result<u64> {
+    let released = if repaid >= debt {
+        user_position.borrowed_value_usd
+    } else {
+        (user_position.borrowed_value_usd as u128)
+            .checked_mul(repaid as u128)
+            .ok_or(Errors::MathOverflow)?
+            .checked_div(debt as u128)
+            .ok_or(Errors::MathOverflow)? as u64
+    };
+    user_position.borrowed_value_usd -= released;
+    Ok(released)
+}
+
+/// Counts a debt position that is about to go from nothing owed to a
+/// balance in the user's open borrows.
+pub fn open_borrow(
+    user_position: &mut UserPosition,
+    user_pool_position: &mut UserPoolPosition,
+    pool: &Pool,
+) -> Result<()> {
+    user_position.open_borrows = user_position
+        .open_borrows
+        .checked_add(1)
+        .ok_or(Errors::MathOverflow)?;
+    user_pool_position.outside_isolation = !pool.borrowable_in_isolation;
+    if user_pool_position.outside_isolation {
+        user_position.open_borrows_outside_isolation = user_position
+            .open_borrows_outside_isolation
+            .checked_add(1)
+            .ok_or(Errors::MathOverflow)?;
+    }
+    Ok(())
+}
+
+/// Drops a debt position that has been paid off from the user's open
+/// borrows.
+pub fn close_borrow(user_position: &mut UserPosition, user_pool_position: &mut UserPoolPosition) {
+    user_position.open_borrows = user_position.open_borrows.saturating_sub(1);
+    if user_pool_position.outside_isolation {
+        user_position.open_borrows_outside_isolation =
+            user_position.open_borrows_outside_isolation.saturating_sub(1);
+        user_pool_position.outside_isolation = false;
+    }
+}
+
 pub fn accrue_interest(pool: &mut Pool) -> Result<()> {
     let now = Clock::get()?.unix_timestamp as u64;
 
@@ -107,7 +161,10 @@ pub fn update_interest_rate(pool: &mut Pool) -> Result<()> {
     const SECONDS_PER_YEAR: u128 = 31_536_000; // 365 * 24 * 3600
     const ONE: u128 = 1_000_000_000_000_000_000; // 1e18
 
-    let utilization = if pool.total_liquidity > 0 {
+    //deprecated pools charge the full-utilization rate to push borrowers out
+    let utilization = if pool.status == PoolStatus::Deprecated {
+        ONE
+    } else if pool.total_liquidity > 0 {
         (pool.total_borrowed as u128)
             .checked_mul(ONE)
             .ok_or(Errors::MathOverflow)?
@@ -0,0 +1,41 @@
+use crate::error::Errors;
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+
+/// Rejects calls that reach the program through CPI. The instructions sysvar
+/// only lists top-level instructions, so checks on the instructions around
+/// the current one are meaningless unless it is itself top-level.
+pub fn require_top_level_instruction(instructions_sysvar: &AccountInfo) -> Result<usize> {
+    let current_index = load_current_index_checked(instructions_sysvar)? as usize;
+    let current = load_instruction_at_checked(current_index, instructions_sysvar)?;
+    require_keys_eq!(current.program_id, crate::ID, Errors::CpiNotAllowed);
+    Ok(current_index)
+}
+
+/// Requires a later top-level instruction of this program with
+/// `discriminator` that passes `account` at position `account_index`, i.e. the
+/// instruction that settles the current one within the same transaction.
+pub fn require_following_instruction(
+    instructions_sysvar: &AccountInfo,
+    discriminator: &[u8],
+    account_index: usize,
+    account: Pubkey,
+) -> Result<()> {
+    let current_index = require_top_level_instruction(instructions_sysvar)?;
+    let mut index = current_index + 1;
+    while let Ok(instruction) = load_instruction_at_checked(index, instructions_sysvar) {
+        if instruction.program_id == crate::ID
+            && instruction.data.starts_with(discriminator)
+            && instruction
+                .accounts
+                .get(account_index)
+                .is_some_and(|meta| meta.pubkey == account)
+        {
+            return Ok(());
+        }
+        index += 1;
+    }
+    err!(Errors::MissingSettlementInstruction)
+}
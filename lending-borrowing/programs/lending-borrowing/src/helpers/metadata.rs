@@ -0,0 +1,30 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+
+pub const MAX_DTOKEN_NAME_LEN: usize = 32;
+pub const MAX_DTOKEN_SYMBOL_LEN: usize = 10;
+pub const MAX_DTOKEN_URI_LEN: usize = 200;
+
+/// Tops up `account` from `payer` so it stays rent exempt after the token
+/// program reallocates it for variable-length metadata.
+pub fn fund_rent_exemption<'info>(
+    account: AccountInfo<'info>,
+    payer: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+) -> Result<()> {
+    let minimum_balance = Rent::get()?.minimum_balance(account.data_len());
+    let missing_lamports = minimum_balance.saturating_sub(account.lamports());
+    if missing_lamports > 0 {
+        transfer(
+            CpiContext::new(
+                system_program,
+                Transfer {
+                    from: payer,
+                    to: account,
+                },
+            ),
+            missing_lamports,
+        )?;
+    }
+    Ok(())
+}
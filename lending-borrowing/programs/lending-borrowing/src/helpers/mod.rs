@@ -1,2 +1,8 @@
+pub mod collateral;
+pub mod emode;
 pub mod interest;
-pub use interest::*;
+pub mod introspection;
+pub mod metadata;
+pub mod oracle;
+pub mod token;
+pub mod transfer_hook;
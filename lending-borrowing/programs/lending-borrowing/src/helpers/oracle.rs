@@ -0,0 +1,514 @@
+use crate::{
+    error::Errors,
+    event::OracleFallbackEvent,
+    math::normalize_pyth_price_to_usd_1e6,
+    state::{MockOracle, OracleSource, Pool, PriceMode},
+};
+use anchor_lang::{prelude::*, Discriminator};
+use pyth_solana_receiver_sdk::price_update::{PriceUpdateV2, VerificationLevel};
+
+/// Switchboard On-Demand program on mainnet.
+pub const SWITCHBOARD_ON_DEMAND_PROGRAM_ID: Pubkey =
+    pubkey!("SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv");
+
+// Layout of Switchboard's `PullFeedAccountData` (repr(C), after the 8 byte
+// discriminator). Read by offset because the SDK does not build against the
+// Solana version this program uses.
+const SWITCHBOARD_PULL_FEED_DISCRIMINATOR: [u8; 8] = [196, 27, 108, 196, 10, 215, 219, 40];
+const SWITCHBOARD_PULL_FEED_LEN: usize = 8 + 3200;
+const SWITCHBOARD_LAST_UPDATE_TIMESTAMP_OFFSET: usize = 8 + 2208;
+const SWITCHBOARD_RESULT_VALUE_OFFSET: usize = 8 + 2256;
+const SWITCHBOARD_RESULT_STD_DEV_OFFSET: usize = 8 + 2272;
+const SWITCHBOARD_RESULT_SLOT_OFFSET: usize = 8 + 2360;
+/// Switchboard values carry 18 decimals; they are scaled down to this exponent
+/// so they fit the same i64 representation as Pyth prices.
+const SWITCHBOARD_EXPO: i32 = -10;
+const SWITCHBOARD_SCALE_DOWN: i128 = 100_000_000;
+
+/// A raw oracle reading before normalisation.
+struct OraclePrice {
+    price: i64,
+    conf: u64,
+    expo: i32,
+    publish_time: i64,
+}
+
+/// Checks the oracle limits an admin configures on a pool.
+pub fn validate_oracle_config(max_price_age_secs: u64, max_confidence_bps: u16) -> Result<()> {
+    require!(
+        max_price_age_secs > 0 && max_price_age_secs <= i64::MAX as u64,
+        Errors::InvalidOracleConfig
+    );
+    require!(
+        max_confidence_bps > 0 && max_confidence_bps <= 10_000,
+        Errors::InvalidOracleConfig
+    );
+    Ok(())
+}
+
+/// Checks that a pool can value prices with `price_mode`. Any mode but `Spot`
+/// smooths Pyth feeds with their own EMA and every other source with the
+/// pool's TWAP, so a non-Pyth primary or secondary needs a TWAP window.
+pub fn validate_price_mode(pool: &Pool, price_mode: PriceMode, twap_window_secs: u64) -> Result<()> {
+    let needs_twap = pool.oracle_source != OracleSource::PythPull
+        || pool
+            .secondary_oracle_source
+            .is_some_and(|source| source != OracleSource::PythPull);
+    require!(
+        price_mode == PriceMode::Spot || !needs_twap || twap_window_secs > 0,
+        Errors::InvalidOracleConfig
+    );
+    Ok(())
+}
+
+/// Checks that `oracle` is an account `source` can be read from. Pyth
+/// accounts must also be fully verified updates for `feed_id`.
+pub fn validate_oracle_source(
+    source: &OracleSource,
+    oracle: &AccountInfo,
+    feed_id: &[u8; 32],
+) -> Result<()> {
+    match source {
+        OracleSource::PythPull => {
+            load_pyth(oracle, feed_id)?;
+        }
+        OracleSource::SwitchboardOnDemand => {
+            read_switchboard(oracle)?;
+        }
+        OracleSource::FixedPrice { price_usd_1e6 } => {
+            require!(*price_usd_1e6 > 0, Errors::InvalidPrice);
+        }
+        OracleSource::Mock => {
+            load_mock(oracle)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the pool's price from `oracle` according to `pool.oracle_source`,
+/// rejecting it when it is older than `pool.max_price_age_secs` or its
+/// confidence interval is wider than `pool.max_confidence_bps` of the price.
+///
+/// Pools with a secondary oracle must also pass `secondary_oracle`. A fresh
+/// primary is checked against the secondary and rejected when the two are
+/// further apart than `pool.max_price_deviation_bps`; if the secondary cannot
+/// produce a usable price the primary is used alone. A stale primary falls
+/// back to the secondary.
+///
+/// # Returns
+/// The price in USD * 1e6.
+pub fn get_price_usd_1e6(
+    oracle: &AccountInfo,
+    secondary_oracle: Option<&AccountInfo>,
+    pool: &Account<Pool>,
+) -> Result<u64> {
+    require_keys_eq!(oracle.key(), pool.oracle, Errors::InvalidOracleAccount);
+    let clock = Clock::get()?;
+    let primary = read_oracle(&pool.oracle_source, oracle, pool, &clock)?;
+
+    let Some(secondary_source) = pool.secondary_oracle_source else {
+        return primary.to_usd_1e6(pool, &clock);
+    };
+    let secondary_oracle = secondary_oracle.ok_or(Errors::MissingSecondaryOracle)?;
+    require_keys_eq!(
+        secondary_oracle.key(),
+        pool.secondary_oracle,
+        Errors::InvalidOracleAccount
+    );
+
+    if primary.is_fresh(pool, &clock)? {
+        let price_usd_1e6 = primary.to_usd_1e6(pool, &clock)?;
+        //the secondary only cross-checks a fresh primary, so an unusable one
+        //must not block the pool
+        let secondary_price_usd_1e6 = read_oracle(&secondary_source, secondary_oracle, pool, &clock)
+            .and_then(|secondary| secondary.to_usd_1e6(pool, &clock));
+        if let Ok(secondary_price_usd_1e6) = secondary_price_usd_1e6 {
+            check_deviation(
+                price_usd_1e6,
+                secondary_price_usd_1e6,
+                pool.max_price_deviation_bps,
+            )?;
+        }
+        return Ok(price_usd_1e6);
+    }
+
+    let price_usd_1e6 = read_oracle(&secondary_source, secondary_oracle, pool, &clock)?
+        .to_usd_1e6(pool, &clock)?;
+    emit!(OracleFallbackEvent {
+        pool: pool.key(),
+        oracle: pool.oracle,
+        secondary_oracle: pool.secondary_oracle,
+        price_usd_1e6,
+        timestamp: clock.unix_timestamp,
+    });
+    Ok(price_usd_1e6)
+}
+
+/// Which side of a position a price values.
+#[derive(Clone, Copy)]
+pub enum PriceSide {
+    Collateral,
+    Debt,
+}
+
+/// Turns the spot price from `get_price_usd_1e6` into the price used for
+/// borrow limits and liquidations according to `pool.price_mode`.
+///
+/// Smoothing follows the oracle the spot price came from: the primary while
+/// it is fresh, otherwise the secondary it fell back to. A Pyth feed smooths
+/// with its own EMA; every other source uses the pool's TWAP, which must have
+/// been refreshed within `pool.max_price_age_secs`.
+pub fn valuation_price_usd_1e6<'a, 'info>(
+    spot_price_usd_1e6: u64,
+    oracle: &'a AccountInfo<'info>,
+    secondary_oracle: Option<&'a AccountInfo<'info>>,
+    pool: &Pool,
+    side: PriceSide,
+) -> Result<u64> {
+    if pool.price_mode == PriceMode::Spot {
+        return Ok(spot_price_usd_1e6);
+    }
+    require_keys_eq!(oracle.key(), pool.oracle, Errors::InvalidOracleAccount);
+    let clock = Clock::get()?;
+    let (source, spot_oracle) = match (pool.secondary_oracle_source, secondary_oracle) {
+        (Some(secondary_source), Some(secondary_oracle))
+            if !read_oracle(&pool.oracle_source, oracle, pool, &clock)?.is_fresh(pool, &clock)? =>
+        {
+            require_keys_eq!(
+                secondary_oracle.key(),
+                pool.secondary_oracle,
+                Errors::InvalidOracleAccount
+            );
+            (secondary_source, secondary_oracle)
+        }
+        _ => (pool.oracle_source, oracle),
+    };
+    let smoothed_price_usd_1e6 = match source {
+        OracleSource::PythPull => read_pyth_ema(spot_oracle, pool)?.to_usd_1e6(pool, &clock)?,
+        _ => {
+            let twap_age = clock
+                .unix_timestamp
+                .checked_sub(pool.twap_last_update_ts)
+                .ok_or(Errors::MathOverflow)?;
+            require!(
+                pool.twap_last_update_ts > 0 && twap_age <= pool.max_price_age_secs as i64,
+                Errors::StaleTwap
+            );
+            pool.twap_price_usd_1e6
+        }
+    };
+    Ok(match (pool.price_mode, side) {
+        (PriceMode::Conservative, PriceSide::Collateral) => {
+            spot_price_usd_1e6.min(smoothed_price_usd_1e6)
+        }
+        (PriceMode::Conservative, PriceSide::Debt) => {
+            spot_price_usd_1e6.max(smoothed_price_usd_1e6)
+        }
+        _ => smoothed_price_usd_1e6,
+    })
+}
+
+/// Moves the pool's TWAP towards `spot_price_usd_1e6` by the share of
+/// `pool.twap_window_secs` elapsed since the last refresh.
+pub fn update_twap(pool: &mut Pool, spot_price_usd_1e6: u64, now: i64) -> Result<()> {
+    require!(pool.twap_window_secs > 0, Errors::InvalidOracleConfig);
+    if pool.twap_last_update_ts == 0 {
+        pool.twap_price_usd_1e6 = spot_price_usd_1e6;
+    } else {
+        let elapsed = now
+            .checked_sub(pool.twap_last_update_ts)
+            .ok_or(Errors::MathOverflow)?
+            .max(0) as u64;
+        let weight = elapsed.min(pool.twap_window_secs) as i128;
+        let twap = pool.twap_price_usd_1e6 as i128;
+        let delta = (spot_price_usd_1e6 as i128 - twap) * weight / pool.twap_window_secs as i128;
+        pool.twap_price_usd_1e6 =
+            u64::try_from(twap + delta).map_err(|_| error!(Errors::MathOverflow))?;
+    }
+    pool.twap_last_update_ts = now;
+    Ok(())
+}
+
+impl OraclePrice {
+    fn is_fresh(&self, pool: &Pool, clock: &Clock) -> Result<bool> {
+        let age = clock
+            .unix_timestamp
+            .checked_sub(self.publish_time)
+            .ok_or(Errors::MathOverflow)?;
+        Ok(age <= pool.max_price_age_secs as i64)
+    }
+
+    fn to_usd_1e6(&self, pool: &Pool, clock: &Clock) -> Result<u64> {
+        require!(self.is_fresh(pool, clock)?, Errors::InvalidOraclePrice);
+        check_confidence(self.price, self.conf, pool.max_confidence_bps)?;
+        normalize_pyth_price_to_usd_1e6(self.price, self.expo)
+    }
+}
+
+fn read_oracle(
+    source: &OracleSource,
+    oracle: &AccountInfo,
+    pool: &Pool,
+    clock: &Clock,
+) -> Result<OraclePrice> {
+    match *source {
+        OracleSource::PythPull => {
+            // age is checked by the caller so a stale feed can fall back
+            let message = load_pyth(oracle, &pool.feed_id)?.price_message;
+            Ok(OraclePrice {
+                price: message.price,
+                conf: message.conf,
+                expo: message.exponent,
+                publish_time: message.publish_time,
+            })
+        }
+        OracleSource::SwitchboardOnDemand => read_switchboard(oracle),
+        OracleSource::FixedPrice { price_usd_1e6 } => Ok(OraclePrice {
+            price: i64::try_from(price_usd_1e6).map_err(|_| error!(Errors::MathOverflow))?,
+            conf: 0,
+            expo: -6,
+            publish_time: clock.unix_timestamp,
+        }),
+        OracleSource::Mock => {
+            let mock_oracle = load_mock(oracle)?;
+            Ok(OraclePrice {
+                price: mock_oracle.price,
+                conf: mock_oracle.conf,
+                expo: mock_oracle.expo,
+                publish_time: mock_oracle.publish_time,
+            })
+        }
+    }
+}
+
+/// Loads a `PriceUpdateV2` owned by the Pyth receiver, fully verified by the
+/// Wormhole guardians and carrying `feed_id`.
+fn load_pyth(oracle: &AccountInfo, feed_id: &[u8; 32]) -> Result<PriceUpdateV2> {
+    require_keys_eq!(
+        *oracle.owner,
+        pyth_solana_receiver_sdk::ID,
+        Errors::InvalidOracleAccount
+    );
+    let data = oracle.try_borrow_data()?;
+    let price_update = PriceUpdateV2::try_deserialize(&mut &data[..])?;
+    require!(
+        price_update.verification_level == VerificationLevel::Full,
+        Errors::OracleNotFullyVerified
+    );
+    require!(
+        price_update.price_message.feed_id == *feed_id,
+        Errors::OracleFeedMismatch
+    );
+    Ok(price_update)
+}
+
+fn read_pyth_ema(oracle: &AccountInfo, pool: &Pool) -> Result<OraclePrice> {
+    let message = load_pyth(oracle, &pool.feed_id)?.price_message;
+    Ok(OraclePrice {
+        price: message.ema_price,
+        conf: message.ema_conf,
+        expo: message.exponent,
+        publish_time: message.publish_time,
+    })
+}
+
+fn load_mock(oracle: &AccountInfo) -> Result<MockOracle> {
+    require!(cfg!(feature = "test-mode"), Errors::UnsupportedOracleSource);
+    require_keys_eq!(*oracle.owner, crate::ID, Errors::InvalidOracleAccount);
+    let data = oracle.try_borrow_data()?;
+    require!(
+        data.starts_with(MockOracle::DISCRIMINATOR),
+        Errors::InvalidOracleAccount
+    );
+    MockOracle::try_deserialize(&mut &data[..])
+}
+
+fn read_switchboard(oracle: &AccountInfo) -> Result<OraclePrice> {
+    require_keys_eq!(
+        *oracle.owner,
+        SWITCHBOARD_ON_DEMAND_PROGRAM_ID,
+        Errors::InvalidOracleAccount
+    );
+    let data = oracle.try_borrow_data()?;
+    require!(
+        data.len() >= SWITCHBOARD_PULL_FEED_LEN
+            && data[..8] == SWITCHBOARD_PULL_FEED_DISCRIMINATOR,
+        Errors::InvalidOracleAccount
+    );
+    let read_i128 = |offset: usize| i128::from_le_bytes(data[offset..offset + 16].try_into().unwrap());
+    let read_u64 = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+    // a zero slot means the feed has never produced a result
+    require!(
+        read_u64(SWITCHBOARD_RESULT_SLOT_OFFSET) > 0,
+        Errors::InvalidOraclePrice
+    );
+    let price = read_i128(SWITCHBOARD_RESULT_VALUE_OFFSET) / SWITCHBOARD_SCALE_DOWN;
+    let conf = read_i128(SWITCHBOARD_RESULT_STD_DEV_OFFSET) / SWITCHBOARD_SCALE_DOWN;
+    Ok(OraclePrice {
+        price: i64::try_from(price).map_err(|_| error!(Errors::MathOverflow))?,
+        conf: u64::try_from(conf).map_err(|_| error!(Errors::InvalidOraclePrice))?,
+        expo: SWITCHBOARD_EXPO,
+        publish_time: read_u64(SWITCHBOARD_LAST_UPDATE_TIMESTAMP_OFFSET) as i64,
+    })
+}
+
+fn check_deviation(price_usd_1e6: u64, other_price_usd_1e6: u64, max_deviation_bps: u16) -> Result<()> {
+    require!(price_usd_1e6 > 0, Errors::InvalidPrice);
+    let deviation_bps = (price_usd_1e6.abs_diff(other_price_usd_1e6) as u128)
+        .checked_mul(10_000)
+        .ok_or(Errors::MathOverflow)?
+        / price_usd_1e6 as u128;
+    require!(
+        deviation_bps <= max_deviation_bps as u128,
+        Errors::OraclePriceDeviation
+    );
+    Ok(())
+}
+
+fn check_confidence(price: i64, conf: u64, max_confidence_bps: u16) -> Result<()> {
+    require!(price > 0, Errors::InvalidPrice);
+    let max_conf = (price as u128)
+        .checked_mul(max_confidence_bps as u128)
+        .ok_or(Errors::MathOverflow)?
+        / 10_000;
+    require!(conf as u128 <= max_conf, Errors::OracleConfidenceTooWide);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyth_solana_receiver_sdk::price_update::PriceFeedMessage;
+
+    /// SOL/USD pull feed at $150.25 +/- $0.05, last updated at
+    /// `SWITCHBOARD_FIXTURE_UPDATED_AT`.
+    const SWITCHBOARD_FIXTURE: &[u8] =
+        include_bytes!("../../../../tests/fixtures/switchboard_sol_usd_pull_feed.bin");
+    const SWITCHBOARD_FIXTURE_UPDATED_AT: i64 = 1_717_000_000;
+
+    fn pool(max_price_age_secs: u64) -> Pool {
+        let data = vec![0u8; 8 + Pool::INIT_SPACE];
+        let mut pool = Pool::try_deserialize_unchecked(&mut &data[..]).unwrap();
+        pool.max_price_age_secs = max_price_age_secs;
+        pool.max_confidence_bps = 100;
+        pool
+    }
+
+    fn clock(unix_timestamp: i64) -> Clock {
+        Clock {
+            unix_timestamp,
+            ..Clock::default()
+        }
+    }
+
+    fn with_account<T>(owner: Pubkey, data: &[u8], f: impl FnOnce(&AccountInfo) -> T) -> T {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = data.to_vec();
+        let account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+        f(&account)
+    }
+
+    const PYTH_FEED_ID: [u8; 32] = [7; 32];
+
+    fn pyth_update(verification_level: VerificationLevel) -> Vec<u8> {
+        let update = PriceUpdateV2 {
+            write_authority: Pubkey::new_unique(),
+            verification_level,
+            price_message: PriceFeedMessage {
+                feed_id: PYTH_FEED_ID,
+                price: 15_025_000_000,
+                conf: 7_500_000,
+                exponent: -8,
+                publish_time: 1_717_000_000,
+                prev_publish_time: 1_716_999_999,
+                ema_price: 15_010_000_000,
+                ema_conf: 8_000_000,
+            },
+            posted_slot: 270_000_000,
+        };
+        let mut data = Vec::with_capacity(PriceUpdateV2::LEN);
+        update.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn loads_fully_verified_pyth_update() {
+        let data = pyth_update(VerificationLevel::Full);
+        let update = with_account(pyth_solana_receiver_sdk::ID, &data, |oracle| {
+            load_pyth(oracle, &PYTH_FEED_ID)
+        })
+        .unwrap();
+        assert_eq!(update.price_message.price, 15_025_000_000);
+    }
+
+    #[test]
+    fn rejects_pyth_update_for_another_feed() {
+        let data = pyth_update(VerificationLevel::Full);
+        let error = with_account(pyth_solana_receiver_sdk::ID, &data, |oracle| {
+            load_pyth(oracle, &[8; 32]).map(|_| ())
+        })
+        .unwrap_err();
+        assert_eq!(error, Errors::OracleFeedMismatch.into());
+    }
+
+    #[test]
+    fn rejects_partially_verified_pyth_update() {
+        let data = pyth_update(VerificationLevel::Partial { num_signatures: 5 });
+        let error = with_account(pyth_solana_receiver_sdk::ID, &data, |oracle| {
+            load_pyth(oracle, &PYTH_FEED_ID).map(|_| ())
+        })
+        .unwrap_err();
+        assert_eq!(error, Errors::OracleNotFullyVerified.into());
+    }
+
+    #[test]
+    fn reads_switchboard_pull_feed() {
+        let price = with_account(SWITCHBOARD_ON_DEMAND_PROGRAM_ID, SWITCHBOARD_FIXTURE, |oracle| {
+            read_switchboard(oracle)
+        })
+        .unwrap();
+        assert_eq!(price.price, 1_502_500_000_000);
+        assert_eq!(price.conf, 500_000_000);
+        assert_eq!(price.expo, SWITCHBOARD_EXPO);
+        assert_eq!(price.publish_time, SWITCHBOARD_FIXTURE_UPDATED_AT);
+
+        let fresh = clock(SWITCHBOARD_FIXTURE_UPDATED_AT + 60);
+        assert_eq!(price.to_usd_1e6(&pool(60), &fresh).unwrap(), 150_250_000);
+    }
+
+    #[test]
+    fn rejects_stale_switchboard_pull_feed() {
+        let price = with_account(SWITCHBOARD_ON_DEMAND_PROGRAM_ID, SWITCHBOARD_FIXTURE, |oracle| {
+            read_switchboard(oracle)
+        })
+        .unwrap();
+        let stale = clock(SWITCHBOARD_FIXTURE_UPDATED_AT + 61);
+        assert!(!price.is_fresh(&pool(60), &stale).unwrap());
+        assert_eq!(
+            price.to_usd_1e6(&pool(60), &stale).unwrap_err(),
+            Errors::InvalidOraclePrice.into()
+        );
+    }
+
+    #[test]
+    fn rejects_switchboard_feed_from_another_owner() {
+        let error = with_account(Pubkey::new_unique(), SWITCHBOARD_FIXTURE, |oracle| {
+            read_switchboard(oracle).map(|_| ())
+        })
+        .unwrap_err();
+        assert_eq!(error, Errors::InvalidOracleAccount.into());
+    }
+
+    #[test]
+    fn rejects_switchboard_feed_without_a_result() {
+        let mut data = SWITCHBOARD_FIXTURE.to_vec();
+        data[SWITCHBOARD_RESULT_SLOT_OFFSET..SWITCHBOARD_RESULT_SLOT_OFFSET + 8].fill(0);
+        let error = with_account(SWITCHBOARD_ON_DEMAND_PROGRAM_ID, &data, |oracle| {
+            read_switchboard(oracle).map(|_| ())
+        })
+        .unwrap_err();
+        assert_eq!(error, Errors::InvalidOraclePrice.into());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::error::Errors;
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    spl_token_2022::{
+        extension::{
+            transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType,
+            StateWithExtensions,
+        },
+        state::Mint as MintState,
+    },
+    close_account, transfer_checked, CloseAccount, Mint, TokenAccount, TransferChecked,
+};
+
+/// Token-2022 mint extensions a pool can hold safely. Anything else, such as a
+/// permanent delegate, non-transferable tokens, transfer hooks or pausing, lets
+/// a third party move or lock vault funds outside the program's control.
+const SUPPORTED_MINT_EXTENSIONS: [ExtensionType; 8] = [
+    ExtensionType::TransferFeeConfig,
+    ExtensionType::MintCloseAuthority,
+    ExtensionType::InterestBearingConfig,
+    ExtensionType::MetadataPointer,
+    ExtensionType::TokenMetadata,
+    ExtensionType::GroupPointer,
+    ExtensionType::GroupMemberPointer,
+    ExtensionType::TokenGroupMember,
+];
+
+/// Rejects underlying mints that carry extensions outside
+/// `SUPPORTED_MINT_EXTENSIONS`. Classic SPL mints have no extensions and pass.
+pub fn validate_underlying_mint(mint: &AccountInfo) -> Result<()> {
+    let data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&data)?;
+    for extension in mint_state.get_extension_types()? {
+        require!(
+            SUPPORTED_MINT_EXTENSIONS.contains(&extension),
+            Errors::UnsupportedMintExtension
+        );
+    }
+    Ok(())
+}
+
+/// Transfers `amount` into `vault` and returns how much the vault actually
+/// received, which is less than `amount` for transfer-fee mints.
+pub fn transfer_into_vault<'info>(
+    token_program: AccountInfo<'info>,
+    from: AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: &mut InterfaceAccount<'info, TokenAccount>,
+    authority: AccountInfo<'info>,
+    amount: u64,
+) -> Result<u64> {
+    let balance_before = vault.amount;
+    transfer_checked(
+        CpiContext::new(
+            token_program,
+            TransferChecked {
+                from,
+                mint: mint.to_account_info(),
+                to: vault.to_account_info(),
+                authority,
+            },
+        ),
+        amount,
+        mint.decimals,
+    )?;
+    vault.reload()?;
+    let received = vault
+        .amount
+        .checked_sub(balance_before)
+        .ok_or(Errors::MathOverflow)?;
+    require!(received > 0, Errors::AmountZero);
+    Ok(received)
+}
+
+/// How much has to be sent for `net_amount` to arrive after the mint's
+/// transfer fee for the current epoch. Mints without a transfer fee return
+/// `net_amount` unchanged.
+pub fn gross_up_for_transfer_fee(mint: &InterfaceAccount<Mint>, net_amount: u64) -> Result<u64> {
+    let mint_info = mint.to_account_info();
+    let data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&data)?;
+    let Ok(transfer_fee_config) = mint_state.get_extension::<TransferFeeConfig>() else {
+        return Ok(net_amount);
+    };
+    let gross_amount = transfer_fee_config
+        .get_epoch_fee(Clock::get()?.epoch)
+        .calculate_pre_fee_amount(net_amount)
+        .ok_or(Errors::MathOverflow)?;
+    Ok(gross_amount)
+}
+
+/// How much of `amount` arrives after the mint's transfer fee for the current
+/// epoch. Mints without a transfer fee return `amount` unchanged.
+pub fn net_of_transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64) -> Result<u64> {
+    let mint_info = mint.to_account_info();
+    let data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&data)?;
+    let Ok(transfer_fee_config) = mint_state.get_extension::<TransferFeeConfig>() else {
+        return Ok(amount);
+    };
+    let fee = transfer_fee_config
+        .calculate_epoch_fee(Clock::get()?.epoch, amount)
+        .ok_or(Errors::MathOverflow)?;
+    Ok(amount.checked_sub(fee).ok_or(Errors::MathOverflow)?)
+}
+
+/// Closes a wSOL account owned by `owner`, delivering its whole balance plus
+/// rent to `owner` as native SOL.
+pub fn unwrap_sol<'info>(
+    token_program: AccountInfo<'info>,
+    wsol_account: AccountInfo<'info>,
+    owner: AccountInfo<'info>,
+) -> Result<()> {
+    close_account(CpiContext::new(
+        token_program,
+        CloseAccount {
+            account: wsol_account,
+            destination: owner.clone(),
+            authority: owner,
+        },
+    ))
+}
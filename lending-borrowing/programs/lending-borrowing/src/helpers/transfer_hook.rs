@@ -0,0 +1,45 @@
+use anchor_lang::prelude::*;
+use spl_tlv_account_resolution::{
+    account::ExtraAccountMeta, seeds::Seed, state::ExtraAccountMetaList,
+};
+use spl_transfer_hook_interface::instruction::ExecuteInstruction;
+
+/// Accounts Token-2022 resolves for the dToken transfer hook, after the
+/// source, mint, destination, authority and extra-account-metas accounts.
+pub const DTOKEN_TRANSFER_HOOK_EXTRA_ACCOUNTS: usize = 2;
+
+/// Bytes needed for the dToken mint's extra-account-metas account.
+pub fn dtoken_extra_account_metas_space() -> usize {
+    ExtraAccountMetaList::size_of(DTOKEN_TRANSFER_HOOK_EXTRA_ACCOUNTS).unwrap()
+}
+
+/// Writes the extra accounts of `config`'s dToken transfer hook: the config
+/// itself and the `user-position` of whoever owns the source token account.
+/// The owner is read from the source account's data, so delegated transfers
+/// resolve to the owner's position as well.
+pub fn init_dtoken_extra_account_metas(
+    extra_account_metas: &AccountInfo,
+    config: &Pubkey,
+) -> Result<()> {
+    let metas = [
+        ExtraAccountMeta::new_with_pubkey(config, false, false)?,
+        ExtraAccountMeta::new_with_seeds(
+            &[
+                Seed::Literal {
+                    bytes: b"user-position".to_vec(),
+                },
+                Seed::AccountKey { index: 5 },
+                Seed::AccountData {
+                    account_index: 0,
+                    data_index: 32,
+                    length: 32,
+                },
+            ],
+            false,
+            false,
+        )?,
+    ];
+    let mut data = extra_account_metas.try_borrow_mut_data()?;
+    ExtraAccountMetaList::init::<ExecuteInstruction>(&mut data, &metas)?;
+    Ok(())
+}
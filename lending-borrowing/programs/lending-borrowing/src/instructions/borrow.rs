@@ -1,25 +1,32 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{transfer, Mint, Token, TokenAccount, Transfer},
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
 
 use crate::state::*;
 use crate::{
     error::Errors,
-    math::{calculate_health_factor, normalize_pyth_price_to_usd_1e6},
+    math::calculate_health_factor,
+};
+use crate::{
+    event::BorrowEvent,
+    helpers::{
+        collateral::{book_isolated_debt, remaining_collateral},
+        emode::*,
+        interest::*,
+        oracle::*,
+    },
 };
-use crate::{event::BorrowEvent, helpers::interest::*};
-#[cfg(not(feature="test-mode"))]
-use pyth_solana_receiver_sdk::price_update::{ PriceUpdateV2};
-#[cfg(feature="test-mode")]
-use crate::state::config::MockOracle;
 #[derive(Accounts)]
 pub struct Borrow<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    #[account(address= pool.mint)]
-    pub underlying_mint: Account<'info, Mint>,
+    #[account(
+        address= pool.mint,
+        mint::token_program = token_program,
+    )]
+    pub underlying_mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
         seeds= [b"pool", config.key().as_ref(), underlying_mint.key().as_ref()],
@@ -28,16 +35,18 @@ pub struct Borrow<'info> {
     pub pool: Account<'info, Pool>,
     #[account(
         mut,
-        seeds=[b"config", config.admin.key().as_ref()],
+        seeds=[b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
         bump= config.bump,
     )]
     pub config: Account<'info, Config>,
     #[account(
-        mut, 
+        init_if_needed,
+        payer = user,
         associated_token::mint= underlying_mint,
         associated_token::authority=user,
+        associated_token::token_program = token_program,
     )]
-    pub user_ata: Account<'info, TokenAccount>,
+    pub user_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         init_if_needed,
         payer= user,
@@ -48,55 +57,118 @@ pub struct Borrow<'info> {
     pub user_pool_position: Account<'info, UserPoolPosition>,
     #[account(
         mut,
-        seeds=[b"user-position", user.key().as_ref()],
+        seeds=[b"user-position", config.key().as_ref(), user.key().as_ref()],
         bump,
     )]
     pub user_position: Account<'info, UserPosition>,
+    // required while the user is in e-mode
+    #[account(
+        seeds = [b"emode-category", config.key().as_ref(), &[emode_category.id]],
+        bump = emode_category.bump,
+    )]
+    pub emode_category: Option<Account<'info, EModeCategory>>,
     #[account(
         mut,
         associated_token::mint=pool.mint,
         associated_token::authority= pool,
+        associated_token::token_program = token_program,
     )]
-    pub vault: Account<'info, TokenAccount>,
-    #[cfg(not(feature="test-mode"))]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: validated against `pool.oracle` and parsed according to
+    /// `pool.oracle_source` by `get_price_usd_1e6`
     #[account(address = pool.oracle)]
-    pub oracle: Account<'info, PriceUpdateV2>,
-    // Test mode:  mock oracle
-    #[cfg(feature = "test-mode")]
-    #[account(
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= oracle.bump,
-    )]
-    pub oracle: Account<'info, MockOracle>,
-    pub token_program: Program<'info, Token>,
+    pub oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = pool.secondary_oracle)]
+    pub secondary_oracle: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> Borrow<'info> {
-    pub fn borrow(&mut self, amount: u64) -> Result<()> {
+    /// Books `borrow_value_usd` against the debt ceiling of the isolated pool
+    /// whose collateral backs this borrow. Only pools marked
+    /// `borrowable_in_isolation` can be borrowed against isolated collateral.
+    fn borrow_in_isolation(
+        &mut self,
+        isolated_pool_info: &'info AccountInfo<'info>,
+        borrow_value_usd: u64,
+    ) -> Result<()> {
+        let isolated_pool_key = isolated_pool_info.key();
+        require!(
+            self.pool.borrowable_in_isolation && isolated_pool_key != self.pool.key(),
+            Errors::IsolationModeBorrowNotAllowed
+        );
+        require!(isolated_pool_info.is_writable, Errors::InvalidCollateralAccounts);
+
+        let mut isolated_pool = Account::<Pool>::try_from(isolated_pool_info)?;
+        book_isolated_debt(
+            isolated_pool_key,
+            &mut isolated_pool,
+            &mut self.user_position,
+            borrow_value_usd,
+        )?;
+        isolated_pool.exit(&crate::ID)
+    }
+
+    pub fn borrow(&mut self, amount: u64, remaining_accounts: &'info [AccountInfo<'info>]) -> Result<()> {
+        require!(self.pool.status == PoolStatus::Active, Errors::PoolNotActive);
+        require!(self.pool.borrowing_enabled, Errors::BorrowingDisabled);
         accrue_interest(&mut self.pool)?;
+        if self.user_pool_position.user == Pubkey::default() {
+            self.user_pool_position.user = self.user.key();
+            self.user_pool_position.pool = self.pool.key();
+        }
         update_user_borrow_state(&mut self.user_pool_position, &self.pool)?;
         update_interest_rate(&mut self.pool)?;
-        //fetch oracle price and normalize to usd * 1e6
-        #[cfg(not(feature="test-mode"))]
-         let price_usd_1e6 = {
-            let price_update = &self.oracle;
-            let maximum_age: u64 = i64::MAX as u64;
-            let feed_id: [u8; 32] = self.pool.feed_id;
-            let price = price_update.get_price_no_older_than(&Clock::get()?, maximum_age, &feed_id)?;
-            normalize_pyth_price_to_usd_1e6(price.price, price.exponent)?
-        };
-        #[cfg(feature="test-mode")]
-        let price_usd_1e6={
-            let mock_oracle= &self.oracle;
-            normalize_pyth_price_to_usd_1e6(mock_oracle.price, mock_oracle.expo)?
+
+        //value collateral from the dTokens the user holds right now
+        let collateral = remaining_collateral(
+            remaining_accounts,
+            self.user.key(),
+            self.config.key(),
+            None,
+        )?;
+        let collateral_value_usd = collateral.value_usd;
+        self.user_position.collateral_value_usd = collateral_value_usd;
+
+        //in e-mode every borrow stays inside the category, and its params
+        //apply while the collateral does too
+        require!(
+            self.user_position.emode_category == 0
+                || self.pool.emode_category == self.user_position.emode_category,
+            Errors::EModeBorrowNotAllowed
+        );
+        let (ltv_bps, liquidation_threshold_bps) = match active_emode_category(
+            &self.user_position,
+            self.emode_category.as_deref(),
+            shared_emode_category(collateral.emode_category, self.pool.emode_category),
+        )? {
+            Some(category) => (category.ltv_bps, category.liquidation_threshold_bps),
+            None => (self.pool.ltv_bps, self.pool.liquidation_treshold_bps),
         };
+        //fetch oracle price and normalize to usd * 1e6
+        let price_usd_1e6 = get_price_usd_1e6(
+            &self.oracle.to_account_info(),
+            self.secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.pool,
+        )?;
         
+        let debt_price_usd_1e6 = valuation_price_usd_1e6(
+            price_usd_1e6,
+            &self.oracle.to_account_info(),
+            self.secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.pool,
+            PriceSide::Debt,
+        )?;
+
         //calculate amount user wants to borrow in usd
         let mint_decimals = self.underlying_mint.decimals;
         let scale = 10u128.pow(mint_decimals as u32);
         let borrow_value_usd = (amount as u128)
-            .checked_mul(price_usd_1e6 as u128)
+            .checked_mul(debt_price_usd_1e6 as u128)
             .ok_or(Errors::MathOverflow)?
             .checked_div(scale)
             .ok_or(Errors::MathOverflow)? as u64;
@@ -108,41 +180,60 @@ impl<'info> Borrow<'info> {
             .checked_add(borrow_value_usd)
             .ok_or(Errors::MathOverflow)?;
 
-        //check LTV
-        let max_borrowable = self
-            .user_position
-            .collateral_value_usd
-            .checked_mul(self.pool.ltv_bps as u64)
+        //check LTV, disabled collateral gives no borrowing power
+        let max_borrowable = (collateral_value_usd - collateral.disabled_value_usd)
+            .checked_mul(ltv_bps as u64)
             .ok_or(Errors::MathOverflow)?
             / 10_000; // convert bps to fraction
 
         require!(new_total_debt <= max_borrowable, Errors::ExceedsLTV);
 
-        //update pool + user state
-        self.pool.total_borrowed = self
+        match collateral.isolated_pools[..] {
+            [] => {}
+            [isolated_pool] => self.borrow_in_isolation(isolated_pool, borrow_value_usd)?,
+            _ => return err!(Errors::MultipleIsolatedCollateral),
+        }
+
+        let new_total_borrowed = self
             .pool
             .total_borrowed
             .checked_add(amount)
             .ok_or(Errors::MathOverflow)?;
+        require!(
+            self.pool.borrow_cap == 0 || new_total_borrowed <= self.pool.borrow_cap,
+            Errors::BorrowCapExceeded
+        );
+
+        //update pool + user state
+        self.pool.total_borrowed = new_total_borrowed;
+        if self.user_pool_position.borrowed_amount == 0 && amount > 0 {
+            open_borrow(&mut self.user_position, &mut self.user_pool_position, &self.pool)?;
+        }
         self.user_pool_position.borrowed_amount = self
             .user_pool_position
             .borrowed_amount
             .checked_add(amount)
             .ok_or(Errors::MathOverflow)?;
+        self.user_pool_position.borrowed_value_usd = self
+            .user_pool_position
+            .borrowed_value_usd
+            .checked_add(borrow_value_usd)
+            .ok_or(Errors::MathOverflow)?;
         self.user_position.debt_value_usd = new_total_debt;
 
         //check health factor
         let hf = calculate_health_factor(
-            self.user_position.collateral_value_usd,
+            collateral_value_usd,
             new_total_debt,
-            self.pool.liquidation_treshold_bps.into(),
+            liquidation_threshold_bps.into(),
         )?;
         require!(hf >= 1, Errors::BadHealthFactor);
 
         //transfer tokens to the user
         let program = self.token_program.to_account_info();
-        let accounts = Transfer {
+        let accounts = TransferChecked {
             from: self.vault.to_account_info(),
+            mint: self.underlying_mint.to_account_info(),
             to: self.user_ata.to_account_info(),
             authority: self.pool.to_account_info(),
         };
@@ -155,12 +246,12 @@ impl<'info> Borrow<'info> {
             &[self.pool.pool_bump],
         ]];
         let cpi_ctx = CpiContext::new_with_signer(program, accounts, signer_seeds);
-        transfer(cpi_ctx, amount)?;
+        transfer_checked(cpi_ctx, amount, self.underlying_mint.decimals)?;
         emit!(BorrowEvent {
             user: self.user.key(),
             pool: self.pool.key(),
             mint: self.underlying_mint.key(),
-            amount: amount,
+            amount,
             price_usd_1e6,
             timestamp: Clock::get()?.unix_timestamp,
         });
@@ -1,33 +1,56 @@
 use crate::error::Errors;
 use crate::event::CreatePoolEvent;
-use crate::state::{Config, Pool};
+use crate::helpers::{
+    metadata::*,
+    oracle::{validate_oracle_config, validate_oracle_source},
+    token::validate_underlying_mint,
+    transfer_hook::*,
+};
+use crate::state::{Config, OracleSource, Pool, PoolStatus, PriceMode};
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{Mint, Token, TokenAccount},
+    token_2022::Token2022,
+    token_interface::{
+        token_metadata_initialize, Mint, TokenAccount, TokenInterface, TokenMetadataInitialize,
+    },
 };
-#[cfg(feature="test-mode")]
-use crate::state::MockOracle;
 #[derive(Accounts)]
 pub struct CreatePool<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
-    pub mint: Account<'info, Mint>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
-        seeds = [b"config",config.admin.key().as_ref()],
+        seeds = [b"config",config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
         bump = config.bump,
     )]
     pub config: Account<'info, Config>,
     #[account(
         init,
         payer= admin,
-        mint::decimals =6,
+        mint::decimals = mint.decimals,
         mint::authority= pool,
         mint::freeze_authority = pool,
-        
+        mint::token_program = dtoken_program,
+        extensions::metadata_pointer::authority = pool,
+        extensions::metadata_pointer::metadata_address = dtoken_mint,
+        extensions::permanent_delegate::delegate = pool,
+        extensions::transfer_hook::authority = pool,
+        extensions::transfer_hook::program_id = crate::ID,
+    )]
+    pub dtoken_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: written by `init_dtoken_extra_account_metas` with the accounts
+    /// the dToken transfer hook needs
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"extra-account-metas", dtoken_mint.key().as_ref()],
+        bump,
+        space = dtoken_extra_account_metas_space(),
     )]
-    pub dtoken_mint: Account<'info, Mint>,
+    pub extra_account_metas: UncheckedAccount<'info>,
     #[account(
         init,
         payer= admin,
@@ -41,79 +64,72 @@ pub struct CreatePool<'info> {
         payer=admin,
         associated_token::mint = mint,
         associated_token::authority = pool,
+        associated_token::token_program = token_program,
     )]
-    pub vault: Account<'info, TokenAccount>,
-    #[cfg(feature="test-mode")]
-    #[account(
-        mut,
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= mock_oracle.bump,
-    )]
-    pub mock_oracle: Account<'info,MockOracle>,
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: checked by `validate_oracle_source` against the pool's oracle
+    /// source. Fixed-price pools never read it.
+    pub oracle: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dtoken_program: Program<'info, Token2022>,
 }
 
 impl<'info> CreatePool<'info> {
-    #[cfg(feature = "test-mode")]
-    pub fn create_pool(
-        &mut self,
-        liquidation_treshold_bps: u16,
-        ltv_bps: u16,
-        liquidation_bonus_bps: u16,
-        close_factor_bps: u16,
-        base_rate: u128,
-        slope1: u128,
-        slope2: u128,
-        optimal_utilization: u128,
-        bumps: &CreatePoolBumps,
-    ) -> Result<()> {
-        const ONE_E_18: u128 = 1_000_000_000_000_000_000;
-        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
-        
-        let oracle = self.mock_oracle.key();
-        let feed_id = [0u8; 32];
-        
-        self.pool.set_inner(Pool {
-            pool_id: self.config.pool_count,
-            oracle,
-            feed_id,
-            mint: self.mint.key(),
-            mint_dtoken: self.dtoken_mint.key(),
-            vault: self.vault.key(),
-            config: self.config.key(),
-            total_liquidity: 0,
-            total_borrowed: 0,
-            total_dtoken_supplied: 0,
-            liquidation_treshold_bps,
-            ltv_bps,
-            liquidation_bonus_bps,
-            pool_bump: bumps.pool,
-            last_accrual_ts: 0,
-            borrow_index: ONE_E_18,
-            borrow_rate_per_sec: ONE_E_18,
-            close_factor_bps,
-            base_rate,
-            slope1,
-            slope2,
-            optimal_utilization,
-        });
-        
-        self.config.pool_count += 1;
-        emit!(CreatePoolEvent {
-            pool: self.pool.key(),
-            mint: self.mint.key(),
-            timestamp: Clock::get()?.unix_timestamp,
-        });
-        Ok(())
+    /// Writes the dToken name, symbol and URI into the mint's Token-2022
+    /// metadata extension, with the pool as update authority.
+    fn init_dtoken_metadata(&self, name: String, symbol: String, uri: String) -> Result<()> {
+        require!(
+            !name.is_empty() && name.len() <= MAX_DTOKEN_NAME_LEN,
+            Errors::InvalidMetadata
+        );
+        require!(
+            !symbol.is_empty() && symbol.len() <= MAX_DTOKEN_SYMBOL_LEN,
+            Errors::InvalidMetadata
+        );
+        require!(uri.len() <= MAX_DTOKEN_URI_LEN, Errors::InvalidMetadata);
+
+        let config_key = self.config.key();
+        let mint_key = self.mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"pool",
+            config_key.as_ref(),
+            mint_key.as_ref(),
+            &[self.pool.pool_bump],
+        ]];
+        token_metadata_initialize(
+            CpiContext::new_with_signer(
+                self.dtoken_program.to_account_info(),
+                TokenMetadataInitialize {
+                    program_id: self.dtoken_program.to_account_info(),
+                    metadata: self.dtoken_mint.to_account_info(),
+                    update_authority: self.pool.to_account_info(),
+                    mint_authority: self.pool.to_account_info(),
+                    mint: self.dtoken_mint.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            name,
+            symbol,
+            uri,
+        )?;
+
+        fund_rent_exemption(
+            self.dtoken_mint.to_account_info(),
+            self.admin.to_account_info(),
+            self.system_program.to_account_info(),
+        )
     }
-    
-    #[cfg(not(feature = "test-mode"))]
+
+    #[allow(clippy::too_many_arguments)]
     pub fn create_pool(
         &mut self,
-        oracle: Pubkey,
+        oracle_source: OracleSource,
         feed_id: [u8; 32],
+        dtoken_name: String,
+        dtoken_symbol: String,
+        dtoken_uri: String,
         liquidation_treshold_bps: u16,
         ltv_bps: u16,
         liquidation_bonus_bps: u16,
@@ -122,16 +138,23 @@ impl<'info> CreatePool<'info> {
         slope1: u128,
         slope2: u128,
         optimal_utilization: u128,
+        max_price_age_secs: u64,
+        max_confidence_bps: u16,
         bumps: &CreatePoolBumps,
     ) -> Result<()> {
         const ONE_E_18: u128 = 1_000_000_000_000_000_000;
         require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
-        
+        validate_underlying_mint(&self.mint.to_account_info())?;
+        validate_oracle_config(max_price_age_secs, max_confidence_bps)?;
+        validate_oracle_source(&oracle_source, &self.oracle.to_account_info(), &feed_id)?;
+
         self.pool.set_inner(Pool {
             pool_id: self.config.pool_count,
-            oracle,
+            oracle_source,
+            oracle: self.oracle.key(),
             feed_id,
             mint: self.mint.key(),
+            decimals: self.mint.decimals,
             mint_dtoken: self.dtoken_mint.key(),
             vault: self.vault.key(),
             config: self.config.key(),
@@ -150,7 +173,37 @@ impl<'info> CreatePool<'info> {
             slope1,
             slope2,
             optimal_utilization,
+            max_price_age_secs,
+            max_confidence_bps,
+            secondary_oracle_source: None,
+            secondary_oracle: Pubkey::default(),
+            max_price_deviation_bps: 0,
+            price_mode: PriceMode::Spot,
+            twap_window_secs: 0,
+            twap_price_usd_1e6: 0,
+            twap_last_update_ts: 0,
+            max_liquidation_bonus_bps: 0,
+            max_bonus_health_factor_bps: 0,
+            protocol_liquidation_fee_bps: 0,
+            total_reserves: 0,
+            critical_health_factor_bps: 0,
+            full_liquidation_debt_usd: 0,
+            flash_loan_fee_bps: 0,
+            flash_loan_protocol_share_bps: 0,
+            flash_loan_amount: 0,
+            supply_cap: 0,
+            borrow_cap: 0,
+            isolated: false,
+            isolation_debt_ceiling_usd: 0,
+            isolated_debt_usd: 0,
+            borrowable_in_isolation: false,
+            emode_category: 0,
+            borrowing_enabled: true,
+            collateral_enabled: true,
+            status: PoolStatus::Active,
         });
+        self.init_dtoken_metadata(dtoken_name, dtoken_symbol, dtoken_uri)?;
+        init_dtoken_extra_account_metas(&self.extra_account_metas, &self.config.key())?;
         
         self.config.pool_count += 1;
         emit!(CreatePoolEvent {
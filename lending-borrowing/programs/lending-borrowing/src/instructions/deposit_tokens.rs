@@ -1,34 +1,35 @@
 use crate::error::Errors;
-use crate::event::DepositEvent;
-use crate::helpers::interest::*;
-use crate::math::{calculate_borrowed_value_usd, calculate_health_factor};
-#[cfg(feature = "test-mode")]
-use crate::state::MockOracle;
+use crate::event::{DepositEvent, UpdatePositionEvent};
+use crate::helpers::{collateral::*, emode::*, interest::*, oracle::*, token::transfer_into_vault};
+use crate::math::calculate_health_factor;
 use crate::{
-    math::{calculate_dtoken_mint_amount, normalize_pyth_price_to_usd_1e6},
+    math::calculate_dtoken_mint_amount,
     state::*,
 };
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{mint_to, transfer, Mint, MintTo, Token, TokenAccount, Transfer},
+    token_2022::Token2022,
+    token_interface::{mint_to, Mint, MintTo, TokenAccount, TokenInterface},
 };
-#[cfg(not(feature = "test-mode"))]
-use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
 
 #[derive(Accounts)]
 pub struct DepositTokens<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    #[account(address=pool.mint)]
-    pub underlying_mint: Account<'info, Mint>,
+    #[account(
+        address=pool.mint,
+        mint::token_program = token_program,
+    )]
+    pub underlying_mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
         address= pool.mint_dtoken,
+        mint::token_program = dtoken_program,
     )]
-    pub dtoken_mint: Account<'info, Mint>,
+    pub dtoken_mint: InterfaceAccount<'info, Mint>,
     #[account(
-        seeds= [b"config", config.admin.key().as_ref()],
+        seeds= [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
         bump = config.bump,
     )]
     pub config: Account<'info, Config>,
@@ -42,21 +43,25 @@ pub struct DepositTokens<'info> {
         mut,
         associated_token::mint = pool.mint,
         associated_token::authority = pool,
+        associated_token::token_program = token_program,
     )]
-    pub vault: Account<'info, TokenAccount>,
+    pub vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
-        mut,
-        associated_token::mint = pool.mint,
+        init_if_needed,
+        payer = user,
+        associated_token::mint = underlying_mint,
         associated_token::authority = user,
+        associated_token::token_program = token_program,
     )]
-    pub user_ata: Account<'info, TokenAccount>,
+    pub user_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         init_if_needed,
         payer= user,
         associated_token::mint= dtoken_mint,
         associated_token::authority=user,
+        associated_token::token_program = dtoken_program,
     )]
-    pub user_dtoken_ata: Account<'info, TokenAccount>,
+    pub user_dtoken_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         init_if_needed,
         payer= user,
@@ -66,34 +71,49 @@ pub struct DepositTokens<'info> {
     )]
     pub user_pool_position: Account<'info, UserPoolPosition>,
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dtoken_program: Program<'info, Token2022>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 impl<'info> DepositTokens<'info> {
     pub fn deposit_tokens(&mut self, amount: u64) -> Result<()> {
+        require!(amount > 0, Errors::AmountZero);
+
+        // Transfer tokens to vault, only what arrives after transfer fees counts
+        let received = transfer_into_vault(
+            self.token_program.to_account_info(),
+            self.user_ata.to_account_info(),
+            &self.underlying_mint,
+            &mut self.vault,
+            self.user.to_account_info(),
+            amount,
+        )?;
+        self.credit_deposit(received)
+    }
+
+    /// Mints dTokens for `received` underlying that already sits in the vault
+    /// and books the deposit on the pool.
+    pub fn credit_deposit(&mut self, received: u64) -> Result<()> {
+        require!(self.pool.status == PoolStatus::Active, Errors::PoolNotActive);
         // Interest accrual
         accrue_interest(&mut self.pool)?;
         update_user_borrow_state(&mut self.user_pool_position, &self.pool)?;
         update_interest_rate(&mut self.pool)?;
 
-        require!(amount > 0, Errors::AmountZero);
+        let new_total_liquidity = self
+            .pool
+            .total_liquidity
+            .checked_add(received)
+            .ok_or(Errors::MathOverflow)?;
+        require!(
+            self.pool.supply_cap == 0 || new_total_liquidity <= self.pool.supply_cap,
+            Errors::SupplyCapExceeded
+        );
 
-        // Transfer tokens to vault
-        transfer(
-            CpiContext::new(
-                self.token_program.to_account_info(),
-                Transfer {
-                    from: self.user_ata.to_account_info(),
-                    to: self.vault.to_account_info(),
-                    authority: self.user.to_account_info(),
-                },
-            ),
-            amount,
-        )?;
         //calculate mint amount
         let mint_amount = calculate_dtoken_mint_amount(
-            amount,
+            received,
             self.pool.total_liquidity,
             self.pool.total_dtoken_supplied,
         )?;
@@ -105,7 +125,7 @@ impl<'info> DepositTokens<'info> {
 
         mint_to(
             CpiContext::new_with_signer(
-                self.token_program.to_account_info(),
+                self.dtoken_program.to_account_info(),
                 MintTo {
                     mint: self.dtoken_mint.to_account_info(),
                     to: self.user_dtoken_ata.to_account_info(),
@@ -117,11 +137,7 @@ impl<'info> DepositTokens<'info> {
         )?;
 
         // Update pool state
-        self.pool.total_liquidity = self
-            .pool
-            .total_liquidity
-            .checked_add(amount)
-            .ok_or(Errors::MathOverflow)?;
+        self.pool.total_liquidity = new_total_liquidity;
         self.pool.total_dtoken_supplied = self
             .pool
             .total_dtoken_supplied
@@ -132,16 +148,18 @@ impl<'info> DepositTokens<'info> {
         if self.user_pool_position.user == Pubkey::default() {
             self.user_pool_position.user = self.user.key();
             self.user_pool_position.pool = self.pool.key();
-            self.user_pool_position.deposited_amount = amount;
             self.user_pool_position.borrowed_amount = 0;
-        } else {
-            self.user_pool_position.deposited_amount = self
-                .user_pool_position
-                .deposited_amount
-                .checked_add(amount)
-                .ok_or(Errors::MathOverflow)?;
         }
 
+        emit!(DepositEvent {
+            user: self.user.key(),
+            pool: self.pool.key(),
+            mint: self.pool.mint,
+            deposit_amount: received,
+            dtoken_minted: mint_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
         Ok(())
     }
 }
@@ -151,9 +169,9 @@ impl<'info> DepositTokens<'info> {
 pub struct UpdateDepositPosition<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    pub underlying_mint: Account<'info, Mint>,
+    pub underlying_mint: InterfaceAccount<'info, Mint>,
     #[account(
-        seeds= [b"config", config.admin.key().as_ref()],
+        seeds= [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
         bump = config.bump,
     )]
     pub config: Account<'info, Config>,
@@ -166,97 +184,98 @@ pub struct UpdateDepositPosition<'info> {
     #[account(
         init_if_needed,
         payer = user,
-        seeds= [b"user-position", user.key().as_ref()],
+        seeds= [b"user-position", config.key().as_ref(), user.key().as_ref()],
         bump,
         space = 8 + UserPosition::INIT_SPACE,
     )]
     pub user_position: Account<'info, UserPosition>,
+    // required while the user is in e-mode
     #[account(
-        seeds= [b"user-pool-position", user.key().as_ref(), pool.key().as_ref()],
-        bump,
+        seeds = [b"emode-category", config.key().as_ref(), &[emode_category.id]],
+        bump = emode_category.bump,
     )]
-    pub user_pool_position: Account<'info, UserPoolPosition>,
-
-    // Production mode:  real Pyth oracle
-    #[cfg(not(feature = "test-mode"))]
-    #[account(address = pool.oracle)]
-    pub oracle: Account<'info, PriceUpdateV2>,
-
-    // Test mode:  mock oracle
-    #[cfg(feature = "test-mode")]
+    pub emode_category: Option<Account<'info, EModeCategory>>,
     #[account(
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= oracle.bump,
+        associated_token::mint = pool.mint_dtoken,
+        associated_token::authority = user,
+        associated_token::token_program = dtoken_program,
     )]
-    pub oracle: Account<'info, MockOracle>,
+    pub user_dtoken_ata: InterfaceAccount<'info, TokenAccount>,
 
+    /// CHECK: validated against `pool.oracle` and parsed according to
+    /// `pool.oracle_source` by `get_price_usd_1e6`
+    #[account(address = pool.oracle)]
+    pub oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = pool.secondary_oracle)]
+    pub secondary_oracle: Option<UncheckedAccount<'info>>,
+
+    pub dtoken_program: Program<'info, Token2022>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> UpdateDepositPosition<'info> {
-    #[cfg(not(feature = "test-mode"))]
-    pub fn update_position(&mut self, amount: u64) -> Result<()> {
-        // Get oracle price from Pyth
-        let p = self.oracle.get_price_no_older_than(
-            &Clock::get()?,
-            i64::MAX as u64,
-            &self.pool.feed_id,
+    /// Re-values the user's collateral from their dToken balances: this pool
+    /// plus any pools passed as `[pool, user_dtoken_ata, oracle]` groups in
+    /// `remaining_accounts`.
+    pub fn update_position(&mut self, remaining_accounts: &'info [AccountInfo<'info>]) -> Result<()> {
+        let spot_price_usd_1e6 = get_price_usd_1e6(
+            &self.oracle.to_account_info(),
+            self.secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.pool,
+        )?;
+        let price_usd_1e6 = valuation_price_usd_1e6(
+            spot_price_usd_1e6,
+            &self.oracle.to_account_info(),
+            self.secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.pool,
+            PriceSide::Collateral,
         )?;
-        let price_usd_1e6 = normalize_pyth_price_to_usd_1e6(p.price, p.exponent)?;
-
-        self.update_position_internal(amount, price_usd_1e6)
-    }
-
-    // Test mode implementation
-    #[cfg(feature = "test-mode")]
-    pub fn update_position(&mut self, amount: u64) -> Result<()> {
-        // Get oracle price from MockOracle
-        let current_time = Clock::get()?.unix_timestamp;
-        let time_diff = current_time - self.oracle.publish_time;
-        require!(time_diff <= 30, Errors::InvalidOraclePrice);
-
-        let price_usd_1e6 = normalize_pyth_price_to_usd_1e6(self.oracle.price, self.oracle.expo)?;
-
-        self.update_position_internal(amount, price_usd_1e6)
-    }
 
-    // Shared logic for both modes
-    fn update_position_internal(&mut self, amount: u64, price_usd_1e6: u64) -> Result<()> {
-        // Calculate collateral value
-        let collateral_usd =
-            calculate_borrowed_value_usd(amount, price_usd_1e6, self.underlying_mint.decimals)?;
+        let pool_collateral_usd =
+            dtoken_collateral_value_usd(&self.pool, self.user_dtoken_ata.amount, price_usd_1e6)?;
+        let other_collateral = remaining_collateral(
+            remaining_accounts,
+            self.user.key(),
+            self.config.key(),
+            Some(self.pool.key()),
+        )?;
+        let collateral_usd = pool_collateral_usd
+            .checked_add(other_collateral.value_usd)
+            .ok_or(Errors::MathOverflow)?;
+        let collateral_category = if self.user_dtoken_ata.amount > 0 {
+            shared_emode_category(other_collateral.emode_category, self.pool.emode_category)
+        } else {
+            other_collateral.emode_category
+        };
+        let liquidation_threshold_bps = match active_emode_category(
+            &self.user_position,
+            self.emode_category.as_deref(),
+            collateral_category,
+        )? {
+            Some(category) => category.liquidation_threshold_bps,
+            None => self.pool.liquidation_treshold_bps,
+        };
 
         // Update user position
-        let is_new = self.user_position.user == Pubkey::default();
-
-        if is_new {
+        if self.user_position.user == Pubkey::default() {
             self.user_position.user = self.user.key();
-            self.user_position.collateral_value_usd = collateral_usd;
             self.user_position.debt_value_usd = 0;
-            self.user_position.health_factor = u64::MAX;
-        } else {
-            let new_collateral = self
-                .user_position
-                .collateral_value_usd
-                .checked_add(collateral_usd)
-                .ok_or(Errors::MathOverflow)?;
-            let hf = calculate_health_factor(
-                new_collateral,
-                self.user_position.debt_value_usd,
-                self.pool.liquidation_treshold_bps.into(),
-            )?;
-            self.user_position.collateral_value_usd = new_collateral;
-            self.user_position.health_factor = hf;
         }
+        let hf = calculate_health_factor(
+            collateral_usd,
+            self.user_position.debt_value_usd,
+            liquidation_threshold_bps.into(),
+        )?;
+        self.user_position.collateral_value_usd = collateral_usd;
+        self.user_position.health_factor = hf;
 
-        emit!(DepositEvent {
+        emit!(UpdatePositionEvent {
             user: self.user.key(),
-            pool: self.pool.key(),
-            mint: self.pool.mint,
-            deposit_amount: amount,
-            dtoken_minted: 0,
-            price_usd_1e6,
             collateral_value_usd: collateral_usd,
+            debt_value_usd: self.user_position.debt_value_usd,
+            health_factor: hf,
             timestamp: Clock::get()?.unix_timestamp,
         });
 
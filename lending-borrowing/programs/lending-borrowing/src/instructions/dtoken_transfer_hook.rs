@@ -0,0 +1,50 @@
+use crate::error::Errors;
+use crate::state::{Config, UserPosition};
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+
+// Token-2022 calls this on every dToken transfer. Collateral follows the live
+// dToken balance, so letting a borrower move dTokens out would pull collateral
+// from under their debt without a health check.
+
+#[derive(Accounts)]
+pub struct DTokenTransferHook<'info> {
+    #[account(token::mint = dtoken_mint)]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+    pub dtoken_mint: InterfaceAccount<'info, Mint>,
+    #[account(token::mint = dtoken_mint)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: the owner or delegate that signed the transfer; the token
+    /// program has already checked it
+    pub authority: UncheckedAccount<'info>,
+    /// CHECK: the dToken mint's extra-account-metas, written by `create_pool`
+    #[account(
+        seeds = [b"extra-account-metas", dtoken_mint.key().as_ref()],
+        bump,
+    )]
+    pub extra_account_metas: UncheckedAccount<'info>,
+    pub config: Account<'info, Config>,
+    /// CHECK: the source owner's position, which does not exist yet when they
+    /// never refreshed a position in this market
+    #[account(
+        seeds = [b"user-position", config.key().as_ref(), source.owner.as_ref()],
+        bump,
+    )]
+    pub source_position: UncheckedAccount<'info>,
+}
+
+impl<'info> DTokenTransferHook<'info> {
+    /// Rejects the transfer while the sender still owes any pool.
+    pub fn check_dtoken_transfer(&self) -> Result<()> {
+        if self.source_position.owner != &crate::ID {
+            return Ok(());
+        }
+        let data = self.source_position.try_borrow_data()?;
+        let position = UserPosition::try_deserialize(&mut &data[..])?;
+        require!(
+            position.open_borrows == 0,
+            Errors::DTokenTransferWithOpenBorrows
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    error::Errors,
+    event::{SetPoolEModeCategoryEvent, SetUserEModeEvent, UpdateEModeCategoryEvent},
+    state::{Config, EModeCategory, Pool, UserPosition},
+};
+use anchor_lang::prelude::*;
+
+// Efficiency mode: correlated assets grouped into categories with a higher
+// LTV and threshold and a lower liquidation bonus than their pools'. Users
+// opt their position into one category; its parameters apply whenever every
+// pool behind a check belongs to it.
+
+fn validate_emode_params(
+    ltv_bps: u16,
+    liquidation_threshold_bps: u16,
+    liquidation_bonus_bps: u16,
+) -> Result<()> {
+    require!(
+        ltv_bps <= liquidation_threshold_bps
+            && liquidation_threshold_bps <= 10_000
+            && liquidation_bonus_bps <= 10_000,
+        Errors::InvalidEModeCategory
+    );
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(id: u8)]
+pub struct CreateEModeCategory<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + EModeCategory::INIT_SPACE,
+        seeds = [b"emode-category", config.key().as_ref(), &[id]],
+        bump,
+    )]
+    pub emode_category: Account<'info, EModeCategory>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> CreateEModeCategory<'info> {
+    /// Creates category `id`; 0 is reserved for "no category".
+    pub fn create_emode_category(
+        &mut self,
+        id: u8,
+        label: String,
+        ltv_bps: u16,
+        liquidation_threshold_bps: u16,
+        liquidation_bonus_bps: u16,
+        bumps: &CreateEModeCategoryBumps,
+    ) -> Result<()> {
+        require!(id != 0 && label.len() <= 32, Errors::InvalidEModeCategory);
+        validate_emode_params(ltv_bps, liquidation_threshold_bps, liquidation_bonus_bps)?;
+
+        self.emode_category.set_inner(EModeCategory {
+            config: self.config.key(),
+            id,
+            label,
+            ltv_bps,
+            liquidation_threshold_bps,
+            liquidation_bonus_bps,
+            bump: bumps.emode_category,
+        });
+
+        emit!(UpdateEModeCategoryEvent {
+            emode_category: self.emode_category.key(),
+            id,
+            ltv_bps,
+            liquidation_threshold_bps,
+            liquidation_bonus_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct UpdateEModeCategory<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"emode-category", config.key().as_ref(), &[emode_category.id]],
+        bump = emode_category.bump,
+    )]
+    pub emode_category: Account<'info, EModeCategory>,
+}
+
+impl<'info> UpdateEModeCategory<'info> {
+    pub fn update_emode_category(
+        &mut self,
+        ltv_bps: u16,
+        liquidation_threshold_bps: u16,
+        liquidation_bonus_bps: u16,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        validate_emode_params(ltv_bps, liquidation_threshold_bps, liquidation_bonus_bps)?;
+
+        self.emode_category.ltv_bps = ltv_bps;
+        self.emode_category.liquidation_threshold_bps = liquidation_threshold_bps;
+        self.emode_category.liquidation_bonus_bps = liquidation_bonus_bps;
+
+        emit!(UpdateEModeCategoryEvent {
+            emode_category: self.emode_category.key(),
+            id: self.emode_category.id,
+            ltv_bps,
+            liquidation_threshold_bps,
+            liquidation_bonus_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct SetPoolEModeCategory<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+    // omitted to take the pool out of e-mode
+    #[account(
+        seeds = [b"emode-category", config.key().as_ref(), &[emode_category.id]],
+        bump = emode_category.bump,
+    )]
+    pub emode_category: Option<Account<'info, EModeCategory>>,
+}
+
+impl<'info> SetPoolEModeCategory<'info> {
+    pub fn set_pool_emode_category(&mut self) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+
+        let emode_category = self.emode_category.as_ref().map_or(0, |category| category.id);
+        self.pool.emode_category = emode_category;
+
+        emit!(SetPoolEModeCategoryEvent {
+            pool: self.pool.key(),
+            emode_category,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct SetUserEMode<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"user-position", config.key().as_ref(), user.key().as_ref()],
+        bump,
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    // omitted to leave e-mode
+    #[account(
+        seeds = [b"emode-category", config.key().as_ref(), &[emode_category.id]],
+        bump = emode_category.bump,
+    )]
+    pub emode_category: Option<Account<'info, EModeCategory>>,
+}
+
+impl<'info> SetUserEMode<'info> {
+    /// Opts the user's position into a category, or out of e-mode. Only
+    /// allowed while nothing is borrowed, since borrows in e-mode are limited
+    /// to the category's pools and that keeps every e-mode debt inside it.
+    pub fn set_user_emode(&mut self) -> Result<()> {
+        require!(
+            self.user_position.open_borrows == 0,
+            Errors::EModeChangeWithOpenBorrows
+        );
+
+        let emode_category = self.emode_category.as_ref().map_or(0, |category| category.id);
+        self.user_position.emode_category = emode_category;
+
+        emit!(SetUserEModeEvent {
+            user: self.user.key(),
+            emode_category,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
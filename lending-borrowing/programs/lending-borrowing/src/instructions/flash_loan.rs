@@ -0,0 +1,216 @@
+use crate::{
+    error::Errors,
+    event::{FlashBorrowEvent, FlashRepayEvent},
+    helpers::{
+        introspection::require_following_instruction,
+        token::{gross_up_for_transfer_fee, transfer_into_vault},
+    },
+    instructions::*,
+    math::calculate_flash_loan_fee,
+    state::{Config, Pool, PoolStatus},
+};
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions::ID as INSTRUCTIONS_SYSVAR_ID;
+use anchor_lang::Discriminator;
+use anchor_spl::token_interface::{
+    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
+
+/// Position of `pool` in `FlashRepay`, read back through introspection.
+const FLASH_REPAY_POOL_INDEX: usize = 3;
+
+#[derive(Accounts)]
+pub struct FlashBorrow<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        address = pool.mint,
+        mint::token_program = token_program,
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), mint.key().as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: the instructions sysvar, used to find the matching `flash_repay`
+    #[account(address = INSTRUCTIONS_SYSVAR_ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> FlashBorrow<'info> {
+    /// Lends `amount` of vault cash for the rest of the transaction. A later
+    /// `flash_repay` for the same pool must be part of the transaction.
+    pub fn flash_borrow(&mut self, amount: u64) -> Result<()> {
+        require!(amount > 0, Errors::AmountZero);
+        require!(self.pool.status == PoolStatus::Active, Errors::PoolNotActive);
+        require!(self.pool.flash_loan_amount == 0, Errors::FlashLoanActive);
+        require!(self.vault.amount >= amount, Errors::InsufficientLiquidity);
+        require_following_instruction(
+            &self.instructions.to_account_info(),
+            crate::instruction::FlashRepay::DISCRIMINATOR,
+            FLASH_REPAY_POOL_INDEX,
+            self.pool.key(),
+        )?;
+
+        let config_key = self.config.key();
+        let mint_key = self.mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"pool",
+            config_key.as_ref(),
+            mint_key.as_ref(),
+            &[self.pool.pool_bump],
+        ]];
+        transfer_checked(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                TransferChecked {
+                    from: self.vault.to_account_info(),
+                    mint: self.mint.to_account_info(),
+                    to: self.destination.to_account_info(),
+                    authority: self.pool.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+            self.mint.decimals,
+        )?;
+        self.pool.flash_loan_amount = amount;
+
+        emit!(FlashBorrowEvent {
+            user: self.user.key(),
+            pool: self.pool.key(),
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+// Liquidation without the debt asset up front: collateral is released first
+// and the repayment becomes a flash loan of the debt pool, so the liquidator
+// can swap the seized collateral before settling it with `flash_repay`.
+
+#[derive(Accounts)]
+pub struct FlashLiquidate<'info> {
+    pub liquidate: Liquidate<'info>,
+    /// CHECK: the instructions sysvar, used to find the matching `flash_repay`
+    #[account(address = INSTRUCTIONS_SYSVAR_ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+impl<'info> FlashLiquidate<'info> {
+    pub fn flash_liquidate(&mut self, repay_amount: u64, receive_dtokens: bool) -> Result<()> {
+        require_following_instruction(
+            &self.instructions.to_account_info(),
+            crate::instruction::FlashRepay::DISCRIMINATOR,
+            FLASH_REPAY_POOL_INDEX,
+            self.liquidate.debt_pool.key(),
+        )?;
+        self.liquidate.liquidate(repay_amount, receive_dtokens, true)
+    }
+}
+
+#[derive(Accounts)]
+pub struct FlashRepay<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        address = pool.mint,
+        mint::token_program = token_program,
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), mint.key().as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> FlashRepay<'info> {
+    /// Returns the outstanding flash loan plus the pool's fee. The fee goes to
+    /// suppliers through `total_liquidity`, less the protocol's share, which is
+    /// added to reserves. On transfer-fee mints the user also pays the
+    /// transfer fee, so the vault receives the full amount owed.
+    pub fn flash_repay(&mut self) -> Result<()> {
+        let amount = self.pool.flash_loan_amount;
+        require!(amount > 0, Errors::NoActiveFlashLoan);
+        let fee = calculate_flash_loan_fee(amount, self.pool.flash_loan_fee_bps)?;
+        let owed = amount.checked_add(fee).ok_or(Errors::MathOverflow)?;
+
+        let received = transfer_into_vault(
+            self.token_program.to_account_info(),
+            self.source.to_account_info(),
+            &self.mint,
+            &mut self.vault,
+            self.user.to_account_info(),
+            gross_up_for_transfer_fee(&self.mint, owed)?,
+        )?;
+        require!(received >= owed, Errors::FlashLoanNotRepaid);
+
+        let protocol_fee = ((fee as u128)
+            .checked_mul(self.pool.flash_loan_protocol_share_bps as u128)
+            .ok_or(Errors::MathOverflow)?
+            / 10_000) as u64;
+        self.pool.total_reserves = self
+            .pool
+            .total_reserves
+            .checked_add(protocol_fee)
+            .ok_or(Errors::MathOverflow)?;
+        self.pool.total_liquidity = self
+            .pool
+            .total_liquidity
+            .checked_add(fee - protocol_fee)
+            .ok_or(Errors::MathOverflow)?;
+        self.pool.flash_loan_amount = 0;
+
+        emit!(FlashRepayEvent {
+            user: self.user.key(),
+            pool: self.pool.key(),
+            amount,
+            fee,
+            protocol_fee,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -1,87 +1,37 @@
-#[cfg(feature = "test-mode")]
-use crate::state::MockOracle;
 use crate::{event::InitConfigEvent, state::Config};
 use anchor_lang::prelude::*;
 #[derive(Accounts)]
+#[instruction(market_id: u16)]
 pub struct InitConfig<'info> {
     #[account(mut)]
     pub initializer: Signer<'info>,
     #[account(
         init,
         payer = initializer,
-        seeds = [b"config", initializer.key().as_ref()],
+        seeds = [b"config", initializer.key().as_ref(), market_id.to_le_bytes().as_ref()],
         bump,
         space= 8 + Config::INIT_SPACE,
     )]
     pub config: Account<'info, Config>,
-    #[cfg(feature = "test-mode")]
-    #[account(
-        init,
-        payer=initializer,
-        space= 8+ MockOracle::INIT_SPACE,
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump,
-    )]
-    pub mock_oracle: Account<'info, MockOracle>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> InitConfig<'info> {
-    pub fn init_config(&mut self, bumps: &InitConfigBumps) -> Result<()> {
+    /// Creates market `market_id` of the initializer. Each market has its own
+    /// pools, risk settings and user positions.
+    pub fn init_config(&mut self, market_id: u16, bumps: &InitConfigBumps) -> Result<()> {
         self.config.set_inner(Config {
             admin: self.initializer.key(),
+            market_id,
             fee_authority: self.initializer.key(),
             paused: false,
             pool_count: 0,
             bump: bumps.config,
         });
-        #[cfg(feature = "test-mode")]
-        {
-            let current_time = Clock::get()?.unix_timestamp;
-            self.mock_oracle.set_inner(MockOracle {
-                price: 100_00000000,
-                conf: 50000,
-                expo: -8,
-                publish_time: current_time,
-                bump: bumps.mock_oracle,
-            });
-        };
         emit!(InitConfigEvent {
             config: self.config.key(),
+            market_id,
         });
         Ok(())
     }
 }
-
-#[cfg(feature = "test-mode")]
-#[derive(Accounts)]
-pub struct UpdateMockOracle<'info> {
-    #[account(mut)]
-    pub admin: Signer<'info>,
-    #[account(
-        mut,
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= mock_oracle.bump,
-    )]
-    pub mock_oracle: Account<'info, MockOracle>,
-    #[account(
-        seeds=[b"config", admin.key().as_ref()],
-        bump= config.bump,
-    )]
-    pub config: Account<'info, Config>,
-}
-
-#[cfg(feature = "test-mode")]
-impl<'info> UpdateMockOracle<'info> {
-    pub fn update_mock_oracle(&mut self, new_price: i64, new_expo: i32) -> Result<()> {
-        self.mock_oracle.price = new_price;
-        self.mock_oracle.expo = new_expo;
-        self.mock_oracle.publish_time = Clock::get()?.unix_timestamp;
-        msg!(
-            "Mock oracle updated: price={}, expo={}",
-            new_price,
-            new_expo
-        );
-        Ok(())
-    }
-}
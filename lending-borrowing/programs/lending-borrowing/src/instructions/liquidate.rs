@@ -1,21 +1,30 @@
-use crate::{error::Errors, event::LiquidateEvent, helpers::interest::*, math::*, state::*};
+use crate::{
+    error::Errors,
+    event::LiquidateEvent,
+    helpers::{collateral::*, emode::*, interest::*, oracle::*, token::{net_of_transfer_fee, transfer_into_vault}},
+    math::*,
+    state::*,
+};
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{transfer, Mint, Token, TokenAccount, Transfer},
+    token_2022::Token2022,
+    token_interface::{
+        burn, mint_to, transfer_checked, Burn, Mint, MintTo, TokenAccount, TokenInterface,
+        TransferChecked,
+    },
 };
-#[cfg(not(feature="test-mode"))]
-use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
-#[cfg(feature="test-mode")]
-use crate::state::config::MockOracle;
 
 #[derive(Accounts)]
 pub struct Liquidate<'info> {
     #[account(mut)]
     pub liquidator: Signer<'info>,
     pub borrower: SystemAccount<'info>,
-    #[account(address= debt_pool.mint)]
-    pub debt_mint: Account<'info, Mint>,
+    #[account(
+        address= debt_pool.mint,
+        mint::token_program = debt_token_program,
+    )]
+    pub debt_mint: Box<InterfaceAccount<'info, Mint>>,
     #[account(
         mut,
         seeds =[b"pool", config.key().as_ref(), debt_mint.key().as_ref()],
@@ -23,7 +32,7 @@ pub struct Liquidate<'info> {
     )]
     pub debt_pool: Box<Account<'info, Pool>>,
     #[account(
-        seeds= [b"config", config.admin.key().as_ref()],
+        seeds= [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
         bump= config.bump,
     )]
     pub config: Box<Account<'info, Config>>,
@@ -35,7 +44,7 @@ pub struct Liquidate<'info> {
     pub borrower_debt_position: Box<Account<'info, UserPoolPosition>>,
     #[account(
         mut, 
-        seeds= [b"user-position", borrower.key().as_ref()],
+        seeds= [b"user-position", config.key().as_ref(), borrower.key().as_ref()],
         bump,
     )]
     pub borrower_position: Box<Account<'info, UserPosition>>,
@@ -43,18 +52,21 @@ pub struct Liquidate<'info> {
         mut,
         associated_token::mint= debt_mint,
         associated_token::authority=debt_pool,
+        associated_token::token_program = debt_token_program,
     )]
-    pub debt_pool_vault: Box<Account<'info, TokenAccount>>,
+    pub debt_pool_vault: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(
         mut,
         associated_token::mint= debt_mint,
         associated_token::authority= liquidator,
+        associated_token::token_program = debt_token_program,
     )]
-    pub liquidator_debt_ata: Account<'info, TokenAccount>,
+    pub liquidator_debt_ata: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(
         address= collateral_pool.mint, 
+        mint::token_program = collateral_token_program,
     )]
-    pub collateral_mint: Account<'info, Mint>,
+    pub collateral_mint: Box<InterfaceAccount<'info, Mint>>,
     #[account(
         mut,
         seeds=[b"pool", config.key().as_ref(), collateral_mint.key().as_ref()],
@@ -63,63 +75,125 @@ pub struct Liquidate<'info> {
     pub collateral_pool: Box<Account<'info, Pool>>,
     #[account(
         mut,
-        seeds= [b"user-pool-position", borrower.key().as_ref(), collateral_pool.key().as_ref()],
-        bump,
+        address = collateral_pool.mint_dtoken,
+        mint::token_program = dtoken_program,
+    )]
+    pub collateral_dtoken_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = collateral_dtoken_mint,
+        associated_token::authority = borrower,
+        associated_token::token_program = dtoken_program,
     )]
-    pub borrower_collateral_position: Box<Account<'info, UserPoolPosition>>,
+    pub borrower_collateral_dtoken_ata: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(
         mut, 
         associated_token::mint= collateral_mint,
         associated_token::authority= collateral_pool,
+        associated_token::token_program = collateral_token_program,
     )]
-    pub collateral_pool_vault: Box<Account<'info, TokenAccount>>,
+    pub collateral_pool_vault: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(
         mut, 
         associated_token::mint = collateral_mint,
         associated_token::authority=liquidator,
+        associated_token::token_program = collateral_token_program,
     )]
-    pub liquidator_collateral_ata: Box<Account<'info, TokenAccount>>,
-    #[cfg(not(feature="test-mode"))]
-    #[account(address = debt_pool.oracle)]
-    pub debt_oracle: Account<'info, PriceUpdateV2>,
-    #[cfg(feature="test-mode")]
+    // only needed when the liquidator takes the seized collateral as tokens
+    pub liquidator_collateral_ata: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // only needed when the liquidator takes the seized collateral as dTokens
     #[account(
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= debt_oracle.bump,
+        mut,
+        associated_token::mint = collateral_dtoken_mint,
+        associated_token::authority = liquidator,
+        associated_token::token_program = dtoken_program,
     )]
-    pub debt_oracle: Account<'info, MockOracle>,
-    #[cfg(not(feature="test-mode"))]
+    pub liquidator_collateral_dtoken_ata: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// CHECK: validated against `debt_pool.oracle` and parsed according to
+    /// `debt_pool.oracle_source` by `get_price_usd_1e6`
+    #[account(address = debt_pool.oracle)]
+    pub debt_oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `debt_pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = debt_pool.secondary_oracle)]
+    pub debt_secondary_oracle: Option<UncheckedAccount<'info>>,
+    /// CHECK: validated against `collateral_pool.oracle` and parsed according to
+    /// `collateral_pool.oracle_source` by `get_price_usd_1e6`
     #[account(address = collateral_pool.oracle)]
-    pub collateral_oracle: Account<'info, PriceUpdateV2>,
-    #[cfg(feature="test-mode")]
+    pub collateral_oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `collateral_pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = collateral_pool.secondary_oracle)]
+    pub collateral_secondary_oracle: Option<UncheckedAccount<'info>>,
+    // required while the borrower has isolated debt, unless the isolated pool
+    // is already passed as the debt or collateral pool
     #[account(
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= collateral_oracle.bump,
+        mut,
+        address = borrower_position.isolation_pool,
+        constraint = borrower_isolation_pool.key() != debt_pool.key()
+            && borrower_isolation_pool.key() != collateral_pool.key()
+            @ Errors::DuplicateIsolationPool,
     )]
-    pub collateral_oracle: Account<'info, MockOracle>,
-    pub token_program: Program<'info, Token>,
+    pub borrower_isolation_pool: Option<Box<Account<'info, Pool>>>,
+    // required while the borrower is in e-mode
+    #[account(
+        seeds = [b"emode-category", config.key().as_ref(), &[borrower_emode_category.id]],
+        bump = borrower_emode_category.bump,
+    )]
+    pub borrower_emode_category: Option<Box<Account<'info, EModeCategory>>>,
+    pub debt_token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Interface<'info, TokenInterface>,
+    pub dtoken_program: Program<'info, Token2022>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> Liquidate<'info> {
-    fn transfer_from_liquidator_to_debt_vault(&mut self, amount: u64) -> Result<()> {
-        let program = self.token_program.to_account_info();
-        let accounts = Transfer {
-            from: self.liquidator_debt_ata.to_account_info(),
-            to: self.debt_pool_vault.to_account_info(),
-            authority: self.liquidator.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(program, accounts);
-        transfer(cpi_ctx, amount)?;
-        Ok(())
+    /// Returns the amount the debt vault received, net of transfer fees.
+    fn transfer_from_liquidator_to_debt_vault(&mut self, amount: u64) -> Result<u64> {
+        transfer_into_vault(
+            self.debt_token_program.to_account_info(),
+            self.liquidator_debt_ata.to_account_info(),
+            &self.debt_mint,
+            &mut self.debt_pool_vault,
+            self.liquidator.to_account_info(),
+            amount,
+        )
     }
 
     fn transfer_collateral_to_liquidator(&mut self, amount: u64) -> Result<()> {
-        let program = self.token_program.to_account_info();
-        let accounts = Transfer {
+        let liquidator_collateral_ata = self
+            .liquidator_collateral_ata
+            .as_ref()
+            .ok_or(Errors::MissingLiquidatorCollateralAccount)?;
+        let program = self.collateral_token_program.to_account_info();
+        let accounts = TransferChecked {
             from: self.collateral_pool_vault.to_account_info(),
-            to: self.liquidator_collateral_ata.to_account_info(),
+            mint: self.collateral_mint.to_account_info(),
+            to: liquidator_collateral_ata.to_account_info(),
+            authority: self.collateral_pool.to_account_info(),
+        };
+        let config_key = self.config.key();
+        let collateral_mint_key = self.collateral_mint.key();
+        let seeds = [
+            b"pool".as_ref(),
+            config_key.as_ref(),
+            collateral_mint_key.as_ref(),
+            &[self.collateral_pool.pool_bump][..],
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[&seeds];
+        let cpi_ctx = CpiContext::new_with_signer(program, accounts, signer_seeds);
+        transfer_checked(cpi_ctx, amount, self.collateral_mint.decimals)?;
+        Ok(())
+    }
+
+    /// Burns seized dTokens from the borrower, signing as the collateral pool,
+    /// which is the permanent delegate of its dToken mint.
+    fn burn_borrower_dtokens(&mut self, amount: u64) -> Result<()> {
+        let program = self.dtoken_program.to_account_info();
+        let accounts = Burn {
+            mint: self.collateral_dtoken_mint.to_account_info(),
+            from: self.borrower_collateral_dtoken_ata.to_account_info(),
             authority: self.collateral_pool.to_account_info(),
         };
         let config_key = self.config.key();
@@ -132,66 +206,231 @@ impl<'info> Liquidate<'info> {
         ];
         let signer_seeds: &[&[&[u8]]] = &[&seeds];
         let cpi_ctx = CpiContext::new_with_signer(program, accounts, signer_seeds);
-        transfer(cpi_ctx, amount)?;
+        burn(cpi_ctx, amount)?;
+        Ok(())
+    }
+
+    /// Mints the liquidator's share of the seized dTokens, signing as the
+    /// collateral pool. The borrower's dTokens are burned rather than moved,
+    /// since a transfer would run the dToken transfer hook back into this
+    /// program.
+    fn mint_dtokens_to_liquidator(&mut self, amount: u64) -> Result<()> {
+        let liquidator_dtoken_ata = self
+            .liquidator_collateral_dtoken_ata
+            .as_ref()
+            .ok_or(Errors::MissingLiquidatorDTokenAccount)?;
+        let program = self.dtoken_program.to_account_info();
+        let accounts = MintTo {
+            mint: self.collateral_dtoken_mint.to_account_info(),
+            to: liquidator_dtoken_ata.to_account_info(),
+            authority: self.collateral_pool.to_account_info(),
+        };
+        let config_key = self.config.key();
+        let collateral_mint_key = self.collateral_mint.key();
+        let seeds = [
+            b"pool".as_ref(),
+            config_key.as_ref(),
+            collateral_mint_key.as_ref(),
+            &[self.collateral_pool.pool_bump][..],
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[&seeds];
+        let cpi_ctx = CpiContext::new_with_signer(program, accounts, signer_seeds);
+        mint_to(cpi_ctx, amount)?;
+        Ok(())
+    }
+
+    /// Collateral owed for repaying `repay_amount` at `bonus_bps` over its
+    /// value, capped at what the borrower holds.
+    fn collateral_to_seize(
+        &self,
+        repay_amount: u64,
+        debt_price_usd_1e6: u64,
+        collateral_price_usd_1e6: u64,
+        bonus_bps: u64,
+        borrower_collateral: u64,
+    ) -> Result<u64> {
+        let repay_value_usd_1e6 =
+            calculate_borrowed_value_usd(repay_amount, debt_price_usd_1e6, self.debt_mint.decimals)?;
+        let seize_value_usd_1e6 = (repay_value_usd_1e6 as u128)
+            .checked_mul((10_000 + bonus_bps) as u128)
+            .ok_or(Errors::MathOverflow)?
+            .checked_div(10_000)
+            .ok_or(Errors::MathOverflow)?;
+
+        //seize_amount = USD * 10^dec / price
+        let seize_amount = seize_value_usd_1e6
+            .checked_mul(10u128.pow(self.collateral_mint.decimals as u32))
+            .ok_or(Errors::MathOverflow)?
+            .checked_div(collateral_price_usd_1e6 as u128)
+            .ok_or(Errors::MathOverflow)?;
+        Ok((seize_amount as u64).min(borrower_collateral))
+    }
+
+    /// Fails unless the pool the borrower's isolated debt is booked in is
+    /// among the accounts, so the debt can be released from its ceiling.
+    fn require_isolation_pool(&self) -> Result<()> {
+        let isolation_pool = self.borrower_position.isolation_pool;
+        require!(
+            self.borrower_position.isolated_debt_usd == 0
+                || isolation_pool == self.debt_pool.key()
+                || isolation_pool == self.collateral_pool.key()
+                || self.borrower_isolation_pool.is_some(),
+            Errors::MissingIsolationPool
+        );
         Ok(())
     }
-    
-    pub fn liquidate(&mut self, repay_amount: u64) -> Result<()> {
-        let close_factor = self.debt_pool.close_factor_bps;
+
+    /// Frees `repaid_usd` of the borrower's isolated debt from the isolated
+    /// pool's debt ceiling.
+    fn release_isolated_debt_usd(&mut self, repaid_usd: u64) -> Result<()> {
+        if self.borrower_position.isolated_debt_usd == 0 {
+            return Ok(());
+        }
+        let isolation_pool_key = self.borrower_position.isolation_pool;
+        let isolation_pool = if isolation_pool_key == self.debt_pool.key() {
+            &mut self.debt_pool
+        } else if isolation_pool_key == self.collateral_pool.key() {
+            &mut self.collateral_pool
+        } else {
+            self.borrower_isolation_pool
+                .as_mut()
+                .ok_or(Errors::MissingIsolationPool)?
+        };
+        release_isolated_debt(isolation_pool, &mut self.borrower_position, repaid_usd);
+        Ok(())
+    }
+
+    pub fn liquidate(
+        &mut self,
+        repay_amount: u64,
+        receive_dtokens: bool,
+        defer_repayment: bool,
+    ) -> Result<()> {
+        //exactly one destination for the seized collateral
+        if receive_dtokens {
+            require!(
+                self.liquidator_collateral_dtoken_ata.is_some(),
+                Errors::MissingLiquidatorDTokenAccount
+            );
+            require!(
+                self.liquidator_collateral_ata.is_none(),
+                Errors::UnexpectedLiquidatorCollateralAccount
+            );
+        } else {
+            require!(
+                self.liquidator_collateral_ata.is_some(),
+                Errors::MissingLiquidatorCollateralAccount
+            );
+            require!(
+                self.liquidator_collateral_dtoken_ata.is_none(),
+                Errors::UnexpectedLiquidatorCollateralAccount
+            );
+        }
+
+        self.require_isolation_pool()?;
 
         accrue_interest(&mut self.debt_pool)?;
         update_interest_rate(&mut self.debt_pool)?;
         update_user_borrow_state(&mut self.borrower_debt_position, &self.debt_pool)?;
 
         //read prices from both oracles
-        #[cfg(not(feature="test-mode"))]
-        let debt_price_usd_1e6 = {
-            let price_update = &self.debt_oracle;
-            let maximum_age: u64 = 30;
-            let feed_id: [u8; 32] = self.debt_pool.feed_id;
-            let price = price_update.get_price_no_older_than(&Clock::get()?, maximum_age, &feed_id)?;
-            normalize_pyth_price_to_usd_1e6(price.price, price.exponent)?
-        };
-        
-        #[cfg(feature="test-mode")]
-        let debt_price_usd_1e6 = {
-            let mock_oracle = &self.debt_oracle;
-            normalize_pyth_price_to_usd_1e6(mock_oracle.price, mock_oracle.expo)?
-        };
+        let debt_price_usd_1e6 = get_price_usd_1e6(
+            &self.debt_oracle.to_account_info(),
+            self.debt_secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.debt_pool,
+        )?;
 
-        #[cfg(not(feature="test-mode"))]
-        let collateral_price_usd_1e6 = {
-            let price_update = &self.collateral_oracle;
-            let maximum_age: u64 = i64::MAX as u64;
-            let feed_id: [u8; 32] = self.collateral_pool.feed_id;
-            let price = price_update.get_price_no_older_than(&Clock::get()?, maximum_age, &feed_id)?;
-            normalize_pyth_price_to_usd_1e6(price.price, price.exponent)?
-        };
-        
-        #[cfg(feature="test-mode")]
-        let collateral_price_usd_1e6 = {
-            let mock_oracle = &self.collateral_oracle;
-            normalize_pyth_price_to_usd_1e6(mock_oracle.price, mock_oracle.expo)?
+        let collateral_price_usd_1e6 =
+            get_price_usd_1e6(
+            &self.collateral_oracle.to_account_info(),
+            self.collateral_secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.collateral_pool,
+        )?;
+
+        //collateral is whatever the borrower's dTokens redeem for right now
+        let borrower_dtokens = self.borrower_collateral_dtoken_ata.amount;
+        let borrower_collateral = if borrower_dtokens == 0 {
+            0
+        } else {
+            calculate_underlying_amount(
+                borrower_dtokens,
+                self.collateral_pool.total_liquidity,
+                self.collateral_pool.total_dtoken_supplied,
+            )?
         };
         let collateral_decimals= self.collateral_mint.decimals;
+        //health is judged on the pool's valuation price, seizing uses spot
+        let collateral_valuation_price_usd_1e6 = valuation_price_usd_1e6(
+            collateral_price_usd_1e6,
+            &self.collateral_oracle.to_account_info(),
+            self.collateral_secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.collateral_pool,
+            PriceSide::Collateral,
+        )?;
         let current_collateral_value = calculate_borrowed_value_usd(
-            self.borrower_collateral_position.deposited_amount,
-            collateral_price_usd_1e6,  // Current price ($40)
+            borrower_collateral,
+            collateral_valuation_price_usd_1e6,
             collateral_decimals
         )?;
-        let current_debt_value = self.borrower_position.debt_value_usd;
+        //the debt pool's leg is re-priced at its valuation price, the
+        //borrower's other debt counts at the value it was booked at
+        let debt_valuation_price_usd_1e6 = valuation_price_usd_1e6(
+            debt_price_usd_1e6,
+            &self.debt_oracle.to_account_info(),
+            self.debt_secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.debt_pool,
+            PriceSide::Debt,
+        )?;
+        let debt_leg_value = calculate_borrowed_value_usd(
+            self.borrower_debt_position.borrowed_amount,
+            debt_valuation_price_usd_1e6,
+            self.debt_mint.decimals,
+        )?;
+        let current_debt_value = self
+            .borrower_position
+            .debt_value_usd
+            .saturating_sub(self.borrower_debt_position.borrowed_value_usd)
+            .checked_add(debt_leg_value)
+            .ok_or(Errors::MathOverflow)?;
+        //e-mode threshold and bonus apply when both pools are in the
+        //borrower's category
+        let emode_params = active_emode_category(
+            &self.borrower_position,
+            self.borrower_emode_category.as_deref().map(|category| &**category),
+            shared_emode_category(
+                Some(self.debt_pool.emode_category),
+                self.collateral_pool.emode_category,
+            ),
+        )?
+        .map(|category| (category.liquidation_threshold_bps, category.liquidation_bonus_bps));
+        let liquidation_threshold_bps = emode_params
+            .map_or(self.debt_pool.liquidation_treshold_bps, |(threshold, _)| threshold);
         //calculate health factor
         let hf = calculate_health_factor(
     current_collateral_value,  
         current_debt_value,        
-self.debt_pool.liquidation_treshold_bps as u64,
+liquidation_threshold_bps as u64,
         )?;
-        require!(hf < 10_000, Errors::NotLiquidatable);
+        //positions touching a deprecated pool can be closed at any health
+        let deprecated = self.debt_pool.status == PoolStatus::Deprecated
+            || self.collateral_pool.status == PoolStatus::Deprecated;
+        require!(hf < 10_000 || deprecated, Errors::NotLiquidatable);
 
         //calculate repay limits 
         let borrowed_amount = self.borrower_debt_position.borrowed_amount;
         require!(borrowed_amount > 0, Errors::NothingToLiquidate);
 
+        //deeply underwater and dust positions can be cleared in one call
+        let borrowed_value_usd =
+            calculate_borrowed_value_usd(borrowed_amount, debt_price_usd_1e6, self.debt_mint.decimals)?;
+        let close_factor = if deprecated
+            || hf < self.debt_pool.critical_health_factor_bps as u64
+            || borrowed_value_usd < self.debt_pool.full_liquidation_debt_usd
+        {
+            10_000
+        } else {
+            self.debt_pool.close_factor_bps
+        };
         let max_repay_by_close_factor: u64 = ((borrowed_amount as u128)
             .checked_mul(close_factor as u128)
             .ok_or(Errors::MathOverflow)?
@@ -203,34 +442,92 @@ self.debt_pool.liquidation_treshold_bps as u64,
             .min(borrowed_amount);
         require!(repay_amount > 0, Errors::InvalidAmount);
 
-        //calculate usd value of the repay amount
-        let debt_decimals = self.debt_mint.decimals;
-        let repay_value_usd_1e6 =
-            calculate_borrowed_value_usd(repay_amount, debt_price_usd_1e6, debt_decimals)?;
-
         //calculate seize amount with liquidation bonus
-        let bonus_bps = self.collateral_pool.liquidation_bonus_bps as u64;
-        let seize_value_usd_1e6 = (repay_value_usd_1e6 as u128)
-            .checked_mul((10_000 + bonus_bps) as u128)
-            .ok_or(Errors::MathOverflow)?
-            .checked_div(10_000)
-            .ok_or(Errors::MathOverflow)? as u64;
+        let bonus_bps = match emode_params {
+            Some((_, emode_bonus_bps)) => emode_bonus_bps as u64,
+            None => calculate_liquidation_bonus_bps(
+                hf,
+                self.collateral_pool.liquidation_bonus_bps,
+                self.collateral_pool.max_liquidation_bonus_bps,
+                self.collateral_pool.max_bonus_health_factor_bps,
+            )?,
+        };
+        //the vault only credits the repayment net of the debt mint's transfer
+        //fee, so the seizure is sized on that before any tokens move; a batch
+        //skips borrowers with nothing to seize and relies on this failing first
+        let received_amount = if defer_repayment {
+            repay_amount
+        } else {
+            net_of_transfer_fee(&self.debt_mint, repay_amount)?
+        };
+        let seize_amount = self.collateral_to_seize(
+            received_amount,
+            debt_price_usd_1e6,
+            collateral_price_usd_1e6,
+            bonus_bps,
+            borrower_collateral,
+        )?;
+        require!(seize_amount > 0, Errors::InsufficientCollateralToSeize);
 
-        let collateral_decimals = self.collateral_mint.decimals;
-        
-        //seize_amount = USD * 10^dec / price
-        let seize_amount_u128 = (seize_value_usd_1e6 as u128)
-            .checked_mul(10u128.pow(collateral_decimals as u32))
-            .ok_or(Errors::MathOverflow)?
-            .checked_div(collateral_price_usd_1e6 as u128)
-            .ok_or(Errors::MathOverflow)?;
-        let mut seize_amount = seize_amount_u128 as u64;
+        //a deferred repayment is owed to the debt pool as a flash loan and
+        //settled by `flash_repay`
+        let repay_amount = if defer_repayment {
+            require!(self.debt_pool.flash_loan_amount == 0, Errors::FlashLoanActive);
+            self.debt_pool.flash_loan_amount = repay_amount;
+            repay_amount
+        } else {
+            self.transfer_from_liquidator_to_debt_vault(repay_amount)?
+        };
 
-        seize_amount = seize_amount.min(self.borrower_collateral_position.deposited_amount);
-        require!(seize_amount > 0, Errors::InsufficientCollateralToSeize);
+        //burn enough dTokens to cover the seized underlying, rounding up
+        let dtokens_to_burn = if seize_amount == borrower_collateral {
+            borrower_dtokens
+        } else {
+            calculate_dtoken_burn_amount(
+                seize_amount,
+                self.collateral_pool.total_liquidity,
+                self.collateral_pool.total_dtoken_supplied,
+            )?
+            .min(borrower_dtokens)
+        };
 
-        self.transfer_from_liquidator_to_debt_vault(repay_amount)?;
-        self.transfer_collateral_to_liquidator(seize_amount)?;
+        //the protocol's share of the bonus stays in the vault as reserves
+        let protocol_fee = calculate_protocol_liquidation_fee(
+            seize_amount,
+            bonus_bps,
+            self.collateral_pool.protocol_liquidation_fee_bps,
+        )?;
+        let liquidator_amount = seize_amount
+            .checked_sub(protocol_fee)
+            .ok_or(Errors::MathOverflow)?;
+
+        //with `receive_dtokens` the liquidator's share is reissued as dTokens
+        //and only the protocol's share leaves the supply, so no cash leaves
+        //the vault and a fully utilized pool can still be liquidated
+        let seized_dtokens = dtokens_to_burn;
+        if seized_dtokens > 0 {
+            self.burn_borrower_dtokens(seized_dtokens)?;
+        }
+        let dtokens_to_burn = if receive_dtokens {
+            let fee_dtokens = if protocol_fee == 0 {
+                0
+            } else {
+                calculate_dtoken_burn_amount(
+                    protocol_fee,
+                    self.collateral_pool.total_liquidity,
+                    self.collateral_pool.total_dtoken_supplied,
+                )?
+                .min(seized_dtokens)
+            };
+            self.mint_dtokens_to_liquidator(seized_dtokens - fee_dtokens)?;
+            fee_dtokens
+        } else {
+            seized_dtokens
+        };
+        let released_amount = if receive_dtokens { protocol_fee } else { seize_amount };
+        if !receive_dtokens {
+            self.transfer_collateral_to_liquidator(liquidator_amount)?;
+        }
         
         //update states
         let seize_value_actual = calculate_borrowed_value_usd(
@@ -245,11 +542,19 @@ self.debt_pool.liquidation_treshold_bps as u64,
             .saturating_sub(seize_value_actual);
             
         // Update debt position - reduce borrowed amount
+        let repaid_value_usd = release_borrowed_value_usd(
+            &mut self.borrower_debt_position,
+            borrowed_amount,
+            repay_amount,
+        )?;
         self.borrower_debt_position.borrowed_amount = self
             .borrower_debt_position
             .borrowed_amount
             .checked_sub(repay_amount)
             .ok_or(Errors::MathOverflow)?;
+        if self.borrower_debt_position.borrowed_amount == 0 {
+            close_borrow(&mut self.borrower_position, &mut self.borrower_debt_position);
+        }
             
         self.debt_pool.total_borrowed = self
             .debt_pool
@@ -257,23 +562,28 @@ self.debt_pool.liquidation_treshold_bps as u64,
             .checked_sub(repay_amount)
             .ok_or(Errors::MathOverflow)?;
             
-        // Update collateral position - reduce deposited amount
-        self.borrower_collateral_position.deposited_amount = self
-            .borrower_collateral_position
-            .deposited_amount
-            .checked_sub(seize_amount)
+        self.collateral_pool.total_dtoken_supplied = self
+            .collateral_pool
+            .total_dtoken_supplied
+            .checked_sub(dtokens_to_burn)
             .ok_or(Errors::MathOverflow)?;
             
         self.collateral_pool.total_liquidity = self
             .collateral_pool
             .total_liquidity
-            .checked_sub(seize_amount)
+            .checked_sub(released_amount)
+            .ok_or(Errors::MathOverflow)?;
+        self.collateral_pool.total_reserves = self
+            .collateral_pool
+            .total_reserves
+            .checked_add(protocol_fee)
             .ok_or(Errors::MathOverflow)?;
             
         self.borrower_position.debt_value_usd = self
             .borrower_position
             .debt_value_usd
-            .saturating_sub(repay_value_usd_1e6);
+            .saturating_sub(repaid_value_usd);
+        self.release_isolated_debt_usd(repaid_value_usd)?;
 
         emit!(LiquidateEvent{
             liquidator: self.liquidator.key(),
@@ -282,6 +592,9 @@ self.debt_pool.liquidation_treshold_bps as u64,
             collater_seized: seize_amount,
             debt_pool: self.debt_pool.key(),
             collateral_pool: self.collateral_pool.key(),
+            bonus_bps,
+            protocol_fee,
+            receive_dtokens,
         });
         Ok(())
     }
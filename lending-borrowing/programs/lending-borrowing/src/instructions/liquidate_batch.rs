@@ -0,0 +1,246 @@
+use crate::{error::Errors, instructions::Liquidate, state::*};
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::{get_associated_token_address_with_program_id, AssociatedToken},
+    token_2022::{Token2022, ID as TOKEN_2022_PROGRAM_ID},
+    token_interface::{Mint, TokenAccount, TokenInterface},
+};
+
+/// Accounts per borrower in `remaining_accounts`:
+/// `[borrower, borrower_debt_position, borrower_position, borrower_collateral_dtoken_ata]`.
+const BATCH_BORROWER_ACCOUNTS: usize = 4;
+
+// Same accounts as `Liquidate` minus the borrower's, which are passed per
+// borrower through `remaining_accounts`.
+#[derive(Accounts)]
+pub struct LiquidateBatch<'info> {
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    #[account(
+        address= debt_pool.mint,
+        mint::token_program = debt_token_program,
+    )]
+    pub debt_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        seeds =[b"pool", config.key().as_ref(), debt_mint.key().as_ref()],
+        bump = debt_pool.pool_bump,
+    )]
+    pub debt_pool: Box<Account<'info, Pool>>,
+    #[account(
+        seeds= [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump= config.bump,
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        associated_token::mint= debt_mint,
+        associated_token::authority=debt_pool,
+        associated_token::token_program = debt_token_program,
+    )]
+    pub debt_pool_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint= debt_mint,
+        associated_token::authority= liquidator,
+        associated_token::token_program = debt_token_program,
+    )]
+    pub liquidator_debt_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        address= collateral_pool.mint,
+        mint::token_program = collateral_token_program,
+    )]
+    pub collateral_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        seeds=[b"pool", config.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = collateral_pool.pool_bump,
+    )]
+    pub collateral_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        address = collateral_pool.mint_dtoken,
+        mint::token_program = dtoken_program,
+    )]
+    pub collateral_dtoken_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint= collateral_mint,
+        associated_token::authority= collateral_pool,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub collateral_pool_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = collateral_mint,
+        associated_token::authority=liquidator,
+        associated_token::token_program = collateral_token_program,
+    )]
+    // only needed when the liquidator takes the seized collateral as tokens
+    pub liquidator_collateral_ata: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // only needed when the liquidator takes the seized collateral as dTokens
+    #[account(
+        mut,
+        associated_token::mint = collateral_dtoken_mint,
+        associated_token::authority = liquidator,
+        associated_token::token_program = dtoken_program,
+    )]
+    pub liquidator_collateral_dtoken_ata: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// CHECK: validated against `debt_pool.oracle` and parsed according to
+    /// `debt_pool.oracle_source` by `get_price_usd_1e6`
+    #[account(address = debt_pool.oracle)]
+    pub debt_oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `debt_pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = debt_pool.secondary_oracle)]
+    pub debt_secondary_oracle: Option<UncheckedAccount<'info>>,
+    /// CHECK: validated against `collateral_pool.oracle` and parsed according to
+    /// `collateral_pool.oracle_source` by `get_price_usd_1e6`
+    #[account(address = collateral_pool.oracle)]
+    pub collateral_oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `collateral_pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = collateral_pool.secondary_oracle)]
+    pub collateral_secondary_oracle: Option<UncheckedAccount<'info>>,
+    // used for borrowers in this e-mode category, borrowers in another one
+    // are skipped
+    #[account(
+        seeds = [b"emode-category", config.key().as_ref(), &[emode_category.id]],
+        bump = emode_category.bump,
+    )]
+    pub emode_category: Option<Box<Account<'info, EModeCategory>>>,
+    // releases the isolated debt of borrowers isolated in this pool;
+    // borrowers isolated elsewhere than here or the debt or collateral pool
+    // are skipped
+    #[account(
+        mut,
+        constraint = isolation_pool.key() != debt_pool.key()
+            && isolation_pool.key() != collateral_pool.key()
+            @ Errors::DuplicateIsolationPool,
+    )]
+    pub isolation_pool: Option<Box<Account<'info, Pool>>>,
+    pub debt_token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Interface<'info, TokenInterface>,
+    pub dtoken_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> LiquidateBatch<'info> {
+    /// Liquidates every borrower in `remaining_accounts` that is below HF 1.0,
+    /// repaying up to `max_repay_amount` of each one's debt within the close
+    /// factor. Healthy or debt-free borrowers are skipped, as are borrowers
+    /// with nothing to seize or a repayment that rounds to zero, borrowers in
+    /// an e-mode category other than `emode_category` and borrowers whose
+    /// isolated debt is booked in a pool that was not passed.
+    pub fn liquidate_batch(
+        &mut self,
+        max_repay_amount: u64,
+        receive_dtokens: bool,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let groups = remaining_accounts.chunks_exact(BATCH_BORROWER_ACCOUNTS);
+        require!(
+            !remaining_accounts.is_empty() && groups.remainder().is_empty(),
+            Errors::InvalidBorrowerAccounts
+        );
+
+        for group in groups {
+            let mut liquidation = self.borrower_liquidation(group)?;
+            match liquidation.liquidate(max_repay_amount, receive_dtokens, false) {
+                Ok(()) => {
+                    liquidation.borrower_debt_position.exit(&crate::ID)?;
+                    liquidation.borrower_position.exit(&crate::ID)?;
+                }
+                //`liquidate` raises all of these before its first transfer, so
+                //a skipped borrower leaves no tokens moved
+                Err(error)
+                    if error == Errors::NotLiquidatable.into()
+                        || error == Errors::NothingToLiquidate.into()
+                        || error == Errors::InvalidAmount.into()
+                        || error == Errors::InsufficientCollateralToSeize.into()
+                        || error == Errors::MissingEModeCategory.into()
+                        || error == Errors::MissingIsolationPool.into() => {}
+                Err(error) => return Err(error),
+            }
+            //carry pool state and reloaded balances into the next borrower
+            self.debt_pool = liquidation.debt_pool;
+            self.collateral_pool = liquidation.collateral_pool;
+            self.debt_pool_vault = liquidation.debt_pool_vault;
+            self.liquidator_debt_ata = liquidation.liquidator_debt_ata;
+            if liquidation.borrower_isolation_pool.is_some() {
+                self.isolation_pool = liquidation.borrower_isolation_pool;
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the single-borrower `Liquidate` accounts for one
+    /// `remaining_accounts` group, checking the borrower's accounts the way
+    /// `Liquidate`'s constraints would.
+    fn borrower_liquidation(&self, group: &'info [AccountInfo<'info>]) -> Result<Liquidate<'info>> {
+        let borrower = SystemAccount::try_from(&group[0])?;
+        let borrower_debt_position = Account::<UserPoolPosition>::try_from(&group[1])?;
+        let borrower_position = Account::<UserPosition>::try_from(&group[2])?;
+        require!(
+            group[1].is_writable && group[2].is_writable && group[3].is_writable,
+            Errors::InvalidBorrowerAccounts
+        );
+        require!(
+            borrower_debt_position.user == borrower.key()
+                && borrower_debt_position.pool == self.debt_pool.key()
+                && borrower_position.user == borrower.key(),
+            Errors::InvalidBorrowerAccounts
+        );
+        //user positions are per market, so only this market's PDA will do
+        let (borrower_position_key, _) = Pubkey::find_program_address(
+            &[b"user-position", self.config.key().as_ref(), borrower.key().as_ref()],
+            &crate::ID,
+        );
+        require_keys_eq!(group[2].key(), borrower_position_key, Errors::InvalidBorrowerAccounts);
+        require_keys_eq!(
+            group[3].key(),
+            get_associated_token_address_with_program_id(
+                &borrower.key(),
+                &self.collateral_dtoken_mint.key(),
+                &TOKEN_2022_PROGRAM_ID
+            ),
+            Errors::InvalidBorrowerAccounts
+        );
+        let borrower_collateral_dtoken_ata = InterfaceAccount::<TokenAccount>::try_from(&group[3])?;
+        let borrower_isolation_pool = self
+            .isolation_pool
+            .clone()
+            .filter(|pool| pool.key() == borrower_position.isolation_pool);
+
+        Ok(Liquidate {
+            liquidator: self.liquidator.clone(),
+            borrower,
+            debt_mint: self.debt_mint.clone(),
+            debt_pool: self.debt_pool.clone(),
+            config: self.config.clone(),
+            borrower_debt_position: Box::new(borrower_debt_position),
+            borrower_position: Box::new(borrower_position),
+            debt_pool_vault: self.debt_pool_vault.clone(),
+            liquidator_debt_ata: self.liquidator_debt_ata.clone(),
+            collateral_mint: self.collateral_mint.clone(),
+            collateral_pool: self.collateral_pool.clone(),
+            collateral_dtoken_mint: self.collateral_dtoken_mint.clone(),
+            borrower_collateral_dtoken_ata: Box::new(borrower_collateral_dtoken_ata),
+            collateral_pool_vault: self.collateral_pool_vault.clone(),
+            liquidator_collateral_ata: self.liquidator_collateral_ata.clone(),
+            liquidator_collateral_dtoken_ata: self.liquidator_collateral_dtoken_ata.clone(),
+            debt_oracle: self.debt_oracle.clone(),
+            debt_secondary_oracle: self.debt_secondary_oracle.clone(),
+            collateral_oracle: self.collateral_oracle.clone(),
+            collateral_secondary_oracle: self.collateral_secondary_oracle.clone(),
+            borrower_isolation_pool,
+            borrower_emode_category: self.emode_category.clone(),
+            debt_token_program: self.debt_token_program.clone(),
+            collateral_token_program: self.collateral_token_program.clone(),
+            dtoken_program: self.dtoken_program.clone(),
+            associated_token_program: self.associated_token_program.clone(),
+            system_program: self.system_program.clone(),
+        })
+    }
+}
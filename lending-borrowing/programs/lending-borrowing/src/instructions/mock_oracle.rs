@@ -0,0 +1,83 @@
+use crate::{
+    error::Errors,
+    state::{Config, MockOracle},
+};
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+// Test-mode price feeds, one per underlying mint so pools can be priced
+// independently. Pools point at them through `Pool.oracle` with
+// `OracleSource::Mock`.
+
+#[derive(Accounts)]
+pub struct CreateMockOracle<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + MockOracle::INIT_SPACE,
+        seeds = [b"mock-oracle", config.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub mock_oracle: Account<'info, MockOracle>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> CreateMockOracle<'info> {
+    pub fn create_mock_oracle(
+        &mut self,
+        price: i64,
+        conf: u64,
+        expo: i32,
+        bumps: &CreateMockOracleBumps,
+    ) -> Result<()> {
+        require!(price > 0, Errors::InvalidPrice);
+        self.mock_oracle.set_inner(MockOracle {
+            mint: self.mint.key(),
+            price,
+            conf,
+            expo,
+            publish_time: Clock::get()?.unix_timestamp,
+            bump: bumps.mock_oracle,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct UpdateMockOracle<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds= [b"mock-oracle", config.key().as_ref(), mock_oracle.mint.as_ref()],
+        bump= mock_oracle.bump,
+    )]
+    pub mock_oracle: Account<'info, MockOracle>,
+    #[account(
+        seeds=[b"config", admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump= config.bump,
+    )]
+    pub config: Account<'info, Config>,
+}
+
+impl<'info> UpdateMockOracle<'info> {
+    pub fn update_mock_oracle(&mut self, new_price: i64, new_expo: i32) -> Result<()> {
+        self.mock_oracle.price = new_price;
+        self.mock_oracle.expo = new_expo;
+        self.mock_oracle.publish_time = Clock::get()?.unix_timestamp;
+        msg!(
+            "Mock oracle updated: price={}, expo={}",
+            new_price,
+            new_expo
+        );
+        Ok(())
+    }
+}
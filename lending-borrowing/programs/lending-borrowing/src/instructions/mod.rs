@@ -2,6 +2,38 @@ pub mod init_config;
 pub use init_config::*;
 pub mod create_pool;
 pub use create_pool::*;
+pub mod update_dtoken_metadata;
+pub use update_dtoken_metadata::*;
+pub mod dtoken_transfer_hook;
+pub use dtoken_transfer_hook::*;
+pub mod update_oracle_config;
+pub use update_oracle_config::*;
+pub mod update_secondary_oracle;
+pub use update_secondary_oracle::*;
+pub mod update_price_mode;
+pub use update_price_mode::*;
+pub mod update_pool_caps;
+pub use update_pool_caps::*;
+pub mod update_pool_flags;
+pub use update_pool_flags::*;
+pub mod update_pool_status;
+pub use update_pool_status::*;
+pub mod update_isolation_mode;
+pub use update_isolation_mode::*;
+pub mod emode;
+pub use emode::*;
+pub mod update_liquidation_bonus;
+pub use update_liquidation_bonus::*;
+pub mod update_protocol_liquidation_fee;
+pub use update_protocol_liquidation_fee::*;
+pub mod update_close_factor;
+pub use update_close_factor::*;
+pub mod update_flash_loan_fee;
+pub use update_flash_loan_fee::*;
+pub mod withdraw_reserves;
+pub use withdraw_reserves::*;
+pub mod refresh_twap;
+pub use refresh_twap::*;
 pub mod borrow;
 pub use borrow::*;
 pub mod withdraw;
@@ -10,5 +42,17 @@ pub mod repay;
 pub use repay::*;
 pub mod liquidate;
 pub use liquidate::*;
+pub mod liquidate_batch;
+pub use liquidate_batch::*;
+pub mod write_off_bad_debt;
+pub use write_off_bad_debt::*;
+pub mod flash_loan;
+pub use flash_loan::*;
 pub mod deposit_tokens;
 pub use deposit_tokens::*;
+pub mod native_sol;
+pub use native_sol::*;
+#[cfg(feature = "test-mode")]
+pub mod mock_oracle;
+#[cfg(feature = "test-mode")]
+pub use mock_oracle::*;
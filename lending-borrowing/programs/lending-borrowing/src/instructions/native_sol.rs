@@ -0,0 +1,101 @@
+use crate::{error::Errors, helpers::token::unwrap_sol, instructions::*};
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use anchor_spl::{
+    token::spl_token::native_mint::ID as NATIVE_MINT,
+    token_interface::{sync_native, SyncNative},
+};
+
+// SOL variants of deposit, borrow and withdraw for the wSOL pool. They take
+// the same accounts as the token instructions; `user_ata` is the user's wSOL
+// ATA, created on demand and closed afterwards so the user only ever sees
+// native SOL. Any wSOL already sitting in that ATA is unwrapped as well.
+
+#[derive(Accounts)]
+pub struct DepositSol<'info> {
+    #[account(constraint = deposit.pool.mint == NATIVE_MINT @ Errors::NotNativeMint)]
+    pub deposit: DepositTokens<'info>,
+}
+
+impl<'info> DepositSol<'info> {
+    pub fn deposit_sol(&mut self, lamports: u64) -> Result<()> {
+        require!(lamports > 0, Errors::AmountZero);
+        let deposit = &mut self.deposit;
+
+        //move lamports straight into the vault and let the token program
+        //count them as wSOL; only the lamports moved here are credited, so
+        //anything sent to the vault outside the program is never claimable
+        transfer(
+            CpiContext::new(
+                deposit.system_program.to_account_info(),
+                Transfer {
+                    from: deposit.user.to_account_info(),
+                    to: deposit.vault.to_account_info(),
+                },
+            ),
+            lamports,
+        )?;
+        sync_native(CpiContext::new(
+            deposit.token_program.to_account_info(),
+            SyncNative {
+                account: deposit.vault.to_account_info(),
+            },
+        ))?;
+
+        deposit.credit_deposit(lamports)?;
+
+        //hand back the rent of an empty wSOL ATA
+        if deposit.user_ata.amount == 0 {
+            unwrap_sol(
+                deposit.token_program.to_account_info(),
+                deposit.user_ata.to_account_info(),
+                deposit.user.to_account_info(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct BorrowSol<'info> {
+    #[account(constraint = borrow.pool.mint == NATIVE_MINT @ Errors::NotNativeMint)]
+    pub borrow: Borrow<'info>,
+}
+
+impl<'info> BorrowSol<'info> {
+    pub fn borrow_sol(
+        &mut self,
+        amount: u64,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let borrow = &mut self.borrow;
+        borrow.borrow(amount, remaining_accounts)?;
+        unwrap_sol(
+            borrow.token_program.to_account_info(),
+            borrow.user_ata.to_account_info(),
+            borrow.user.to_account_info(),
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSol<'info> {
+    #[account(constraint = withdraw.pool.mint == NATIVE_MINT @ Errors::NotNativeMint)]
+    pub withdraw: Withdraw<'info>,
+}
+
+impl<'info> WithdrawSol<'info> {
+    pub fn withdraw_sol(
+        &mut self,
+        dtoken_amount: u64,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let withdraw = &mut self.withdraw;
+        withdraw.withdraw(dtoken_amount, remaining_accounts)?;
+        unwrap_sol(
+            withdraw.token_program.to_account_info(),
+            withdraw.user_token_ata.to_account_info(),
+            withdraw.user.to_account_info(),
+        )
+    }
+}
@@ -0,0 +1,48 @@
+use crate::event::RefreshTwapEvent;
+use crate::helpers::oracle::{get_price_usd_1e6, update_twap};
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+/// Permissionless crank that folds the current spot price into the pool's TWAP.
+#[derive(Accounts)]
+pub struct RefreshTwap<'info> {
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: validated against `pool.oracle` and parsed according to
+    /// `pool.oracle_source` by `get_price_usd_1e6`
+    #[account(address = pool.oracle)]
+    pub oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = pool.secondary_oracle)]
+    pub secondary_oracle: Option<UncheckedAccount<'info>>,
+}
+
+impl<'info> RefreshTwap<'info> {
+    pub fn refresh_twap(&mut self) -> Result<()> {
+        let spot_price_usd_1e6 = get_price_usd_1e6(
+            &self.oracle.to_account_info(),
+            self.secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.pool,
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        update_twap(&mut self.pool, spot_price_usd_1e6, now)?;
+
+        emit!(RefreshTwapEvent {
+            pool: self.pool.key(),
+            spot_price_usd_1e6,
+            twap_price_usd_1e6: self.pool.twap_price_usd_1e6,
+            timestamp: now,
+        });
+        Ok(())
+    }
+}
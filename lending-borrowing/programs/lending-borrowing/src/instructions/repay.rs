@@ -1,18 +1,22 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{transfer, Mint, Token, TokenAccount, Transfer};
-#[cfg(not(feature="test-mode"))]
-use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
-#[cfg(feature="test-mode")]
-use crate::state::config::MockOracle;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
-use crate::{error::Errors, event::RepayEvent, helpers::interest::*, math::*, state::*};
+use crate::{
+    error::Errors,
+    event::RepayEvent,
+    helpers::{collateral::release_isolated_debt, interest::*, token::transfer_into_vault},
+    state::*,
+};
 
 #[derive(Accounts)]
 pub struct Repay<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    #[account(address=pool.mint)]
-    pub underlying_mint: Account<'info, Mint>,
+    #[account(
+        address=pool.mint,
+        mint::token_program = token_program,
+    )]
+    pub underlying_mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
         seeds= [b"pool", config.key().as_ref(), underlying_mint.key().as_ref()],
@@ -20,7 +24,7 @@ pub struct Repay<'info> {
     )]
     pub pool: Account<'info, Pool>,
     #[account(
-        seeds=[b"config", config.admin.key().as_ref()],
+        seeds=[b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
         bump= config.bump,
     )]
     pub config: Account<'info, Config>,
@@ -28,17 +32,19 @@ pub struct Repay<'info> {
         mut, 
         associated_token::mint = underlying_mint,
         associated_token::authority= user,
+        associated_token::token_program = token_program,
     )]
-    pub user_mint_ata: Account<'info, TokenAccount>,
+    pub user_mint_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut, 
         associated_token::mint= underlying_mint,
         associated_token::authority= pool,
+        associated_token::token_program = token_program,
     )]
-    pub vault: Account<'info, TokenAccount>,
+    pub vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut, 
-        seeds=[b"user-position", user.key().as_ref()],
+        seeds=[b"user-position", config.key().as_ref(), user.key().as_ref()],
         bump, 
     )]
     pub user_position: Account<'info, UserPosition>,
@@ -48,43 +54,44 @@ pub struct Repay<'info> {
         bump,
     )]
     pub user_pool_position: Account<'info, UserPoolPosition>,
-    #[cfg(not(feature="test-mode"))]
-    pub oracle: Account<'info, PriceUpdateV2>,
-    // Test mode:  mock oracle
-    #[cfg(feature = "test-mode")]
+    // required while the user has debt backed by isolated collateral, unless
+    // the isolated pool is the pool being repaid
     #[account(
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= oracle.bump,
+        mut,
+        address = user_position.isolation_pool,
+        constraint = isolation_pool.key() != pool.key() @ Errors::DuplicateIsolationPool,
     )]
-    pub oracle: Account<'info, MockOracle>,
-    pub token_program: Program<'info, Token>,
+    pub isolation_pool: Option<Box<Account<'info, Pool>>>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 impl<'info> Repay<'info> {
     pub fn repay(&mut self, amount: u64) -> Result<()> {
         accrue_interest(&mut self.pool)?;
         update_interest_rate(&mut self.pool)?;
-        update_user_borrow_state(&mut self.user_pool_position, &mut self.pool)?;
+        update_user_borrow_state(&mut self.user_pool_position, &self.pool)?;
 
         let old_debt = self.user_pool_position.borrowed_amount;
         if old_debt == 0 || amount == 0 {
             return Ok(());
         }
-        let repay_amount = amount.min(old_debt);
+        //transfer repayment tokens from user to the pool vault,
+        //only what arrives after transfer fees reduces the debt
+        let repay_amount = transfer_into_vault(
+            self.token_program.to_account_info(),
+            self.user_mint_ata.to_account_info(),
+            &self.underlying_mint,
+            &mut self.vault,
+            self.user.to_account_info(),
+            amount.min(old_debt),
+        )?;
         let new_debt = old_debt
             .checked_sub(repay_amount)
             .ok_or(Errors::MathOverflow)?;
         self.user_pool_position.borrowed_amount = new_debt;
-
-        //transfer repayment tokens from user to the pool vault
-        let program = self.token_program.to_account_info();
-        let accounts = Transfer {
-            from: self.user_mint_ata.to_account_info(),
-            to: self.vault.to_account_info(),
-            authority: self.user.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(program, accounts);
-        transfer(cpi_ctx, repay_amount)?;
+        if new_debt == 0 {
+            close_borrow(&mut self.user_position, &mut self.user_pool_position);
+        }
         self.pool.total_borrowed = self
             .pool
             .total_borrowed
@@ -96,29 +103,23 @@ impl<'info> Repay<'info> {
             .checked_add(repay_amount)
             .ok_or(Errors::MathOverflow)?;
 
-        //oracle + decimals
-          #[cfg(not(feature="test-mode"))]
-        let price_usd_1e6 = {
-            let price_update = &self.oracle;
-            let maximum_age: u64 = i64::MAX as u64;
-            let feed_id: [u8; 32] = self.pool.feed_id;
-            let price = price_update.get_price_no_older_than(&Clock::get()?, maximum_age, &feed_id)?;
-            normalize_pyth_price_to_usd_1e6(price.price, price.exponent)?
-        };
-        
-        #[cfg(feature="test-mode")]
-        let price_usd_1e6 = {
-            let mock_oracle = &self.oracle;
-            normalize_pyth_price_to_usd_1e6(mock_oracle.price, mock_oracle.expo)?
-        };
-         let decimals = self.underlying_mint.decimals;
-        //compute new total debt USD
-        let old_usd = calculate_borrowed_value_usd(old_debt, price_usd_1e6, decimals)?;
-        let new_usd = calculate_borrowed_value_usd(new_debt, price_usd_1e6, decimals)?;
-        let delta_usd = old_usd.checked_sub(new_usd).ok_or(Errors::MathOverflow)?;
+        //debt leaves at the value borrow booked it at, so repaying never
+        //waits on a fresh price
+        let delta_usd =
+            release_borrowed_value_usd(&mut self.user_pool_position, old_debt, repay_amount)?;
 
         self.user_position.debt_value_usd =
             self.user_position.debt_value_usd.saturating_sub(delta_usd);
+        if self.user_position.isolated_debt_usd > 0 {
+            let isolation_pool = if self.user_position.isolation_pool == self.pool.key() {
+                &mut self.pool
+            } else {
+                self.isolation_pool
+                    .as_mut()
+                    .ok_or(Errors::MissingIsolationPool)?
+            };
+            release_isolated_debt(isolation_pool, &mut self.user_position, delta_usd);
+        }
 
         emit!(RepayEvent {
             user: self.user.key(),
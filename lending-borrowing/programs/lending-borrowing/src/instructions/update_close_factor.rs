@@ -0,0 +1,53 @@
+use crate::error::Errors;
+use crate::event::UpdateCloseFactorEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdateCloseFactor<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdateCloseFactor<'info> {
+    /// Sets how much of a borrower's debt in this pool one liquidation may
+    /// repay. Positions below `critical_health_factor_bps`, or owing less than
+    /// `full_liquidation_debt_usd` here, can be repaid in full.
+    pub fn update_close_factor(
+        &mut self,
+        close_factor_bps: u16,
+        critical_health_factor_bps: u16,
+        full_liquidation_debt_usd: u64,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        require!(
+            close_factor_bps > 0
+                && close_factor_bps <= 10_000
+                && critical_health_factor_bps < 10_000,
+            Errors::InvalidCloseFactor
+        );
+
+        self.pool.close_factor_bps = close_factor_bps;
+        self.pool.critical_health_factor_bps = critical_health_factor_bps;
+        self.pool.full_liquidation_debt_usd = full_liquidation_debt_usd;
+
+        emit!(UpdateCloseFactorEvent {
+            pool: self.pool.key(),
+            close_factor_bps,
+            critical_health_factor_bps,
+            full_liquidation_debt_usd,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
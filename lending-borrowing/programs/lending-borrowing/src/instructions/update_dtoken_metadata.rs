@@ -0,0 +1,80 @@
+use crate::error::Errors;
+use crate::event::UpdateDTokenMetadataEvent;
+use crate::helpers::metadata::*;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    token_2022::Token2022,
+    token_interface::{
+        spl_token_metadata_interface::state::Field, token_metadata_update_field, Mint,
+        TokenMetadataUpdateField,
+    },
+};
+
+#[derive(Accounts)]
+pub struct UpdateDTokenMetadata<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        address = pool.mint_dtoken,
+        mint::token_program = dtoken_program,
+    )]
+    pub dtoken_mint: InterfaceAccount<'info, Mint>,
+    pub dtoken_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> UpdateDTokenMetadata<'info> {
+    pub fn update_dtoken_metadata_uri(&mut self, uri: String) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        require!(uri.len() <= MAX_DTOKEN_URI_LEN, Errors::InvalidMetadata);
+
+        let config_key = self.config.key();
+        let mint_key = self.pool.mint;
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"pool",
+            config_key.as_ref(),
+            mint_key.as_ref(),
+            &[self.pool.pool_bump],
+        ]];
+        token_metadata_update_field(
+            CpiContext::new_with_signer(
+                self.dtoken_program.to_account_info(),
+                TokenMetadataUpdateField {
+                    program_id: self.dtoken_program.to_account_info(),
+                    metadata: self.dtoken_mint.to_account_info(),
+                    update_authority: self.pool.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            Field::Uri,
+            uri.clone(),
+        )?;
+
+        // a longer URI grows the mint account, so cover the extra rent
+        fund_rent_exemption(
+            self.dtoken_mint.to_account_info(),
+            self.admin.to_account_info(),
+            self.system_program.to_account_info(),
+        )?;
+
+        emit!(UpdateDTokenMetadataEvent {
+            pool: self.pool.key(),
+            dtoken_mint: self.dtoken_mint.key(),
+            uri,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use crate::error::Errors;
+use crate::event::UpdateFlashLoanFeeEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdateFlashLoanFee<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdateFlashLoanFee<'info> {
+    /// Sets the flash loan fee and the share of it kept as protocol reserves.
+    pub fn update_flash_loan_fee(
+        &mut self,
+        flash_loan_fee_bps: u16,
+        flash_loan_protocol_share_bps: u16,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        require!(
+            flash_loan_fee_bps <= 10_000 && flash_loan_protocol_share_bps <= 10_000,
+            Errors::InvalidFlashLoanFee
+        );
+
+        self.pool.flash_loan_fee_bps = flash_loan_fee_bps;
+        self.pool.flash_loan_protocol_share_bps = flash_loan_protocol_share_bps;
+
+        emit!(UpdateFlashLoanFeeEvent {
+            pool: self.pool.key(),
+            flash_loan_fee_bps,
+            flash_loan_protocol_share_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use crate::error::Errors;
+use crate::event::UpdateIsolationModeEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdateIsolationMode<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdateIsolationMode<'info> {
+    /// Marks the pool's asset as isolated collateral with a USD debt ceiling,
+    /// and/or as one of the pools isolated collateral may be borrowed from.
+    /// Debt already booked against the ceiling is kept when it changes.
+    pub fn update_isolation_mode(
+        &mut self,
+        isolated: bool,
+        isolation_debt_ceiling_usd: u64,
+        borrowable_in_isolation: bool,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+
+        self.pool.isolated = isolated;
+        self.pool.isolation_debt_ceiling_usd = isolation_debt_ceiling_usd;
+        self.pool.borrowable_in_isolation = borrowable_in_isolation;
+
+        emit!(UpdateIsolationModeEvent {
+            pool: self.pool.key(),
+            isolated,
+            isolation_debt_ceiling_usd,
+            borrowable_in_isolation,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
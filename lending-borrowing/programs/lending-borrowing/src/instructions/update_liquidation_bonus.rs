@@ -0,0 +1,57 @@
+use crate::error::Errors;
+use crate::event::UpdateLiquidationBonusEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdateLiquidationBonus<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdateLiquidationBonus<'info> {
+    /// Sets the bonus liquidators earn on this pool's collateral. With a
+    /// non-zero `max_liquidation_bonus_bps` the bonus scales from
+    /// `liquidation_bonus_bps` at HF 1.0 up to the max at
+    /// `max_bonus_health_factor_bps`.
+    pub fn update_liquidation_bonus(
+        &mut self,
+        liquidation_bonus_bps: u16,
+        max_liquidation_bonus_bps: u16,
+        max_bonus_health_factor_bps: u16,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        require!(liquidation_bonus_bps <= 10_000, Errors::InvalidLiquidationBonus);
+        if max_liquidation_bonus_bps > 0 {
+            require!(
+                max_liquidation_bonus_bps >= liquidation_bonus_bps
+                    && max_liquidation_bonus_bps <= 10_000
+                    && max_bonus_health_factor_bps < 10_000,
+                Errors::InvalidLiquidationBonus
+            );
+        }
+
+        self.pool.liquidation_bonus_bps = liquidation_bonus_bps;
+        self.pool.max_liquidation_bonus_bps = max_liquidation_bonus_bps;
+        self.pool.max_bonus_health_factor_bps = max_bonus_health_factor_bps;
+
+        emit!(UpdateLiquidationBonusEvent {
+            pool: self.pool.key(),
+            liquidation_bonus_bps,
+            max_liquidation_bonus_bps,
+            max_bonus_health_factor_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
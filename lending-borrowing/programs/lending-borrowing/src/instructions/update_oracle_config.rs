@@ -0,0 +1,43 @@
+use crate::error::Errors;
+use crate::event::UpdateOracleConfigEvent;
+use crate::helpers::oracle::validate_oracle_config;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdateOracleConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdateOracleConfig<'info> {
+    pub fn update_oracle_config(
+        &mut self,
+        max_price_age_secs: u64,
+        max_confidence_bps: u16,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        validate_oracle_config(max_price_age_secs, max_confidence_bps)?;
+
+        self.pool.max_price_age_secs = max_price_age_secs;
+        self.pool.max_confidence_bps = max_confidence_bps;
+
+        emit!(UpdateOracleConfigEvent {
+            pool: self.pool.key(),
+            max_price_age_secs,
+            max_confidence_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use crate::error::Errors;
+use crate::event::UpdatePoolCapsEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdatePoolCaps<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdatePoolCaps<'info> {
+    /// Sets the most underlying the pool accepts in deposits and lends out, in
+    /// base units. A cap of 0 leaves that side uncapped. Lowering a cap below
+    /// the current total only blocks new deposits or borrows.
+    pub fn update_pool_caps(&mut self, supply_cap: u64, borrow_cap: u64) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+
+        self.pool.supply_cap = supply_cap;
+        self.pool.borrow_cap = borrow_cap;
+
+        emit!(UpdatePoolCapsEvent {
+            pool: self.pool.key(),
+            supply_cap,
+            borrow_cap,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use crate::error::Errors;
+use crate::event::UpdatePoolFlagsEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdatePoolFlags<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdatePoolFlags<'info> {
+    /// Turns borrowing from the pool and borrowing against its dTokens on or
+    /// off. Disabling collateral works like an LTV of 0: deposits stop adding
+    /// borrowing power but keep counting towards the health factor.
+    pub fn update_pool_flags(
+        &mut self,
+        borrowing_enabled: bool,
+        collateral_enabled: bool,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+
+        self.pool.borrowing_enabled = borrowing_enabled;
+        self.pool.collateral_enabled = collateral_enabled;
+
+        emit!(UpdatePoolFlagsEvent {
+            pool: self.pool.key(),
+            borrowing_enabled,
+            collateral_enabled,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
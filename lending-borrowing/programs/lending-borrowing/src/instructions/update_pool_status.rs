@@ -0,0 +1,44 @@
+use crate::error::Errors;
+use crate::event::UpdatePoolStatusEvent;
+use crate::state::{Config, Pool, PoolStatus};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdatePoolStatus<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdatePoolStatus<'info> {
+    /// Moves the pool between `Active` and `Frozen`, or deprecates it.
+    /// Deprecation is final: liquidators may already be closing positions
+    /// that would be healthy again in an active pool.
+    pub fn update_pool_status(&mut self, status: PoolStatus) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        let previous_status = self.pool.status;
+        require!(
+            previous_status != PoolStatus::Deprecated,
+            Errors::InvalidPoolStatusTransition
+        );
+
+        self.pool.status = status;
+
+        emit!(UpdatePoolStatusEvent {
+            pool: self.pool.key(),
+            previous_status,
+            status,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
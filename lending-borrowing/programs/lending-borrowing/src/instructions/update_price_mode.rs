@@ -0,0 +1,46 @@
+use crate::error::Errors;
+use crate::event::UpdatePriceModeEvent;
+use crate::helpers::oracle::validate_price_mode;
+use crate::state::{Config, Pool, PriceMode};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdatePriceMode<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdatePriceMode<'info> {
+    /// Picks the price used for borrow limits and liquidations. Pools with a
+    /// non-Pyth primary or secondary oracle need a TWAP window for any mode but
+    /// `Spot`; changing it restarts the TWAP.
+    pub fn update_price_mode(&mut self, price_mode: PriceMode, twap_window_secs: u64) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        validate_price_mode(&self.pool, price_mode, twap_window_secs)?;
+
+        self.pool.price_mode = price_mode;
+        if self.pool.twap_window_secs != twap_window_secs {
+            self.pool.twap_window_secs = twap_window_secs;
+            self.pool.twap_price_usd_1e6 = 0;
+            self.pool.twap_last_update_ts = 0;
+        }
+
+        emit!(UpdatePriceModeEvent {
+            pool: self.pool.key(),
+            price_mode,
+            twap_window_secs,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
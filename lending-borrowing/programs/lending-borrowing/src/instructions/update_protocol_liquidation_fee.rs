@@ -0,0 +1,38 @@
+use crate::error::Errors;
+use crate::event::UpdateProtocolLiquidationFeeEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdateProtocolLiquidationFee<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+impl<'info> UpdateProtocolLiquidationFee<'info> {
+    /// Sets the share of the liquidation bonus on this pool's collateral that
+    /// is kept as protocol reserves instead of going to the liquidator.
+    pub fn update_protocol_liquidation_fee(&mut self, protocol_liquidation_fee_bps: u16) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+        require!(protocol_liquidation_fee_bps <= 10_000, Errors::InvalidProtocolFee);
+
+        self.pool.protocol_liquidation_fee_bps = protocol_liquidation_fee_bps;
+
+        emit!(UpdateProtocolLiquidationFeeEvent {
+            pool: self.pool.key(),
+            protocol_liquidation_fee_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
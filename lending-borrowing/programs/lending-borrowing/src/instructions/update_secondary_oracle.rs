@@ -0,0 +1,65 @@
+use crate::error::Errors;
+use crate::event::UpdateSecondaryOracleEvent;
+use crate::helpers::oracle::{validate_oracle_source, validate_price_mode};
+use crate::state::{Config, OracleSource, Pool};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct UpdateSecondaryOracle<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), pool.mint.as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: checked by `validate_oracle_source`; omitted when removing the
+    /// secondary oracle
+    pub secondary_oracle: Option<UncheckedAccount<'info>>,
+}
+
+impl<'info> UpdateSecondaryOracle<'info> {
+    /// Sets or, with `None`, removes the oracle the pool falls back to when its
+    /// primary price is stale. Pools valued with a smoothed price need a TWAP
+    /// window before a non-Pyth secondary can be set.
+    pub fn update_secondary_oracle(
+        &mut self,
+        secondary_oracle_source: Option<OracleSource>,
+        max_price_deviation_bps: u16,
+    ) -> Result<()> {
+        require_keys_eq!(self.admin.key(), self.config.admin.key(), Errors::NotAdmin);
+
+        let secondary_oracle = match (&secondary_oracle_source, &self.secondary_oracle) {
+            (Some(source), Some(oracle)) => {
+                require!(
+                    max_price_deviation_bps > 0 && max_price_deviation_bps <= 10_000,
+                    Errors::InvalidOracleConfig
+                );
+                require_keys_neq!(oracle.key(), self.pool.oracle, Errors::InvalidOracleConfig);
+                validate_oracle_source(source, oracle, &self.pool.feed_id)?;
+                oracle.key()
+            }
+            (None, None) => Pubkey::default(),
+            _ => return err!(Errors::InvalidOracleConfig),
+        };
+
+        self.pool.secondary_oracle_source = secondary_oracle_source;
+        self.pool.secondary_oracle = secondary_oracle;
+        self.pool.max_price_deviation_bps = max_price_deviation_bps;
+        // a non-Pyth secondary is smoothed with the pool's TWAP
+        validate_price_mode(&self.pool, self.pool.price_mode, self.pool.twap_window_secs)?;
+
+        emit!(UpdateSecondaryOracleEvent {
+            pool: self.pool.key(),
+            secondary_oracle,
+            max_price_deviation_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -1,31 +1,34 @@
 use crate::{
     error::Errors,
     event::WithdrawEvent,
-    helpers::interest::*,
-    math::{calculate_health_factor, normalize_pyth_price_to_usd_1e6},
+    helpers::{collateral::*, emode::*, interest::*, oracle::*},
+    math::{calculate_health_factor, calculate_underlying_amount},
     state::*,
 };
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{burn, transfer, Burn, Mint, Token, TokenAccount, Transfer},
+    token_2022::Token2022,
+    token_interface::{
+        burn, transfer_checked, Burn, Mint, TokenAccount, TokenInterface, TransferChecked,
+    },
 };
-#[cfg(not(feature="test-mode"))]
-use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
-#[cfg(feature="test-mode")]
-use crate::state::config::MockOracle;
 
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    #[account(address=pool.mint)]
-    pub mint: Account<'info, Mint>,
+    #[account(
+        address=pool.mint,
+        mint::token_program = token_program,
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
-        address=pool.mint_dtoken
+        address=pool.mint_dtoken,
+        mint::token_program = dtoken_program,
     )]
-    pub mint_dtoken: Account<'info, Mint>,
+    pub mint_dtoken: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
         seeds= [b"pool", config.key().as_ref(), mint.key().as_ref()],
@@ -33,7 +36,7 @@ pub struct Withdraw<'info> {
     )]
     pub pool: Account<'info, Pool>,
     #[account(
-        seeds=[b"config", config.admin.key().as_ref()],
+        seeds=[b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
         bump = config.bump,
     )]
     pub config: Account<'info, Config>,
@@ -41,57 +44,136 @@ pub struct Withdraw<'info> {
         mut, 
         associated_token::mint= pool.mint,
         associated_token::authority=pool,
+        associated_token::token_program = token_program,
     )]
-    pub vault: Account<'info, TokenAccount>,
+    pub vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut, 
         associated_token::mint=pool.mint_dtoken,
         associated_token::authority=user,
+        associated_token::token_program = dtoken_program,
     )]
-    pub user_dtoken_ata: Account<'info, TokenAccount>,
+    pub user_dtoken_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
-        mut, 
-        associated_token::mint= pool.mint,
+        init_if_needed,
+        payer = user,
+        associated_token::mint= mint,
         associated_token::authority=user,
+        associated_token::token_program = token_program,
     )]
-    pub user_token_ata: Account<'info, TokenAccount>,
+    pub user_token_ata: InterfaceAccount<'info, TokenAccount>,
+    // holders who received dTokens by transfer may not have positions yet
     #[account(
-        mut,
+        init_if_needed,
+        payer = user,
         seeds = [b"user-pool-position", user.key().as_ref(), pool.key().as_ref()],
-        bump, 
+        bump,
+        space = 8 + UserPoolPosition::INIT_SPACE,
     )]
     pub user_pool_position: Account<'info, UserPoolPosition>,
     #[account(
-        mut,
-        seeds= [b"user-position", user.key().as_ref()],
+        init_if_needed,
+        payer = user,
+        seeds= [b"user-position", config.key().as_ref(), user.key().as_ref()],
         bump,
+        space = 8 + UserPosition::INIT_SPACE,
     )]
     pub user_position: Account<'info, UserPosition>,
-    #[cfg(not(feature="test-mode"))]
-    #[account(address = pool.oracle)]
-    pub oracle: Account<'info, PriceUpdateV2>,
-    // Test mode:  mock oracle
-    #[cfg(feature = "test-mode")]
+    // required while the user is in e-mode
     #[account(
-        seeds= [b"mock-oracle", config.key().as_ref()],
-        bump= oracle.bump,
+        seeds = [b"emode-category", config.key().as_ref(), &[emode_category.id]],
+        bump = emode_category.bump,
     )]
-    pub oracle: Account<'info, MockOracle>,
-    pub token_program: Program<'info, Token>,
+    pub emode_category: Option<Account<'info, EModeCategory>>,
+    /// CHECK: validated against `pool.oracle` and parsed according to
+    /// `pool.oracle_source` by `get_price_usd_1e6`
+    #[account(address = pool.oracle)]
+    pub oracle: UncheckedAccount<'info>,
+    /// CHECK: validated against `pool.secondary_oracle` and parsed by
+    /// `get_price_usd_1e6`; only needed when the pool has a secondary oracle
+    #[account(address = pool.secondary_oracle)]
+    pub secondary_oracle: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dtoken_program: Program<'info, Token2022>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 impl<'info> Withdraw<'info> {
-    pub fn withdraw(&mut self, dtoken_amount: u64) -> Result<()> {
+    /// Holds debt left backed by isolated collateral to the rules of an
+    /// isolated borrow: it must come from pools borrowable in isolation, and
+    /// whatever is not yet booked goes against the isolated pool's debt
+    /// ceiling. `other_isolated_pools` are the isolated pools among the
+    /// user's other collateral.
+    fn isolate_remaining_debt(
+        &mut self,
+        other_isolated_pools: &[&'info AccountInfo<'info>],
+        remaining_dtokens: u64,
+    ) -> Result<()> {
+        if self.user_position.debt_value_usd == 0 {
+            return Ok(());
+        }
+        let keeps_isolated_collateral = self.pool.isolated && remaining_dtokens > 0;
+        let other_isolated_pool = match (keeps_isolated_collateral, other_isolated_pools) {
+            (false, []) => return Ok(()),
+            (true, []) => None,
+            (false, [isolated_pool_info]) => Some(*isolated_pool_info),
+            _ => return err!(Errors::MultipleIsolatedCollateral),
+        };
+        let isolated_pool_key = other_isolated_pool.map_or(self.pool.key(), |info| info.key());
+        require!(
+            self.user_position.isolation_pool == Pubkey::default()
+                || self.user_position.isolation_pool == isolated_pool_key,
+            Errors::MultipleIsolatedCollateral
+        );
+
+        let unbooked_debt_usd = self
+            .user_position
+            .debt_value_usd
+            .saturating_sub(self.user_position.isolated_debt_usd);
+        if unbooked_debt_usd == 0 {
+            return Ok(());
+        }
+        require!(
+            self.user_position.open_borrows_outside_isolation == 0,
+            Errors::IsolationModeBorrowNotAllowed
+        );
+        match other_isolated_pool {
+            None => book_isolated_debt(
+                isolated_pool_key,
+                &mut self.pool,
+                &mut self.user_position,
+                unbooked_debt_usd,
+            ),
+            Some(isolated_pool_info) => {
+                require!(isolated_pool_info.is_writable, Errors::InvalidCollateralAccounts);
+                let mut isolated_pool = Account::<Pool>::try_from(isolated_pool_info)?;
+                book_isolated_debt(
+                    isolated_pool_key,
+                    &mut isolated_pool,
+                    &mut self.user_position,
+                    unbooked_debt_usd,
+                )?;
+                isolated_pool.exit(&crate::ID)
+            }
+        }
+    }
+
+    pub fn withdraw(&mut self, dtoken_amount: u64, remaining_accounts: &'info [AccountInfo<'info>]) -> Result<()> {
         accrue_interest(&mut self.pool)?;
+        if self.user_pool_position.user == Pubkey::default() {
+            self.user_pool_position.user = self.user.key();
+            self.user_pool_position.pool = self.pool.key();
+        }
+        if self.user_position.user == Pubkey::default() {
+            self.user_position.user = self.user.key();
+        }
         update_user_borrow_state(&mut self.user_pool_position, &self.pool)?;
         update_interest_rate(&mut self.pool)?;
-        require!(self.pool.total_dtoken_supplied > 0, Errors::InvalidAmount);
-        let underlying_amount = dtoken_amount
-            .checked_mul(self.pool.total_liquidity)
-            .ok_or(Errors::MathOverflow)?
-            .checked_div(self.pool.total_dtoken_supplied)
-            .ok_or(Errors::MathOverflow)?;
+        let underlying_amount = calculate_underlying_amount(
+            dtoken_amount,
+            self.pool.total_liquidity,
+            self.pool.total_dtoken_supplied,
+        )?;
         //checks
         require!(underlying_amount > 0, Errors::InvalidAmount);
         require!(
@@ -103,7 +185,7 @@ impl<'info> Withdraw<'info> {
             Errors::InvalidAmount
         );
         //burn dtokens from the user
-        let program = self.token_program.to_account_info();
+        let program = self.dtoken_program.to_account_info();
         let accounts = Burn {
             mint: self.mint_dtoken.to_account_info(),
             from: self.user_dtoken_ata.to_account_info(),
@@ -114,8 +196,9 @@ impl<'info> Withdraw<'info> {
 
         //transfer underlyting tokens back to user
         let program = self.token_program.to_account_info();
-        let accounts = Transfer {
+        let accounts = TransferChecked {
             from: self.vault.to_account_info(),
+            mint: self.mint.to_account_info(),
             to: self.user_token_ata.to_account_info(),
             authority: self.pool.to_account_info(),
         };
@@ -129,7 +212,7 @@ impl<'info> Withdraw<'info> {
         ];
         let signer_seeds = &[&seeds[..]];
         let cpi_ctx = CpiContext::new_with_signer(program, accounts, signer_seeds);
-        transfer(cpi_ctx, underlying_amount)?;
+        transfer_checked(cpi_ctx, underlying_amount, self.mint.decimals)?;
 
         //update state
         self.pool.total_liquidity = self
@@ -142,50 +225,61 @@ impl<'info> Withdraw<'info> {
             .total_dtoken_supplied
             .checked_sub(dtoken_amount)
             .ok_or(Errors::MathOverflow)?;
-        self.user_pool_position.deposited_amount = self
-            .user_pool_position
-            .deposited_amount
-            .checked_sub(underlying_amount)
-            .ok_or(Errors::MathOverflow)?;
 
          //fetch oracle price and normalize to usd * 1e6
-        #[cfg(not(feature="test-mode"))]
-        let price_usd_1e6 = {
-            let price_update = &self.oracle;
-            let maximum_age: u64 = i64::MAX as u64;
-            let feed_id: [u8; 32] = self.pool.feed_id;
-            let price = price_update.get_price_no_older_than(&Clock::get()?, maximum_age, &feed_id)?;
-            normalize_pyth_price_to_usd_1e6(price.price, price.exponent)?
-        };
-        
-        #[cfg(feature="test-mode")]
-        let price_usd_1e6 = {
-            let mock_oracle = &self.oracle;
-            normalize_pyth_price_to_usd_1e6(mock_oracle.price, mock_oracle.expo)?
-        };
-
-        //compute collateral value
-        let mint_decimals = self.mint.decimals;
-        let scale = 10u128.pow(mint_decimals as u32);
+        let price_usd_1e6 = get_price_usd_1e6(
+            &self.oracle.to_account_info(),
+            self.secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.pool,
+        )?;
 
-        let delta_collateral_usd_1e6 = (underlying_amount as u128)
-            .checked_mul(price_usd_1e6 as u128)
-            .ok_or(Errors::MathOverflow)?
-            .checked_div(scale)
-            .ok_or(Errors::MathOverflow)? as u64;
-        //simulate post-withdraw aggregates for HF
-        let new_total_collateral_usd = self
-            .user_position
-            .collateral_value_usd
-            .checked_sub(delta_collateral_usd_1e6)
+        //value post-withdraw collateral from the remaining dToken balances
+        let remaining_dtokens = self
+            .user_dtoken_ata
+            .amount
+            .checked_sub(dtoken_amount)
+            .ok_or(Errors::MathOverflow)?;
+        let collateral_price_usd_1e6 = valuation_price_usd_1e6(
+            price_usd_1e6,
+            &self.oracle.to_account_info(),
+            self.secondary_oracle.as_ref().map(|oracle| oracle.as_ref()),
+            &self.pool,
+            PriceSide::Collateral,
+        )?;
+        let pool_collateral_usd =
+            dtoken_collateral_value_usd(&self.pool, remaining_dtokens, collateral_price_usd_1e6)?;
+        let other_collateral = remaining_collateral(
+            remaining_accounts,
+            self.user.key(),
+            self.config.key(),
+            Some(self.pool.key()),
+        )?;
+        let new_total_collateral_usd = pool_collateral_usd
+            .checked_add(other_collateral.value_usd)
             .ok_or(Errors::MathOverflow)?;
+        //e-mode debt is always inside the category, so only the collateral
+        //decides whether its threshold applies
+        let collateral_category = if remaining_dtokens > 0 {
+            shared_emode_category(other_collateral.emode_category, self.pool.emode_category)
+        } else {
+            other_collateral.emode_category
+        };
+        let liquidation_threshold_bps = match active_emode_category(
+            &self.user_position,
+            self.emode_category.as_deref(),
+            collateral_category,
+        )? {
+            Some(category) => category.liquidation_threshold_bps,
+            None => self.pool.liquidation_treshold_bps,
+        };
         let borrow_usd_1e6 = self.user_position.debt_value_usd;
         let hf = calculate_health_factor(
             new_total_collateral_usd,
             borrow_usd_1e6,
-            self.pool.liquidation_treshold_bps as u64,
+            liquidation_threshold_bps as u64,
         )?;
         require!(hf >= 10_000, Errors::BadHealthFactor);
+        self.isolate_remaining_debt(&other_collateral.isolated_pools, remaining_dtokens)?;
         //user position update
         self.user_position.collateral_value_usd = new_total_collateral_usd;
         emit!(WithdrawEvent {
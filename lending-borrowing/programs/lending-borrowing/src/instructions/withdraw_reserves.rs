@@ -0,0 +1,96 @@
+use crate::error::Errors;
+use crate::event::WithdrawReservesEvent;
+use crate::state::{Config, Pool};
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
+
+#[derive(Accounts)]
+pub struct WithdrawReserves<'info> {
+    pub fee_authority: Signer<'info>,
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        address = pool.mint,
+        mint::token_program = token_program,
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), mint.key().as_ref()],
+        bump = pool.pool_bump,
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> WithdrawReserves<'info> {
+    /// Moves `amount` of the pool's protocol reserves out of the vault. The
+    /// reserves are not backed by dTokens, so suppliers are unaffected.
+    pub fn withdraw_reserves(&mut self, amount: u64) -> Result<()> {
+        require_keys_eq!(
+            self.fee_authority.key(),
+            self.config.fee_authority,
+            Errors::NotFeeAuthority
+        );
+        require!(amount > 0, Errors::AmountZero);
+        require!(amount <= self.pool.total_reserves, Errors::InsufficientReserves);
+        require!(self.vault.amount >= amount, Errors::InsufficientLiquidity);
+
+        let config_key = self.config.key();
+        let mint_key = self.mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"pool",
+            config_key.as_ref(),
+            mint_key.as_ref(),
+            &[self.pool.pool_bump],
+        ]];
+        transfer_checked(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                TransferChecked {
+                    from: self.vault.to_account_info(),
+                    mint: self.mint.to_account_info(),
+                    to: self.destination.to_account_info(),
+                    authority: self.pool.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+            self.mint.decimals,
+        )?;
+
+        self.pool.total_reserves = self
+            .pool
+            .total_reserves
+            .checked_sub(amount)
+            .ok_or(Errors::MathOverflow)?;
+
+        emit!(WithdrawReservesEvent {
+            pool: self.pool.key(),
+            fee_authority: self.fee_authority.key(),
+            destination: self.destination.key(),
+            amount,
+            remaining_reserves: self.pool.total_reserves,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    error::Errors,
+    event::BadDebtEvent,
+    helpers::{collateral::*, interest::*},
+    state::*,
+};
+use anchor_lang::prelude::*;
+
+/// Permissionless crank that books the debt a liquidation left behind once the
+/// borrower has nothing left to seize.
+#[derive(Accounts)]
+pub struct WriteOffBadDebt<'info> {
+    #[account(
+        seeds = [b"config", config.admin.key().as_ref(), config.market_id.to_le_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub borrower: SystemAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"pool", config.key().as_ref(), debt_pool.mint.as_ref()],
+        bump = debt_pool.pool_bump,
+    )]
+    pub debt_pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        seeds = [b"user-pool-position", borrower.key().as_ref(), debt_pool.key().as_ref()],
+        bump,
+    )]
+    pub borrower_debt_position: Box<Account<'info, UserPoolPosition>>,
+    #[account(
+        mut,
+        seeds = [b"user-position", config.key().as_ref(), borrower.key().as_ref()],
+        bump,
+    )]
+    pub borrower_position: Box<Account<'info, UserPosition>>,
+    // required while the borrower has isolated debt, unless the isolated pool
+    // is the debt pool
+    #[account(
+        mut,
+        address = borrower_position.isolation_pool,
+        constraint = borrower_isolation_pool.key() != debt_pool.key() @ Errors::DuplicateIsolationPool,
+    )]
+    pub borrower_isolation_pool: Option<Box<Account<'info, Pool>>>,
+}
+
+impl<'info> WriteOffBadDebt<'info> {
+    /// Clears whatever debt the borrower still owes the debt pool once they
+    /// have no collateral left. Protocol reserves absorb it first and the rest
+    /// is socialized by shrinking the liquidity suppliers' dTokens redeem for.
+    ///
+    /// `remaining_accounts` carries a `[pool, user_dtoken_ata]` pair for every
+    /// pool of the market, so an empty borrower is proven rather than claimed.
+    pub fn write_off_bad_debt(
+        &mut self,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        accrue_interest(&mut self.debt_pool)?;
+        update_interest_rate(&mut self.debt_pool)?;
+        update_user_borrow_state(&mut self.borrower_debt_position, &self.debt_pool)?;
+
+        let bad_debt = self.borrower_debt_position.borrowed_amount;
+        require!(bad_debt > 0, Errors::NothingToLiquidate);
+        require_no_collateral(remaining_accounts, self.borrower.key(), &self.config)?;
+
+        let bad_debt_usd =
+            release_borrowed_value_usd(&mut self.borrower_debt_position, bad_debt, bad_debt)?;
+        let covered_by_reserves = bad_debt.min(self.debt_pool.total_reserves);
+        let socialized = bad_debt - covered_by_reserves;
+
+        self.borrower_debt_position.borrowed_amount = 0;
+        close_borrow(&mut self.borrower_position, &mut self.borrower_debt_position);
+        self.debt_pool.total_borrowed = self.debt_pool.total_borrowed.saturating_sub(bad_debt);
+        self.debt_pool.total_reserves -= covered_by_reserves;
+        self.debt_pool.total_liquidity = self.debt_pool.total_liquidity.saturating_sub(socialized);
+
+        self.borrower_position.debt_value_usd =
+            self.borrower_position.debt_value_usd.saturating_sub(bad_debt_usd);
+        if self.borrower_position.isolated_debt_usd > 0 {
+            let isolation_pool = if self.borrower_position.isolation_pool == self.debt_pool.key() {
+                &mut self.debt_pool
+            } else {
+                self.borrower_isolation_pool
+                    .as_mut()
+                    .ok_or(Errors::MissingIsolationPool)?
+            };
+            release_isolated_debt(isolation_pool, &mut self.borrower_position, bad_debt_usd);
+        }
+
+        emit!(BadDebtEvent {
+            borrower: self.borrower.key(),
+            debt_pool: self.debt_pool.key(),
+            bad_debt,
+            covered_by_reserves,
+            socialized,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
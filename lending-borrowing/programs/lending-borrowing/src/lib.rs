@@ -1,9 +1,12 @@
 #![allow(deprecated)]
 use anchor_lang::prelude::*;
+use spl_discriminator::SplDiscriminate;
+use spl_transfer_hook_interface::instruction::ExecuteInstruction;
 
 declare_id!("4A2DJsPrMxb1EChuCqyUAvWYUt9xHHFHSHsjW9pdvSHV");
 mod instructions;
 use instructions::*;
+use state::{OracleSource, PoolStatus, PriceMode};
 mod error;
 mod event;
 mod helpers;
@@ -13,14 +16,19 @@ mod state;
 pub mod lending_borrowing {
     use super::*;
 
-    pub fn init_config(ctx: Context<InitConfig>) -> Result<()> {
-        ctx.accounts.init_config(&ctx.bumps)?;
+    pub fn init_config(ctx: Context<InitConfig>, market_id: u16) -> Result<()> {
+        ctx.accounts.init_config(market_id, &ctx.bumps)?;
         Ok(())
     }
 
-    #[cfg(feature = "test-mode")]
+    #[allow(clippy::too_many_arguments)]
     pub fn create_pool(
         ctx: Context<CreatePool>,
+        oracle_source: OracleSource,
+        feed_id: [u8; 32],
+        dtoken_name: String,
+        dtoken_symbol: String,
+        dtoken_uri: String,
         liquidation_treshold_bps: u16,
         ltv_bps: u16,
         liquidation_bonus_bps: u16,
@@ -29,8 +37,15 @@ pub mod lending_borrowing {
         slope1: u128,
         slope2: u128,
         optimal_utilization: u128,
+        max_price_age_secs: u64,
+        max_confidence_bps: u16,
     ) -> Result<()> {
         ctx.accounts.create_pool(
+            oracle_source,
+            feed_id,
+            dtoken_name,
+            dtoken_symbol,
+            dtoken_uri,
             liquidation_treshold_bps,
             ltv_bps,
             liquidation_bonus_bps,
@@ -39,46 +54,176 @@ pub mod lending_borrowing {
             slope1,
             slope2,
             optimal_utilization,
+            max_price_age_secs,
+            max_confidence_bps,
             &ctx.bumps,
         )?;
         Ok(())
     }
-
-    #[cfg(not(feature = "test-mode"))]
-    pub fn create_pool(
-        ctx: Context<CreatePool>,
-        oracle: Pubkey,
-        feed_id: [u8; 32],
-        liquidation_treshold_bps: u16,
+    pub fn update_dtoken_metadata_uri(
+        ctx: Context<UpdateDTokenMetadata>,
+        uri: String,
+    ) -> Result<()> {
+        ctx.accounts.update_dtoken_metadata_uri(uri)?;
+        Ok(())
+    }
+    #[instruction(discriminator = ExecuteInstruction::SPL_DISCRIMINATOR_SLICE)]
+    pub fn dtoken_transfer_hook(ctx: Context<DTokenTransferHook>, _amount: u64) -> Result<()> {
+        ctx.accounts.check_dtoken_transfer()?;
+        Ok(())
+    }
+    pub fn update_oracle_config(
+        ctx: Context<UpdateOracleConfig>,
+        max_price_age_secs: u64,
+        max_confidence_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts
+            .update_oracle_config(max_price_age_secs, max_confidence_bps)?;
+        Ok(())
+    }
+    pub fn update_secondary_oracle(
+        ctx: Context<UpdateSecondaryOracle>,
+        secondary_oracle_source: Option<OracleSource>,
+        max_price_deviation_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts
+            .update_secondary_oracle(secondary_oracle_source, max_price_deviation_bps)?;
+        Ok(())
+    }
+    pub fn update_price_mode(
+        ctx: Context<UpdatePriceMode>,
+        price_mode: PriceMode,
+        twap_window_secs: u64,
+    ) -> Result<()> {
+        ctx.accounts.update_price_mode(price_mode, twap_window_secs)?;
+        Ok(())
+    }
+    pub fn update_pool_caps(
+        ctx: Context<UpdatePoolCaps>,
+        supply_cap: u64,
+        borrow_cap: u64,
+    ) -> Result<()> {
+        ctx.accounts.update_pool_caps(supply_cap, borrow_cap)?;
+        Ok(())
+    }
+    pub fn update_pool_flags(
+        ctx: Context<UpdatePoolFlags>,
+        borrowing_enabled: bool,
+        collateral_enabled: bool,
+    ) -> Result<()> {
+        ctx.accounts.update_pool_flags(borrowing_enabled, collateral_enabled)?;
+        Ok(())
+    }
+    pub fn update_pool_status(ctx: Context<UpdatePoolStatus>, status: PoolStatus) -> Result<()> {
+        ctx.accounts.update_pool_status(status)?;
+        Ok(())
+    }
+    pub fn update_isolation_mode(
+        ctx: Context<UpdateIsolationMode>,
+        isolated: bool,
+        isolation_debt_ceiling_usd: u64,
+        borrowable_in_isolation: bool,
+    ) -> Result<()> {
+        ctx.accounts.update_isolation_mode(
+            isolated,
+            isolation_debt_ceiling_usd,
+            borrowable_in_isolation,
+        )?;
+        Ok(())
+    }
+    pub fn create_emode_category(
+        ctx: Context<CreateEModeCategory>,
+        id: u8,
+        label: String,
         ltv_bps: u16,
+        liquidation_threshold_bps: u16,
         liquidation_bonus_bps: u16,
-        close_factor_bps: u16,
-        base_rate: u128,
-        slope1: u128,
-        slope2: u128,
-        optimal_utilization: u128,
     ) -> Result<()> {
-        ctx.accounts.create_pool(
-            oracle,
-            feed_id,
-            liquidation_treshold_bps,
+        ctx.accounts.create_emode_category(
+            id,
+            label,
             ltv_bps,
+            liquidation_threshold_bps,
             liquidation_bonus_bps,
-            close_factor_bps,
-            base_rate,
-            slope1,
-            slope2,
-            optimal_utilization,
             &ctx.bumps,
         )?;
         Ok(())
     }
-    pub fn borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
-        ctx.accounts.borrow(amount)?;
+    pub fn update_emode_category(
+        ctx: Context<UpdateEModeCategory>,
+        ltv_bps: u16,
+        liquidation_threshold_bps: u16,
+        liquidation_bonus_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts.update_emode_category(
+            ltv_bps,
+            liquidation_threshold_bps,
+            liquidation_bonus_bps,
+        )?;
+        Ok(())
+    }
+    pub fn set_pool_emode_category(ctx: Context<SetPoolEModeCategory>) -> Result<()> {
+        ctx.accounts.set_pool_emode_category()?;
         Ok(())
     }
-    pub fn withdraw(ctx: Context<Withdraw>, dtoken_amount: u64) -> Result<()> {
-        ctx.accounts.withdraw(dtoken_amount)?;
+    pub fn set_user_emode(ctx: Context<SetUserEMode>) -> Result<()> {
+        ctx.accounts.set_user_emode()?;
+        Ok(())
+    }
+    pub fn update_liquidation_bonus(
+        ctx: Context<UpdateLiquidationBonus>,
+        liquidation_bonus_bps: u16,
+        max_liquidation_bonus_bps: u16,
+        max_bonus_health_factor_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts.update_liquidation_bonus(
+            liquidation_bonus_bps,
+            max_liquidation_bonus_bps,
+            max_bonus_health_factor_bps,
+        )?;
+        Ok(())
+    }
+    pub fn update_protocol_liquidation_fee(
+        ctx: Context<UpdateProtocolLiquidationFee>,
+        protocol_liquidation_fee_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts
+            .update_protocol_liquidation_fee(protocol_liquidation_fee_bps)?;
+        Ok(())
+    }
+    pub fn update_close_factor(
+        ctx: Context<UpdateCloseFactor>,
+        close_factor_bps: u16,
+        critical_health_factor_bps: u16,
+        full_liquidation_debt_usd: u64,
+    ) -> Result<()> {
+        ctx.accounts.update_close_factor(
+            close_factor_bps,
+            critical_health_factor_bps,
+            full_liquidation_debt_usd,
+        )?;
+        Ok(())
+    }
+    pub fn withdraw_reserves(ctx: Context<WithdrawReserves>, amount: u64) -> Result<()> {
+        ctx.accounts.withdraw_reserves(amount)?;
+        Ok(())
+    }
+    pub fn refresh_twap(ctx: Context<RefreshTwap>) -> Result<()> {
+        ctx.accounts.refresh_twap()?;
+        Ok(())
+    }
+    pub fn borrow<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Borrow<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.borrow(amount, ctx.remaining_accounts)?;
+        Ok(())
+    }
+    pub fn withdraw<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Withdraw<'info>>,
+        dtoken_amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.withdraw(dtoken_amount, ctx.remaining_accounts)?;
         Ok(())
     }
 
@@ -86,17 +231,91 @@ pub mod lending_borrowing {
         ctx.accounts.repay(amount)?;
         Ok(())
     }
-    pub fn liquidate(ctx: Context<Liquidate>, repay_amount: u64) -> Result<()> {
-        ctx.accounts.liquidate(repay_amount)?;
+    pub fn liquidate(
+        ctx: Context<Liquidate>,
+        repay_amount: u64,
+        receive_dtokens: bool,
+    ) -> Result<()> {
+        ctx.accounts.liquidate(repay_amount, receive_dtokens, false)?;
+        Ok(())
+    }
+    pub fn liquidate_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateBatch<'info>>,
+        max_repay_amount: u64,
+        receive_dtokens: bool,
+    ) -> Result<()> {
+        ctx.accounts
+            .liquidate_batch(max_repay_amount, receive_dtokens, ctx.remaining_accounts)?;
+        Ok(())
+    }
+    pub fn flash_borrow(ctx: Context<FlashBorrow>, amount: u64) -> Result<()> {
+        ctx.accounts.flash_borrow(amount)?;
+        Ok(())
+    }
+    pub fn flash_repay(ctx: Context<FlashRepay>) -> Result<()> {
+        ctx.accounts.flash_repay()?;
+        Ok(())
+    }
+    pub fn flash_liquidate(
+        ctx: Context<FlashLiquidate>,
+        repay_amount: u64,
+        receive_dtokens: bool,
+    ) -> Result<()> {
+        ctx.accounts.flash_liquidate(repay_amount, receive_dtokens)?;
+        Ok(())
+    }
+    pub fn write_off_bad_debt<'info>(
+        ctx: Context<'_, '_, 'info, 'info, WriteOffBadDebt<'info>>,
+    ) -> Result<()> {
+        ctx.accounts.write_off_bad_debt(ctx.remaining_accounts)?;
+        Ok(())
+    }
+    pub fn update_flash_loan_fee(
+        ctx: Context<UpdateFlashLoanFee>,
+        flash_loan_fee_bps: u16,
+        flash_loan_protocol_share_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts
+            .update_flash_loan_fee(flash_loan_fee_bps, flash_loan_protocol_share_bps)?;
         Ok(())
     }
     pub fn deposit_tokens(ctx: Context<DepositTokens>, amount: u64) -> Result<()> {
         ctx.accounts.deposit_tokens(amount)?;
         Ok(())
     }
+    pub fn deposit_sol(ctx: Context<DepositSol>, lamports: u64) -> Result<()> {
+        ctx.accounts.deposit_sol(lamports)?;
+        Ok(())
+    }
+    pub fn borrow_sol<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BorrowSol<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.borrow_sol(amount, ctx.remaining_accounts)?;
+        Ok(())
+    }
+    pub fn withdraw_sol<'info>(
+        ctx: Context<'_, '_, 'info, 'info, WithdrawSol<'info>>,
+        dtoken_amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.withdraw_sol(dtoken_amount, ctx.remaining_accounts)?;
+        Ok(())
+    }
 
-    pub fn update_deposit_position(ctx: Context<UpdateDepositPosition>, amount: u64) -> Result<()> {
-        ctx.accounts.update_position(amount)?;
+    pub fn update_deposit_position<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateDepositPosition<'info>>,
+    ) -> Result<()> {
+        ctx.accounts.update_position(ctx.remaining_accounts)?;
+        Ok(())
+    }
+    #[cfg(feature = "test-mode")]
+    pub fn create_mock_oracle(
+        ctx: Context<CreateMockOracle>,
+        price: i64,
+        conf: u64,
+        expo: i32,
+    ) -> Result<()> {
+        ctx.accounts.create_mock_oracle(price, conf, expo, &ctx.bumps)?;
         Ok(())
     }
     #[cfg(feature = "test-mode")]
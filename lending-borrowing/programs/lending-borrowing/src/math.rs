@@ -2,6 +2,10 @@ use crate::error::Errors;
 use anchor_lang::prelude::*;
 /// Calculates how many dTokens to mint for a given deposit amount.
 ///
+/// dTokens are created with the same decimals as their underlying mint, so the
+/// first deposit mints 1:1 in base units and later deposits follow the pool's
+/// exchange rate.
+///
 /// # Arguments
 /// * `deposit_amount` - The amount of underlying token being deposited.
 /// * `total_liquidity` - Total underlying liquidity currently in the pool.
@@ -9,7 +13,6 @@ use anchor_lang::prelude::*;
 ///
 /// # Returns
 /// The amount of dTokens to mint.
-
 pub fn calculate_dtoken_mint_amount(
     deposit_amount: u64,
     total_liquidity: u64,
@@ -22,11 +25,67 @@ pub fn calculate_dtoken_mint_amount(
 
     let mint_amount = (deposit_amount as u128)
         .checked_mul(total_dtoken_supply as u128)
-        .unwrap()
+        .ok_or(Errors::MathOverflow)?
         .checked_div(total_liquidity as u128)
-        .unwrap();
+        .ok_or(Errors::MathOverflow)?;
+
+    u64::try_from(mint_amount).map_err(|_| error!(Errors::MathOverflow))
+}
+
+/// Calculates how much underlying token a given amount of dTokens redeems for.
+///
+/// dTokens share the decimals of their underlying mint, so the exchange rate is
+/// a plain ratio of base units and needs no decimal scaling.
+///
+/// # Arguments
+/// * `dtoken_amount` - The amount of dTokens being redeemed.
+/// * `total_liquidity` - Total underlying liquidity currently in the pool.
+/// * `total_dtoken_supply` - Total dTokens minted so far.
+///
+/// # Returns
+/// The amount of underlying token owed, rounded down.
+pub fn calculate_underlying_amount(
+    dtoken_amount: u64,
+    total_liquidity: u64,
+    total_dtoken_supply: u64,
+) -> Result<u64> {
+    require!(total_dtoken_supply > 0, Errors::InvalidAmount);
+
+    let underlying_amount = (dtoken_amount as u128)
+        .checked_mul(total_liquidity as u128)
+        .ok_or(Errors::MathOverflow)?
+        .checked_div(total_dtoken_supply as u128)
+        .ok_or(Errors::MathOverflow)?;
+
+    u64::try_from(underlying_amount).map_err(|_| error!(Errors::MathOverflow))
+}
+
+/// Calculates how many dTokens must be burned to release a given amount of
+/// underlying token, rounded up so the pool never gives out more than it burns.
+///
+/// # Arguments
+/// * `underlying_amount` - The amount of underlying token being released.
+/// * `total_liquidity` - Total underlying liquidity currently in the pool.
+/// * `total_dtoken_supply` - Total dTokens minted so far.
+///
+/// # Returns
+/// The amount of dTokens to burn.
+pub fn calculate_dtoken_burn_amount(
+    underlying_amount: u64,
+    total_liquidity: u64,
+    total_dtoken_supply: u64,
+) -> Result<u64> {
+    require!(total_liquidity > 0, Errors::InvalidAmount);
+
+    let numerator = (underlying_amount as u128)
+        .checked_mul(total_dtoken_supply as u128)
+        .ok_or(Errors::MathOverflow)?;
+    let burn_amount = numerator
+        .checked_add(total_liquidity as u128 - 1)
+        .ok_or(Errors::MathOverflow)?
+        / total_liquidity as u128;
 
-    Ok(mint_amount as u64)
+    u64::try_from(burn_amount).map_err(|_| error!(Errors::MathOverflow))
 }
 
 /// Convert a Pyth price (price, exponent) into u64 reprepsenting USD * 1e6 precision.
@@ -37,7 +96,7 @@ pub fn calculate_dtoken_mint_amount(
 pub fn normalize_pyth_price_to_usd_1e6(price: i64, expo: i32) -> Result<u64> {
     {
         // Sanity check: Pyth exponents are typically in range [-12, -6] for USD prices
-        require!(expo >= -20 && expo <= 20, Errors::InvalidOraclePrice);
+        require!((-20..=20).contains(&expo), Errors::InvalidOraclePrice);
         require!(price > 0, Errors::InvalidPrice);
 
         let price_i128 = price as i128;
@@ -92,6 +151,94 @@ pub fn calculate_health_factor(
     Ok(hf as u64)
 }
 
+/// Calculates the liquidation bonus for a position at `health_factor`.
+///
+/// The bonus grows linearly from `min_bonus_bps` at a health factor of 1.0 to
+/// `max_bonus_bps` at `max_bonus_health_factor_bps`, and stays there below it.
+/// A `max_bonus_bps` of 0 disables the scaling and always gives `min_bonus_bps`.
+///
+/// # Arguments
+/// * `health_factor` - position health factor in bps (10_000 = 1.0)
+/// * `min_bonus_bps` - bonus for a position just below 1.0
+/// * `max_bonus_bps` - bonus at or below `max_bonus_health_factor_bps`
+/// * `max_bonus_health_factor_bps` - health factor where the bonus tops out
+///
+/// # Returns
+/// * Liquidation bonus in bps
+pub fn calculate_liquidation_bonus_bps(
+    health_factor: u64,
+    min_bonus_bps: u16,
+    max_bonus_bps: u16,
+    max_bonus_health_factor_bps: u16,
+) -> Result<u64> {
+    let min_bonus = min_bonus_bps as u64;
+    if max_bonus_bps == 0 || health_factor >= 10_000 {
+        return Ok(min_bonus);
+    }
+    let max_bonus = max_bonus_bps as u64;
+    let floor = max_bonus_health_factor_bps as u64;
+    if health_factor <= floor {
+        return Ok(max_bonus);
+    }
+
+    let extra = max_bonus
+        .checked_sub(min_bonus)
+        .ok_or(Errors::MathOverflow)?
+        .checked_mul(10_000 - health_factor)
+        .ok_or(Errors::MathOverflow)?
+        / (10_000 - floor);
+    Ok(min_bonus.checked_add(extra).ok_or(Errors::MathOverflow)?)
+}
+
+/// Calculates the protocol's cut of the collateral seized in a liquidation.
+///
+/// Only the bonus portion of the seizure is shared; the part that covers the
+/// repaid debt always goes to the liquidator.
+///
+/// # Arguments
+/// * `seize_amount` - collateral seized, bonus included, in smallest units
+/// * `bonus_bps` - liquidation bonus the seizure was priced with
+/// * `protocol_fee_bps` - protocol share of the bonus (10_000 = all of it)
+///
+/// # Returns
+/// * Collateral amount kept by the protocol, rounded down
+pub fn calculate_protocol_liquidation_fee(
+    seize_amount: u64,
+    bonus_bps: u64,
+    protocol_fee_bps: u16,
+) -> Result<u64> {
+    if bonus_bps == 0 || protocol_fee_bps == 0 {
+        return Ok(0);
+    }
+    let bonus_amount = (seize_amount as u128)
+        .checked_mul(bonus_bps as u128)
+        .ok_or(Errors::MathOverflow)?
+        .checked_div(10_000 + bonus_bps as u128)
+        .ok_or(Errors::MathOverflow)?;
+    let fee = bonus_amount
+        .checked_mul(protocol_fee_bps as u128)
+        .ok_or(Errors::MathOverflow)?
+        / 10_000;
+    u64::try_from(fee).map_err(|_| error!(Errors::MathOverflow))
+}
+
+/// Calculates the fee owed on a flash loan, rounded up so a loan is never free
+/// while the fee is non-zero.
+///
+/// # Arguments
+/// * `amount` - flash loan amount in smallest units
+/// * `fee_bps` - pool flash loan fee in bps
+///
+/// # Returns
+/// * Fee in smallest units of the borrowed token
+pub fn calculate_flash_loan_fee(amount: u64, fee_bps: u16) -> Result<u64> {
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(Errors::MathOverflow)?
+        .div_ceil(10_000);
+    u64::try_from(fee).map_err(|_| error!(Errors::MathOverflow))
+}
+
 /// Calculates USD value (1e6 precision) of a borrowed token amount.
 ///
 /// # Arguments
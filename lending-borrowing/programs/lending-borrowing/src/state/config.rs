@@ -4,9 +4,11 @@ use anchor_lang::prelude::*;
 #[derive(InitSpace)]
 pub struct Pool {
     pub pool_id: u64,
+    pub oracle_source: OracleSource,
     pub oracle: Pubkey,
     pub feed_id: [u8; 32],
     pub mint: Pubkey,
+    pub decimals: u8,
     pub mint_dtoken: Pubkey,
     pub vault: Pubkey,
     pub config: Pubkey,
@@ -26,12 +28,55 @@ pub struct Pool {
     pub slope1: u128,
     pub slope2: u128,
     pub optimal_utilization: u128,
+    //oracle limits
+    pub max_price_age_secs: u64,
+    pub max_confidence_bps: u16,
+    //fallback oracle, read when the primary is stale
+    pub secondary_oracle_source: Option<OracleSource>,
+    pub secondary_oracle: Pubkey,
+    pub max_price_deviation_bps: u16,
+    //smoothed pricing for borrow limits and liquidations
+    pub price_mode: PriceMode,
+    pub twap_window_secs: u64,
+    pub twap_price_usd_1e6: u64,
+    pub twap_last_update_ts: i64,
+    //dutch-auction liquidation bonus, off while max_liquidation_bonus_bps is 0
+    pub max_liquidation_bonus_bps: u16,
+    pub max_bonus_health_factor_bps: u16,
+    //protocol share of the liquidation bonus, kept in the vault as reserves
+    pub protocol_liquidation_fee_bps: u16,
+    pub total_reserves: u64,
+    //close factor is lifted to 100% below either threshold, 0 turns each off
+    pub critical_health_factor_bps: u16,
+    pub full_liquidation_debt_usd: u64,
+    //flash loans, lent and repaid within one transaction
+    pub flash_loan_fee_bps: u16,
+    pub flash_loan_protocol_share_bps: u16,
+    pub flash_loan_amount: u64,
+    //exposure limits in underlying base units, 0 means uncapped
+    pub supply_cap: u64,
+    pub borrow_cap: u64,
+    //isolation mode: isolated collateral only backs `borrowable_in_isolation`
+    //pools, up to a USD debt ceiling
+    pub isolated: bool,
+    pub isolation_debt_ceiling_usd: u64,
+    pub isolated_debt_usd: u64,
+    pub borrowable_in_isolation: bool,
+    //e-mode category of correlated assets the pool belongs to, 0 for none
+    pub emode_category: u8,
+    //collateral-only and borrow-only listings; disabled collateral gives no
+    //borrowing power but still backs existing debt
+    pub borrowing_enabled: bool,
+    pub collateral_enabled: bool,
+    pub status: PoolStatus,
 }
 
 #[account]
 #[derive(InitSpace)]
 pub struct Config {
     pub admin: Pubkey,
+    //lets one admin run several segregated markets, part of the config seeds
+    pub market_id: u16,
     pub fee_authority: Pubkey,
     pub paused: bool,
     pub pool_count: u64,
@@ -45,6 +90,32 @@ pub struct UserPosition {
     pub collateral_value_usd: u64,
     pub debt_value_usd: u64,
     pub health_factor: u64,
+    //isolated pool backing the user's debt and how much of it, in USD
+    pub isolation_pool: Pubkey,
+    pub isolated_debt_usd: u64,
+    //e-mode category the user opted into, 0 for none, and how many pools
+    //they currently owe
+    pub emode_category: u8,
+    pub open_borrows: u16,
+    //how many of those were opened in pools not borrowable in isolation
+    pub open_borrows_outside_isolation: u16,
+}
+
+/// Risk parameters for positions made only of correlated assets, e.g. USD
+/// stablecoins or SOL liquid staking tokens. They replace the pools' own
+/// while the user has opted into the category and every pool involved
+/// belongs to it.
+#[account]
+#[derive(InitSpace)]
+pub struct EModeCategory {
+    pub config: Pubkey,
+    pub id: u8,
+    #[max_len(32)]
+    pub label: String,
+    pub ltv_bps: u16,
+    pub liquidation_threshold_bps: u16,
+    pub liquidation_bonus_bps: u16,
+    pub bump: u8,
 }
 
 #[account]
@@ -52,15 +123,56 @@ pub struct UserPosition {
 pub struct UserPoolPosition {
     pub user: Pubkey,
     pub pool: Pubkey,
-    pub deposited_amount: u64,
     pub borrowed_amount: u64,
     pub user_borrow_index: u128,
+    /// USD value this debt added to `UserPosition.debt_value_usd` when it was
+    /// borrowed, released in proportion as the debt is paid down.
+    pub borrowed_value_usd: u64,
+    /// Whether the pool was not borrowable in isolation when this debt was
+    /// opened, counted in `UserPosition.open_borrows_outside_isolation`.
+    pub outside_isolation: bool,
+}
+
+/// Where a pool reads its price from. `Pool.oracle` is the account to read
+/// for every source except `FixedPrice`, which carries its own price.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum OracleSource {
+    /// Pyth pull oracle `PriceUpdateV2` account, checked against `Pool.feed_id`.
+    PythPull,
+    /// Switchboard On-Demand pull feed account.
+    SwitchboardOnDemand,
+    /// Constant price, e.g. for stablecoins no feed covers.
+    FixedPrice { price_usd_1e6: u64 },
+    /// Program-owned `MockOracle`, only accepted in `test-mode` builds.
+    Mock,
+}
+
+/// Which price values positions in borrow-limit and liquidation checks. The
+/// smoothed price is Pyth's EMA for `PythPull` pools and the pool's own TWAP,
+/// kept by `refresh_twap`, for every other source.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum PriceMode {
+    Spot,
+    Smoothed,
+    /// Lower of spot and smoothed for collateral, higher for debt.
+    Conservative,
+}
+
+/// Lifecycle of a pool as a market is wound down.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum PoolStatus {
+    Active,
+    /// No new deposits or borrows; repay, withdraw and liquidate still work.
+    Frozen,
+    /// Frozen, charged the full-utilization rate, and every position in the
+    /// pool can be liquidated regardless of its health factor.
+    Deprecated,
 }
 
-#[cfg(feature = "test-mode")]
 #[account]
 #[derive(InitSpace)]
 pub struct MockOracle {
+    pub mint: Pubkey,
     pub price: i64,
     pub conf: u64,         //confidence interval
     pub expo: i32,         //the exponent
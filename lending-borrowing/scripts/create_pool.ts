@@ -5,6 +5,9 @@ import { LendingBorrowing } from "../target/types/lending_borrowing";
 import { getAssociatedTokenAddressSync } from "@solana/spl-token";
 
 
+// market to act on, 0 is the main market
+const MARKET_ID = Number(process.env.MARKET_ID ?? 0);
+
 (async () => {
     const provider = anchor.AnchorProvider.env();
     anchor.setProvider(provider);
@@ -13,13 +16,17 @@ import { getAssociatedTokenAddressSync } from "@solana/spl-token";
     const admin = provider.wallet;
 
     const [configPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("config"), admin.publicKey.toBuffer()],
+        [Buffer.from("config"), admin.publicKey.toBuffer(), new anchor.BN(MARKET_ID).toArrayLike(Buffer, "le", 2)],
         program.programId,
     );
 
     const oracle = new PublicKey("7UVimffxr9ow1uXYxsr4LHAcV58mLzhmwaeKvJ1pjLiE");
     const feedId = Array.from(Buffer.from("ef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d", "hex"));
 
+    const dtoken_name = "Deposit SOL";
+    const dtoken_symbol = "dSOL";
+    const dtoken_uri = "";
+
     const liquidation_treshold_bps = 8000; // 80%
     const ltv_bps = 7500; // 75%
     const liquidation_bonus_bps = 500; // 5%
@@ -28,6 +35,8 @@ import { getAssociatedTokenAddressSync } from "@solana/spl-token";
     const slope1 = new anchor.BN("30000000000000000");
     const slope2 = new anchor.BN("90000000000000000");
     const optimal_utilization = new anchor.BN("800000000000000000");
+    const max_price_age_secs = new anchor.BN(60);
+    const max_confidence_bps = 200; // 2%
 
     const underlyingMint = new PublicKey("So11111111111111111111111111111111111111112");
 
@@ -47,8 +56,11 @@ import { getAssociatedTokenAddressSync } from "@solana/spl-token";
     console.log("Vault:", vault.toBase58());
     console.log("Config PDA:", configPda.toBase58());
     const tx = await program.methods.createPool(
-        oracle,
+        { pythPull: {} },
         feedId,
+        dtoken_name,
+        dtoken_symbol,
+        dtoken_uri,
         liquidation_treshold_bps,
         ltv_bps,
         liquidation_bonus_bps,
@@ -56,7 +68,9 @@ import { getAssociatedTokenAddressSync } from "@solana/spl-token";
         base_rate,
         slope1,
         slope2,
-        optimal_utilization
+        optimal_utilization,
+        max_price_age_secs,
+        max_confidence_bps
     ).accountsPartial({
         admin: admin.publicKey,
         mint: underlyingMint,
@@ -64,6 +78,7 @@ import { getAssociatedTokenAddressSync } from "@solana/spl-token";
         dtokenMint: dtokenMint.publicKey,
         pool: poolPda,
         vault: vault,
+        oracle: oracle,
     }).signers([dtokenMint]).rpc();
 
     console.log("create_pool tx:", tx);
@@ -4,6 +4,9 @@ import { PublicKey, SystemProgram } from "@solana/web3.js";
 import { LendingBorrowing } from "../target/types/lending_borrowing";
 
 
+// market to act on, 0 is the main market
+const MARKET_ID = Number(process.env.MARKET_ID ?? 0);
+
 (async () => {
     const provider = anchor.AnchorProvider.env();
     anchor.setProvider(provider);
@@ -12,13 +15,13 @@ import { LendingBorrowing } from "../target/types/lending_borrowing";
     const admin = provider.wallet;
 
     const [configPda] = PublicKey.findProgramAddressSync(
-        [Buffer.from("config"), admin.publicKey.toBuffer()],
+        [Buffer.from("config"), admin.publicKey.toBuffer(), new anchor.BN(MARKET_ID).toArrayLike(Buffer, "le", 2)],
         program.programId,
     );
 
     console.log("Config PDA:", configPda.toBase58());
 
-    const tx = await program.methods.initConfig().accounts({
+    const tx = await program.methods.initConfig(MARKET_ID).accounts({
         initializer: admin.publicKey,
     }).rpc();
 
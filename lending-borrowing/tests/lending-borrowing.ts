@@ -1,16 +1,27 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program, BN } from "@coral-xyz/anchor";
 import { LendingBorrowing } from "../target/types/lending_borrowing";
-import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
+import { Keypair, PublicKey, SystemProgram, Transaction } from "@solana/web3.js";
 import {
   createMint,
   getAssociatedTokenAddressSync,
   createAssociatedTokenAccount,
   getOrCreateAssociatedTokenAccount,
   TOKEN_PROGRAM_ID,
+  TOKEN_2022_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID,
+  NATIVE_MINT,
   mintTo,
   getAccount,
+  transferCheckedWithTransferHook,
+  getMint,
+  getTokenMetadata,
+  ExtensionType,
+  getMintLen,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  createInitializePermanentDelegateInstruction,
+  createMintToInstruction,
 } from "@solana/spl-token";
 import { assert, util } from "chai";
 import { publicKey } from "@coral-xyz/anchor/dist/cjs/utils";
@@ -34,32 +45,287 @@ describe("lending-borrowing", () => {
   let userPoolPosition: PublicKey;
   let userPosition: PublicKey;
 
-  before(async () => {
-    // Derive PDAs
-    [configPda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("config"), admin.publicKey.toBuffer()],
+  // creates a Token-2022 mint with the given extensions initialised by `initExtensions`
+  const createToken2022Mint = async (
+    extensions: ExtensionType[],
+    initExtensions: (mint: PublicKey) => anchor.web3.TransactionInstruction[]
+  ): Promise<PublicKey> => {
+    const mintKeypair = Keypair.generate();
+    const mintLen = getMintLen(extensions);
+    const lamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    const tx = new Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: admin.publicKey,
+        newAccountPubkey: mintKeypair.publicKey,
+        space: mintLen,
+        lamports,
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      ...initExtensions(mintKeypair.publicKey),
+      createInitializeMintInstruction(mintKeypair.publicKey, 6, admin.publicKey, null, TOKEN_2022_PROGRAM_ID)
+    );
+    await provider.sendAndConfirm(tx, [mintKeypair]);
+    return mintKeypair.publicKey;
+  };
+
+  // remaining accounts that let the program value `owner`'s dTokens in `pool` as collateral
+  // the pool is writable since borrowing against isolated collateral books debt on it
+  const collateralAccounts = (owner: PublicKey, pool: PublicKey, dtokenMint: PublicKey, oracle: PublicKey) => [
+    { pubkey: pool, isSigner: false, isWritable: true },
+    { pubkey: getAssociatedTokenAddressSync(dtokenMint, owner, false, TOKEN_2022_PROGRAM_ID), isSigner: false, isWritable: false },
+    { pubkey: oracle, isSigner: false, isWritable: false },
+  ];
+
+  // little-endian u16 market id, as used in the config seeds
+  const marketSeed = (marketId: number) => new BN(marketId).toArrayLike(Buffer, "le", 2);
+
+  // creates the test-mode price feed for `mint`, $100 unless a price is given
+  const createMockOracle = async (
+    mint: PublicKey,
+    price = new BN("10000000000"),
+    conf = new BN(50000)
+  ): Promise<PublicKey> => {
+    const [oracle] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mock-oracle"), configPda.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .createMockOracle(price, conf, -8)
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        mint: mint,
+        mockOracle: oracle,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    return oracle;
+  };
+
+  type PricedPool = { mint: PublicKey; oracle: PublicKey; dtokenMint: PublicKey; pool: PublicKey; vault: PublicKey };
+
+  // creates a pool for a fresh mint priced by its own mock oracle
+  const createPricedPool = async (decimals: number, price: BN, symbol: string): Promise<PricedPool> => {
+    const mint = await createMint(provider.connection, admin.payer, admin.publicKey, null, decimals);
+    const oracle = await createMockOracle(mint, price);
+    const dtokenMint = Keypair.generate();
+    const [pool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), configPda.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    const vault = getAssociatedTokenAddressSync(mint, pool, true);
+    await program.methods
+      .createPool(
+        { mock: {} }, Array(32).fill(0),
+        `Deposit ${symbol}`, `d${symbol}`, "",
+        7000, 8000, 500, 5000,
+        new BN("10000000000000000"),
+        new BN("50000000000000000"),
+        new BN("200000000000000000"),
+        new BN("800000000000000000"),
+        new BN(3600), 200
+      )
+      .accounts({
+        admin: admin.publicKey,
+        mint: mint,
+        config: configPda,
+        dtokenMint: dtokenMint.publicKey,
+        pool: pool,
+        vault: vault,
+        oracle: oracle,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([dtokenMint])
+      .rpc();
+    return { mint, oracle, dtokenMint: dtokenMint.publicKey, pool, vault };
+  };
+
+  // mints `amount` of the pool's asset to `user` and deposits it
+  const depositInto = async (user: Keypair, asset: PricedPool, amount: BN) => {
+    const userAta = getAssociatedTokenAddressSync(asset.mint, user.publicKey);
+    await mintTo(provider.connection, admin.payer, asset.mint, userAta, admin.payer, amount.toNumber());
+    await program.methods
+      .depositTokens(amount)
+      .accounts({
+        user: user.publicKey,
+        underlyingMint: asset.mint,
+        dtokenMint: asset.dtokenMint,
+        config: configPda,
+        pool: asset.pool,
+        vault: asset.vault,
+        userAta: userAta,
+        userDtokenAta: getAssociatedTokenAddressSync(asset.dtokenMint, user.publicKey, false, TOKEN_2022_PROGRAM_ID),
+        userPoolPosition: PublicKey.findProgramAddressSync(
+          [Buffer.from("user-pool-position"), user.publicKey.toBuffer(), asset.pool.toBuffer()],
+          program.programId
+        )[0],
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+  };
+
+  // opens a `borrowAmount` loan from `debt` against `collateralAmount` deposited
+  // into `collateral`, with a lender supplying the debt liquidity and a
+  // liquidator ready with token accounts for both assets
+  const openLoan = async (collateral: PricedPool, debt: PricedPool, collateralAmount: BN, borrowAmount: BN) => {
+    const lender = Keypair.generate();
+    const borrower = Keypair.generate();
+    const liquidator = Keypair.generate();
+    for (const user of [lender, borrower, liquidator]) {
+      const sig = await provider.connection.requestAirdrop(user.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL);
+      await provider.connection.confirmTransaction(sig);
+      for (const mint of [collateral.mint, debt.mint]) {
+        await createAssociatedTokenAccount(provider.connection, user, mint, user.publicKey);
+      }
+    }
+    await depositInto(lender, debt, borrowAmount.muln(2));
+    await depositInto(borrower, collateral, collateralAmount);
+
+    const [borrowerPosition] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user-position"), configPda.toBuffer(), borrower.publicKey.toBuffer()],
+      program.programId
+    );
+    const [borrowerDebtPosition] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user-pool-position"), borrower.publicKey.toBuffer(), debt.pool.toBuffer()],
       program.programId
     );
+    const borrowerCollateralDTokenAta = getAssociatedTokenAddressSync(collateral.dtokenMint, borrower.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    await program.methods
+      .updateDepositPosition()
+      .accounts({
+        user: borrower.publicKey,
+        underlyingMint: collateral.mint,
+        config: configPda,
+        pool: collateral.pool,
+        userPosition: borrowerPosition,
+        userDtokenAta: borrowerCollateralDTokenAta,
+        oracle: collateral.oracle,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([borrower])
+      .rpc();
+
+    await program.methods
+      .borrow(borrowAmount)
+      .accounts({
+        user: borrower.publicKey,
+        underlyingMint: debt.mint,
+        pool: debt.pool,
+        config: configPda,
+        userAta: getAssociatedTokenAddressSync(debt.mint, borrower.publicKey),
+        userPoolPosition: borrowerDebtPosition,
+        userPosition: borrowerPosition,
+        vault: debt.vault,
+        oracle: debt.oracle,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(collateralAccounts(borrower.publicKey, collateral.pool, collateral.dtokenMint, collateral.oracle))
+      .signers([borrower])
+      .rpc();
+    return { borrower, liquidator, borrowerPosition, borrowerDebtPosition, borrowerCollateralDTokenAta };
+  };
+  type Loan = Awaited<ReturnType<typeof openLoan>>;
+
+  // accounts for liquidating `loan` by repaying `debt` and seizing `collateral`
+  const liquidateAccounts = (loan: Loan, collateral: PricedPool, debt: PricedPool, receiveDTokens = false) => ({
+    liquidator: loan.liquidator.publicKey,
+    borrower: loan.borrower.publicKey,
+    debtMint: debt.mint,
+    debtPool: debt.pool,
+    config: configPda,
+    borrowerDebtPosition: loan.borrowerDebtPosition,
+    borrowerPosition: loan.borrowerPosition,
+    debtPoolVault: debt.vault,
+    liquidatorDebtAta: getAssociatedTokenAddressSync(debt.mint, loan.liquidator.publicKey),
+    collateralMint: collateral.mint,
+    collateralPool: collateral.pool,
+    collateralDtokenMint: collateral.dtokenMint,
+    borrowerCollateralDtokenAta: loan.borrowerCollateralDTokenAta,
+    collateralPoolVault: collateral.vault,
+    liquidatorCollateralAta: receiveDTokens
+      ? null
+      : getAssociatedTokenAddressSync(collateral.mint, loan.liquidator.publicKey),
+    liquidatorCollateralDtokenAta: receiveDTokens
+      ? getAssociatedTokenAddressSync(collateral.dtokenMint, loan.liquidator.publicKey, false, TOKEN_2022_PROGRAM_ID)
+      : null,
+    debtOracle: debt.oracle,
+    collateralOracle: collateral.oracle,
+    debtTokenProgram: TOKEN_PROGRAM_ID,
+    collateralTokenProgram: TOKEN_PROGRAM_ID,
+    dtokenProgram: TOKEN_2022_PROGRAM_ID,
+    associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    systemProgram: SystemProgram.programId,
+  });
 
-    [mockOracle] = PublicKey.findProgramAddressSync(
-      [Buffer.from("mock-oracle"), configPda.toBuffer()],
+  // funds the loan's liquidator with `repayAmount` of the debt asset and liquidates
+  const liquidateLoan = async (
+    loan: Loan,
+    collateral: PricedPool,
+    debt: PricedPool,
+    repayAmount: BN,
+    receiveDTokens = false
+  ) => {
+    const liquidatorDebtAta = getAssociatedTokenAddressSync(debt.mint, loan.liquidator.publicKey);
+    await mintTo(provider.connection, admin.payer, debt.mint, liquidatorDebtAta, admin.payer, repayAmount.toNumber());
+    await program.methods
+      .liquidate(repayAmount, receiveDTokens)
+      .accounts(liquidateAccounts(loan, collateral, debt, receiveDTokens))
+      .signers([loan.liquidator])
+      .rpc();
+  };
+
+  // accounts for writing off what `loan` still owes `debt`
+  const writeOffAccounts = (loan: Loan, debt: PricedPool) => ({
+    config: configPda,
+    borrower: loan.borrower.publicKey,
+    debtPool: debt.pool,
+    borrowerDebtPosition: loan.borrowerDebtPosition,
+    borrowerPosition: loan.borrowerPosition,
+    borrowerIsolationPool: null,
+  });
+
+  // [pool, user_dtoken_ata] pairs proving `owner` holds no dTokens in `pools`
+  const marketPoolAccounts = (owner: PublicKey, pools: PricedPool[]) =>
+    pools.flatMap(({ pool, dtokenMint }) => [
+      { pubkey: pool, isSigner: false, isWritable: false },
+      { pubkey: getAssociatedTokenAddressSync(dtokenMint, owner, false, TOKEN_2022_PROGRAM_ID), isSigner: false, isWritable: false },
+    ]);
+
+  before(async () => {
+    // Derive PDAs
+    [configPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("config"), admin.publicKey.toBuffer(), marketSeed(0)],
       program.programId
     );
 
     // Initialize config
     await program.methods
-      .initConfig()
+      .initConfig(0)
       .accounts({
         initializer: admin.publicKey,
         config: configPda,
-        mockOracle: mockOracle,
         systemProgram: SystemProgram.programId,
       })
       .rpc();
     console.log(" Config initialized");
 
+    // Create pool
+    mintX = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    dTokenMint = Keypair.generate();
+
     // Fetch and verify accounts
     const configAccount = await program.account.config.fetch(configPda);
+    mockOracle = await createMockOracle(mintX);
     const mockOracleAccount = await program.account.mockOracle.fetch(mockOracle);
 
     console.log("Config:", configAccount);
@@ -68,12 +334,9 @@ describe("lending-borrowing", () => {
     // Verify mock oracle
     assert.equal(mockOracleAccount.price.toString(), "10000000000");
     assert.equal(mockOracleAccount.expo, -8);
+    assert.equal(mockOracleAccount.mint.toBase58(), mintX.toBase58());
     console.log(" Mock oracle verified");
 
-    // Create pool
-    mintX = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
-    dTokenMint = Keypair.generate();
-
     [poolPda] = PublicKey.findProgramAddressSync(
       [Buffer.from("pool"), configPda.toBuffer(), mintX.toBuffer()],
       program.programId
@@ -82,11 +345,14 @@ describe("lending-borrowing", () => {
 
     await program.methods
       .createPool(
+        { mock: {} }, Array(32).fill(0),
+        "Deposit X", "dX", "https://example.com/dx.json",
         7000, 8000, 500, 5000,
         new BN("10000000000000000"),
         new BN("50000000000000000"),
         new BN("200000000000000000"),
-        new BN("800000000000000000")
+        new BN("800000000000000000"),
+        new BN(3600), 200
       )
       .accounts({
         admin: admin.publicKey,
@@ -95,10 +361,11 @@ describe("lending-borrowing", () => {
         dtokenMint: dTokenMint.publicKey,
         pool: poolPda,
         vault: vaultAta,
-        mockOracle: mockOracle,
+        oracle: mockOracle,
         systemProgram: SystemProgram.programId,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
       })
       .signers([dTokenMint])
       .rpc();
@@ -119,7 +386,7 @@ describe("lending-borrowing", () => {
       program.programId,
     );
     [userPosition] = PublicKey.findProgramAddressSync(
-      [Buffer.from("user-position"), admin.publicKey.toBuffer()],
+      [Buffer.from("user-position"), configPda.toBuffer(), admin.publicKey.toBuffer()],
       program.programId
     );
   });
@@ -135,6 +402,42 @@ describe("lending-borrowing", () => {
     console.log(" Pool configured correctly");
   });
 
+  it("attaches dToken metadata and lets the admin update the URI", async () => {
+    const metadata = await getTokenMetadata(
+      provider.connection,
+      dTokenMint.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.equal(metadata.name, "Deposit X");
+    assert.equal(metadata.symbol, "dX");
+    assert.equal(metadata.uri, "https://example.com/dx.json");
+    assert.equal(metadata.updateAuthority.toBase58(), poolPda.toBase58());
+    console.log(" dToken metadata:", metadata.name, metadata.symbol, metadata.uri);
+
+    const newUri = "https://example.com/metadata/deposit-x-v2.json";
+    await program.methods
+      .updateDtokenMetadataUri(newUri)
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        pool: poolPda,
+        dtokenMint: dTokenMint.publicKey,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const updated = await getTokenMetadata(
+      provider.connection,
+      dTokenMint.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.equal(updated.uri, newUri);
+    console.log(" dToken metadata URI updated");
+  });
+
   it("deposits tokens and updates position", async () => {
     const depositAmount = new BN(1_000_000_000);
 
@@ -150,13 +453,13 @@ describe("lending-borrowing", () => {
     console.log(" Minted tokens");
 
     // Derive PDAs
-    const userDTokenAta = getAssociatedTokenAddressSync(dTokenMint.publicKey, admin.publicKey);
+    const userDTokenAta = getAssociatedTokenAddressSync(dTokenMint.publicKey, admin.publicKey, false, TOKEN_2022_PROGRAM_ID);
     const [userPoolPosition] = PublicKey.findProgramAddressSync(
       [Buffer.from("user-pool-position"), admin.publicKey.toBuffer(), poolPda.toBuffer()],
       program.programId
     );
     const [userPosition] = PublicKey.findProgramAddressSync(
-      [Buffer.from("user-position"), admin.publicKey.toBuffer()],
+      [Buffer.from("user-position"), configPda.toBuffer(), admin.publicKey.toBuffer()],
       program.programId
     );
 
@@ -175,6 +478,7 @@ describe("lending-borrowing", () => {
         userPoolPosition: userPoolPosition,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       })
       .rpc();
@@ -182,15 +486,16 @@ describe("lending-borrowing", () => {
 
     // Update position
     await program.methods
-      .updateDepositPosition(depositAmount)
+      .updateDepositPosition()
       .accounts({
         user: admin.publicKey,
         underlyingMint: mintX,
         config: configPda,
         pool: poolPda,
         userPosition: userPosition,
-        userPoolPosition: userPoolPosition,
+        userDtokenAta: userDTokenAta,
         oracle: mockOracle,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
       .rpc();
@@ -236,8 +541,10 @@ describe("lending-borrowing", () => {
         vault: vaultAta,
         oracle: mockOracle,
         tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(collateralAccounts(admin.publicKey, poolPda, dTokenMint.publicKey, mockOracle))
       .rpc();
     console.log(" Borrow executed");
 
@@ -311,8 +618,10 @@ describe("lending-borrowing", () => {
           vault: vaultAta,
           oracle: mockOracle,
           tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
         })
+        .remainingAccounts(collateralAccounts(admin.publicKey, poolPda, dTokenMint.publicKey, mockOracle))
         .rpc();
 
       assert.fail("Should have failed due to LTV limit");
@@ -397,7 +706,6 @@ describe("lending-borrowing", () => {
         vault: vaultAta,
         userPosition: userPosition,
         userPoolPosition: userPoolPosition,
-        oracle: mockOracle,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .rpc();
@@ -534,7 +842,6 @@ describe("lending-borrowing", () => {
           vault: vaultAta,
           userPosition: userPosition,
           userPoolPosition: userPoolPosition,
-          oracle: mockOracle,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
         .rpc();
@@ -572,13 +879,12 @@ describe("lending-borrowing", () => {
     const poolBefore = await program.account.pool.fetch(poolPda);
     const userPoolPositionBefore = await program.account.userPoolPosition.fetch(userPoolPosition);
     const userPositionBefore = await program.account.userPosition.fetch(userPosition);
-    const userDTokenAta = getAssociatedTokenAddressSync(dTokenMint.publicKey, admin.publicKey);
-    const userDTokenBefore = await getAccount(provider.connection, userDTokenAta);
+    const userDTokenAta = getAssociatedTokenAddressSync(dTokenMint.publicKey, admin.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const userDTokenBefore = await getAccount(provider.connection, userDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
     const userAtaBefore = await getAccount(provider.connection, userAta);
     const vaultBefore = await getAccount(provider.connection, vaultAta);
 
     console.log("Initial state before withdrawal:");
-    console.log("  User deposited amount:", userPoolPositionBefore.depositedAmount.toString());
     console.log("  User borrowed amount:", userPoolPositionBefore.borrowedAmount.toString());
     console.log("  User collateral value USD:", userPositionBefore.collateralValueUsd.toString());
     console.log("  User debt value USD:", userPositionBefore.debtValueUsd.toString());
@@ -596,7 +902,6 @@ describe("lending-borrowing", () => {
     );
 
     //calculate how much dTokens can user withdraw
-    const depositedAmount = userPoolPositionBefore.depositedAmount.toNumber();
     const totalLiquidity = poolBefore.totalLiquidity.toNumber();
     const totalDTokenSupplied = poolBefore.totalDtokenSupplied.toNumber();
 
@@ -628,6 +933,7 @@ describe("lending-borrowing", () => {
         userPosition: userPosition,
         oracle: mockOracle,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
@@ -636,14 +942,12 @@ describe("lending-borrowing", () => {
 
     // get state after withdrawal
     const poolAfter = await program.account.pool.fetch(poolPda);
-    const userPoolPositionAfter = await program.account.userPoolPosition.fetch(userPoolPosition);
     const userPositionAfter = await program.account.userPosition.fetch(userPosition);
-    const userDTokenAfter = await getAccount(provider.connection, userDTokenAta);
+    const userDTokenAfter = await getAccount(provider.connection, userDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
     const userAtaAfter = await getAccount(provider.connection, userAta);
     const vaultAfter = await getAccount(provider.connection, vaultAta);
 
     console.log("\nAfter withdrawal:");
-    console.log("  User deposited amount:", userPoolPositionAfter.depositedAmount.toString());
     console.log("  User collateral value USD:", userPositionAfter.collateralValueUsd.toString());
     console.log("  User dToken balance:", userDTokenAfter.amount.toString());
     console.log("  User token ATA balance:", userAtaAfter.amount.toString());
@@ -700,17 +1004,7 @@ describe("lending-borrowing", () => {
       "Pool dToken supply should decrease by burned amount"
     );
 
-    const depositDecrease = userPoolPositionBefore.depositedAmount.toNumber() -
-      userPoolPositionAfter.depositedAmount.toNumber();
-
     console.log("\nUser position changes:");
-    console.log("  Deposited amount decreased by:", depositDecrease);
-
-    assert.equal(
-      depositDecrease,
-      Number(tokensReceived),
-      "User deposited amount should decrease by withdrawn amount"
-    );
     const collateralDecrease = userPositionBefore.collateralValueUsd.toNumber() -
       userPositionAfter.collateralValueUsd.toNumber();
 
@@ -758,11 +1052,11 @@ describe("lending-borrowing", () => {
     );
 
     const [borrowerPosition] = PublicKey.findProgramAddressSync(
-      [Buffer.from("user-position"), borrower.publicKey.toBuffer()],
+      [Buffer.from("user-position"), configPda.toBuffer(), borrower.publicKey.toBuffer()],
       program.programId
     );
 
-    const borrowerDTokenAta = getAssociatedTokenAddressSync(dTokenMint.publicKey, borrower.publicKey);
+    const borrowerDTokenAta = getAssociatedTokenAddressSync(dTokenMint.publicKey, borrower.publicKey, false, TOKEN_2022_PROGRAM_ID);
 
     console.log(" New borrower created:", borrower.publicKey.toBase58());
 
@@ -794,6 +1088,7 @@ describe("lending-borrowing", () => {
         userPoolPosition: borrowerPoolPosition,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       })
       .signers([borrower])
@@ -801,15 +1096,16 @@ describe("lending-borrowing", () => {
 
     // update position
     await program.methods
-      .updateDepositPosition(depositAmount)
+      .updateDepositPosition()
       .accounts({
         user: borrower.publicKey,
         underlyingMint: mintX,
         config: configPda,
         pool: poolPda,
         userPosition: borrowerPosition,
-        userPoolPosition: borrowerPoolPosition,
+        userDtokenAta: borrowerDTokenAta,
         oracle: mockOracle,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
       .signers([borrower])
@@ -833,8 +1129,10 @@ describe("lending-borrowing", () => {
         vault: vaultAta,
         oracle: mockOracle,
         tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(collateralAccounts(borrower.publicKey, poolPda, dTokenMint.publicKey, mockOracle))
       .signers([borrower])
       .rpc();
 
@@ -849,7 +1147,6 @@ describe("lending-borrowing", () => {
     console.log("  Collateral value USD:", borrowerPositionBefore.collateralValueUsd.toString());
     console.log("  Debt value USD:", borrowerPositionBefore.debtValueUsd.toString());
     console.log("  Borrowed amount (tokens):", borrowerPoolPositionBefore.borrowedAmount.toString());
-    console.log("  Deposited amount (tokens):", borrowerPoolPositionBefore.depositedAmount.toString());
     console.log("  Liquidation threshold:", poolBefore.liquidationTresholdBps, "bps");
 
     // calculate initial HF
@@ -942,9 +1239,10 @@ describe("lending-borrowing", () => {
 
 
     const liquidatorAtaBefore = await getAccount(provider.connection, liquidatorDebtAta);
+    const borrowerDTokenBefore = await getAccount(provider.connection, borrowerDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
 
     await program.methods
-      .liquidate(repayAmount)
+      .liquidate(repayAmount, false)
       .accounts({
         liquidator: liquidator.publicKey,
         borrower: borrower.publicKey,
@@ -957,12 +1255,15 @@ describe("lending-borrowing", () => {
         liquidatorDebtAta: liquidatorDebtAta,
         collateralMint: mintX,
         collateralPool: poolPda,
-        borrowerCollateralPosition: borrowerPoolPosition,
+        collateralDtokenMint: dTokenMint.publicKey,
+        borrowerCollateralDtokenAta: borrowerDTokenAta,
         collateralPoolVault: vaultAta,
         liquidatorCollateralAta: liquidatorCollateralAta,
         debtOracle: mockOracle,
         collateralOracle: mockOracle,
-        tokenProgram: TOKEN_PROGRAM_ID,
+        debtTokenProgram: TOKEN_PROGRAM_ID,
+        collateralTokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
@@ -1003,6 +1304,2443 @@ describe("lending-borrowing", () => {
       "Net change should match expected profit (with rounding tolerance)"
     );
 
+    // seized collateral is taken out of the borrower's dTokens
+    const borrowerDTokenAfter = await getAccount(provider.connection, borrowerDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+    console.log("  Borrower dTokens burned:", (borrowerDTokenBefore.amount - borrowerDTokenAfter.amount).toString());
+    assert.ok(
+      borrowerDTokenAfter.amount < borrowerDTokenBefore.amount,
+      "Borrower dTokens should be burned for the seized collateral"
+    );
+
     console.log("\n All liquidation checks passed!!");
   });
-});
\ No newline at end of file
+
+  for (const decimals of [0, 6, 8, 9]) {
+    it(`mints ${decimals}-decimal dTokens and redeems them at the pool exchange rate`, async () => {
+      console.log(`\nTESTING ${decimals}-DECIMAL POOL`);
+
+      const assetMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, decimals);
+      const assetDTokenMint = Keypair.generate();
+      const assetOracle = await createMockOracle(assetMint);
+      const [assetPool] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pool"), configPda.toBuffer(), assetMint.toBuffer()],
+        program.programId
+      );
+      const assetVault = getAssociatedTokenAddressSync(assetMint, assetPool, true);
+
+      await program.methods
+        .createPool(
+          { mock: {} }, Array(32).fill(0),
+          `Deposit ${decimals}dec`, `d${decimals}DEC`, "",
+          7000, 8000, 500, 5000,
+          new BN("10000000000000000"),
+          new BN("50000000000000000"),
+          new BN("200000000000000000"),
+          new BN("800000000000000000"),
+          new BN(3600), 200
+        )
+        .accounts({
+          admin: admin.publicKey,
+          mint: assetMint,
+          config: configPda,
+          dtokenMint: assetDTokenMint.publicKey,
+          pool: assetPool,
+          vault: assetVault,
+          oracle: assetOracle,
+          systemProgram: SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([assetDTokenMint])
+        .rpc();
+
+      const dTokenMintAccount = await getMint(provider.connection, assetDTokenMint.publicKey, undefined, TOKEN_2022_PROGRAM_ID);
+      assert.equal(dTokenMintAccount.decimals, decimals, "dToken decimals should match the underlying mint");
+      console.log(" dToken mint created with", dTokenMintAccount.decimals, "decimals");
+
+      // fresh depositor so the position only reflects this pool
+      const depositor = Keypair.generate();
+      const airdropSig = await provider.connection.requestAirdrop(
+        depositor.publicKey,
+        2 * anchor.web3.LAMPORTS_PER_SOL
+      );
+      await provider.connection.confirmTransaction(airdropSig);
+
+      const depositorAta = await createAssociatedTokenAccount(
+        provider.connection,
+        depositor,
+        assetMint,
+        depositor.publicKey
+      );
+      const depositorDTokenAta = getAssociatedTokenAddressSync(assetDTokenMint.publicKey, depositor.publicKey, false, TOKEN_2022_PROGRAM_ID);
+      const [depositorPoolPosition] = PublicKey.findProgramAddressSync(
+        [Buffer.from("user-pool-position"), depositor.publicKey.toBuffer(), assetPool.toBuffer()],
+        program.programId
+      );
+      const [depositorPosition] = PublicKey.findProgramAddressSync(
+        [Buffer.from("user-position"), configPda.toBuffer(), depositor.publicKey.toBuffer()],
+        program.programId
+      );
+
+      // 1000 whole tokens in base units
+      const depositAmount = new BN(1000).mul(new BN(10).pow(new BN(decimals)));
+      await mintTo(
+        provider.connection,
+        admin.payer,
+        assetMint,
+        depositorAta,
+        admin.payer,
+        BigInt(depositAmount.toString())
+      );
+
+      await program.methods
+        .depositTokens(depositAmount)
+        .accounts({
+          user: depositor.publicKey,
+          underlyingMint: assetMint,
+          dtokenMint: assetDTokenMint.publicKey,
+          config: configPda,
+          pool: assetPool,
+          vault: assetVault,
+          userAta: depositorAta,
+          userDtokenAta: depositorDTokenAta,
+          userPoolPosition: depositorPoolPosition,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([depositor])
+        .rpc();
+
+      const dTokensAfterDeposit = await getAccount(provider.connection, depositorDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+      assert.equal(
+        dTokensAfterDeposit.amount.toString(),
+        depositAmount.toString(),
+        "First deposit should mint dTokens 1:1 in base units"
+      );
+      console.log(" Deposited", depositAmount.toString(), "base units, minted", dTokensAfterDeposit.amount.toString(), "dTokens");
+
+      // refresh the mock oracle timestamp so the position update sees a fresh price
+      await program.methods
+        .updateMockOracle(new BN("10000000000"), -8)
+        .accounts({
+          admin: admin.publicKey,
+          mockOracle: assetOracle,
+          config: configPda,
+        })
+        .rpc();
+
+      await program.methods
+        .updateDepositPosition()
+        .accounts({
+          user: depositor.publicKey,
+          underlyingMint: assetMint,
+          config: configPda,
+          pool: assetPool,
+          userPosition: depositorPosition,
+          userDtokenAta: depositorDTokenAta,
+          oracle: assetOracle,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([depositor])
+        .rpc();
+
+      const depositorPositionAccount = await program.account.userPosition.fetch(depositorPosition);
+      assert.equal(
+        depositorPositionAccount.collateralValueUsd.toString(),
+        "100000000000",
+        "1000 tokens at $100 should be worth $100k regardless of decimals"
+      );
+
+      const withdrawDTokenAmount = depositAmount.divn(2);
+      const depositorAtaBefore = await getAccount(provider.connection, depositorAta);
+
+      await program.methods
+        .withdraw(withdrawDTokenAmount)
+        .accounts({
+          user: depositor.publicKey,
+          mint: assetMint,
+          mintDtoken: assetDTokenMint.publicKey,
+          pool: assetPool,
+          config: configPda,
+          vault: assetVault,
+          userDtokenAta: depositorDTokenAta,
+          userTokenAta: depositorAta,
+          userPoolPosition: depositorPoolPosition,
+          userPosition: depositorPosition,
+          oracle: assetOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([depositor])
+        .rpc();
+
+      const depositorAtaAfter = await getAccount(provider.connection, depositorAta);
+      const received = BigInt(depositorAtaAfter.amount.toString()) - BigInt(depositorAtaBefore.amount.toString());
+      assert.equal(
+        received.toString(),
+        withdrawDTokenAmount.toString(),
+        "Redeeming half the dTokens should return half the deposit"
+      );
+
+      const depositorPositionAfter = await program.account.userPosition.fetch(depositorPosition);
+      assert.equal(
+        depositorPositionAfter.collateralValueUsd.toString(),
+        "50000000000",
+        "Collateral value should halve after redeeming half the dTokens"
+      );
+
+      console.log(` All ${decimals}-decimal checks passed`);
+    });
+  }
+
+  it("credits only the amount received for transfer-fee Token-2022 assets", async () => {
+    console.log("\nTESTING TRANSFER-FEE ASSET");
+
+    // 1% transfer fee
+    const feeMint = await createToken2022Mint([ExtensionType.TransferFeeConfig], (mint) => [
+      createInitializeTransferFeeConfigInstruction(
+        mint,
+        admin.publicKey,
+        admin.publicKey,
+        100,
+        BigInt(1_000_000_000_000),
+        TOKEN_2022_PROGRAM_ID
+      ),
+    ]);
+    const feeDTokenMint = Keypair.generate();
+    const feeOracle = await createMockOracle(feeMint);
+    const [feePool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), configPda.toBuffer(), feeMint.toBuffer()],
+      program.programId
+    );
+    const feeVault = getAssociatedTokenAddressSync(feeMint, feePool, true, TOKEN_2022_PROGRAM_ID);
+
+    await program.methods
+      .createPool(
+        { mock: {} }, Array(32).fill(0),
+        "Deposit Fee", "dFEE", "",
+        7000, 8000, 500, 5000,
+        new BN("10000000000000000"),
+        new BN("50000000000000000"),
+        new BN("200000000000000000"),
+        new BN("800000000000000000"),
+        new BN(3600), 200
+      )
+      .accounts({
+        admin: admin.publicKey,
+        mint: feeMint,
+        config: configPda,
+        dtokenMint: feeDTokenMint.publicKey,
+        pool: feePool,
+        vault: feeVault,
+        oracle: feeOracle,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([feeDTokenMint])
+      .rpc();
+    console.log(" Transfer-fee pool created");
+
+    const adminFeeAta = await createAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      feeMint,
+      admin.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const depositAmount = 1_000_000_000;
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      feeMint,
+      adminFeeAta,
+      admin.payer,
+      depositAmount,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const adminFeeDTokenAta = getAssociatedTokenAddressSync(feeDTokenMint.publicKey, admin.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const [adminFeePoolPosition] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user-pool-position"), admin.publicKey.toBuffer(), feePool.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .depositTokens(new BN(depositAmount))
+      .accounts({
+        user: admin.publicKey,
+        underlyingMint: feeMint,
+        dtokenMint: feeDTokenMint.publicKey,
+        config: configPda,
+        pool: feePool,
+        vault: feeVault,
+        userAta: adminFeeAta,
+        userDtokenAta: adminFeeDTokenAta,
+        userPoolPosition: adminFeePoolPosition,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const expectedReceived = depositAmount - depositAmount / 100;
+    const vaultAccount = await getAccount(provider.connection, feeVault, undefined, TOKEN_2022_PROGRAM_ID);
+    const dTokenAccount = await getAccount(provider.connection, adminFeeDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+    const feePoolAccount = await program.account.pool.fetch(feePool);
+
+    assert.equal(vaultAccount.amount.toString(), expectedReceived.toString(), "Vault should hold the post-fee amount");
+    assert.equal(dTokenAccount.amount.toString(), expectedReceived.toString(), "dTokens should be minted for the post-fee amount");
+    assert.equal(feePoolAccount.totalLiquidity.toString(), expectedReceived.toString(), "Pool liquidity should track the post-fee amount");
+    console.log(" Deposit of", depositAmount, "credited", expectedReceived, "after the transfer fee");
+
+    // flash repayments are grossed up so the vault nets the full amount owed
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      feeMint,
+      adminFeeAta,
+      admin.payer,
+      10_000_000,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const flashAccounts = {
+      user: admin.publicKey,
+      config: configPda,
+      mint: feeMint,
+      pool: feePool,
+      vault: feeVault,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    };
+    const flashBorrowIx = await program.methods
+      .flashBorrow(new BN(100_000_000))
+      .accounts({ ...flashAccounts, destination: adminFeeAta, instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY })
+      .instruction();
+    const flashRepayIx = await program.methods
+      .flashRepay()
+      .accounts({ ...flashAccounts, source: adminFeeAta })
+      .instruction();
+    await provider.sendAndConfirm(new Transaction().add(flashBorrowIx, flashRepayIx));
+
+    const vaultAfterFlash = await getAccount(provider.connection, feeVault, undefined, TOKEN_2022_PROGRAM_ID);
+    const feePoolAfterFlash = await program.account.pool.fetch(feePool);
+    assert.ok(vaultAfterFlash.amount >= vaultAccount.amount, "Vault should get the whole flash loan back");
+    assert.equal(feePoolAfterFlash.flashLoanAmount.toNumber(), 0, "Flash loan should be settled");
+    console.log(" Flash loan repaid in full despite the transfer fee");
+  });
+
+  it("moves collateral with the dTokens when they are transferred", async () => {
+    console.log("\nTESTING DTOKEN TRANSFERS");
+
+    const assetMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    const assetDTokenMint = Keypair.generate();
+    const assetOracle = await createMockOracle(assetMint);
+    const [assetPool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), configPda.toBuffer(), assetMint.toBuffer()],
+      program.programId
+    );
+    const assetVault = getAssociatedTokenAddressSync(assetMint, assetPool, true);
+
+    await program.methods
+      .createPool(
+        { mock: {} }, Array(32).fill(0),
+        "Deposit Transfer", "dTRF", "",
+        7000, 8000, 500, 5000,
+        new BN("10000000000000000"),
+        new BN("50000000000000000"),
+        new BN("200000000000000000"),
+        new BN("800000000000000000"),
+        new BN(3600), 200
+      )
+      .accounts({
+        admin: admin.publicKey,
+        mint: assetMint,
+        config: configPda,
+        dtokenMint: assetDTokenMint.publicKey,
+        pool: assetPool,
+        vault: assetVault,
+        oracle: assetOracle,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([assetDTokenMint])
+      .rpc();
+
+    const sender = Keypair.generate();
+    const receiver = Keypair.generate();
+    for (const user of [sender, receiver]) {
+      const sig = await provider.connection.requestAirdrop(user.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL);
+      await provider.connection.confirmTransaction(sig);
+    }
+    const senderAta = await createAssociatedTokenAccount(provider.connection, sender, assetMint, sender.publicKey);
+    const receiverAta = await createAssociatedTokenAccount(provider.connection, receiver, assetMint, receiver.publicKey);
+    const senderDTokenAta = getAssociatedTokenAddressSync(assetDTokenMint.publicKey, sender.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const receiverDTokenAta = await createAssociatedTokenAccount(
+      provider.connection,
+      receiver,
+      assetDTokenMint.publicKey,
+      receiver.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const positionsOf = (user: PublicKey) => [
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("user-pool-position"), user.toBuffer(), assetPool.toBuffer()],
+        program.programId
+      )[0],
+      PublicKey.findProgramAddressSync([Buffer.from("user-position"), configPda.toBuffer(), user.toBuffer()], program.programId)[0],
+    ];
+    const [senderPoolPosition, senderPosition] = positionsOf(sender.publicKey);
+    const [receiverPoolPosition, receiverPosition] = positionsOf(receiver.publicKey);
+
+    // sender deposits 1000 tokens and registers $100k of collateral
+    const depositAmount = new BN(1_000_000_000);
+    await mintTo(provider.connection, admin.payer, assetMint, senderAta, admin.payer, depositAmount.toNumber());
+    await program.methods
+      .depositTokens(depositAmount)
+      .accounts({
+        user: sender.publicKey,
+        underlyingMint: assetMint,
+        dtokenMint: assetDTokenMint.publicKey,
+        config: configPda,
+        pool: assetPool,
+        vault: assetVault,
+        userAta: senderAta,
+        userDtokenAta: senderDTokenAta,
+        userPoolPosition: senderPoolPosition,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([sender])
+      .rpc();
+
+    await program.methods
+      .updateMockOracle(new BN("10000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: assetOracle,
+        config: configPda,
+      })
+      .rpc();
+
+    const refreshPosition = (user: Keypair, userPosition: PublicKey, userDtokenAta: PublicKey) =>
+      program.methods
+        .updateDepositPosition()
+        .accounts({
+          user: user.publicKey,
+          underlyingMint: assetMint,
+          config: configPda,
+          pool: assetPool,
+          userPosition: userPosition,
+          userDtokenAta: userDtokenAta,
+          oracle: assetOracle,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+    await refreshPosition(sender, senderPosition, senderDTokenAta);
+
+    // sender hands 600 dTokens to the receiver, through the dToken transfer hook
+    await transferCheckedWithTransferHook(
+      provider.connection,
+      sender,
+      senderDTokenAta,
+      assetDTokenMint.publicKey,
+      receiverDTokenAta,
+      sender,
+      600_000_000,
+      6,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    console.log(" Transferred 600 dTokens to the receiver");
+
+    // the cached $100k no longer lets the sender borrow against dTokens they gave away
+    const borrowAccounts = (user: Keypair, userAta: PublicKey, poolPosition: PublicKey, position: PublicKey) => ({
+      user: user.publicKey,
+      underlyingMint: assetMint,
+      pool: assetPool,
+      config: configPda,
+      userAta: userAta,
+      userPoolPosition: poolPosition,
+      userPosition: position,
+      vault: assetVault,
+      oracle: assetOracle,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    });
+    try {
+      await program.methods
+        .borrow(new BN(500_000_000))
+        .accounts(borrowAccounts(sender, senderAta, senderPoolPosition, senderPosition))
+        .remainingAccounts(collateralAccounts(sender.publicKey, assetPool, assetDTokenMint.publicKey, assetOracle))
+        .signers([sender])
+        .rpc();
+      assert.fail("Sender should not borrow against transferred dTokens");
+    } catch (error) {
+      assert.ok(error.toString().includes("ExceedsLTV"));
+      console.log(" Sender can no longer borrow against transferred dTokens");
+    }
+
+    await refreshPosition(sender, senderPosition, senderDTokenAta);
+    const senderPositionAccount = await program.account.userPosition.fetch(senderPosition);
+    assert.equal(senderPositionAccount.collateralValueUsd.toString(), "40000000000", "Sender keeps $40k of collateral");
+
+    // the receiver never deposited, but can borrow against and redeem the received dTokens
+    await refreshPosition(receiver, receiverPosition, receiverDTokenAta);
+    const receiverPositionAccount = await program.account.userPosition.fetch(receiverPosition);
+    assert.equal(receiverPositionAccount.collateralValueUsd.toString(), "60000000000", "Receiver gains $60k of collateral");
+
+    await program.methods
+      .borrow(new BN(100_000_000))
+      .accounts(borrowAccounts(receiver, receiverAta, receiverPoolPosition, receiverPosition))
+      .remainingAccounts(collateralAccounts(receiver.publicKey, assetPool, assetDTokenMint.publicKey, assetOracle))
+      .signers([receiver])
+      .rpc();
+    console.log(" Receiver borrowed against received dTokens");
+
+    const receiverAtaBefore = await getAccount(provider.connection, receiverAta);
+    await program.methods
+      .withdraw(new BN(200_000_000))
+      .accounts({
+        user: receiver.publicKey,
+        mint: assetMint,
+        mintDtoken: assetDTokenMint.publicKey,
+        pool: assetPool,
+        config: configPda,
+        vault: assetVault,
+        userDtokenAta: receiverDTokenAta,
+        userTokenAta: receiverAta,
+        userPoolPosition: receiverPoolPosition,
+        userPosition: receiverPosition,
+        oracle: assetOracle,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([receiver])
+      .rpc();
+    const receiverAtaAfter = await getAccount(provider.connection, receiverAta);
+    assert.equal(
+      (receiverAtaAfter.amount - receiverAtaBefore.amount).toString(),
+      "200000000",
+      "Receiver should redeem received dTokens for underlying"
+    );
+    console.log(" Receiver redeemed received dTokens");
+
+    // with the loan still open, moving dTokens out would strip its collateral
+    try {
+      await transferCheckedWithTransferHook(
+        provider.connection,
+        receiver,
+        receiverDTokenAta,
+        assetDTokenMint.publicKey,
+        senderDTokenAta,
+        receiver,
+        100_000_000,
+        6,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      assert.fail("Borrower should not transfer dTokens out with open debt");
+    } catch (error) {
+      assert.ok(error.logs?.some((log: string) => log.includes("DTokenTransferWithOpenBorrows")));
+    }
+    const receiverDTokens = await getAccount(provider.connection, receiverDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+    assert.equal(receiverDTokens.amount.toString(), "400000000", "Borrower's dTokens should stay put");
+    console.log(" Borrower with open debt cannot transfer dTokens out");
+  });
+
+  it("deposits, borrows and withdraws native SOL without manual wrapping", async () => {
+    console.log("\nTESTING NATIVE SOL");
+
+    const solDTokenMint = Keypair.generate();
+    const solOracle = await createMockOracle(NATIVE_MINT);
+    const [solPool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), configPda.toBuffer(), NATIVE_MINT.toBuffer()],
+      program.programId
+    );
+    const solVault = getAssociatedTokenAddressSync(NATIVE_MINT, solPool, true);
+
+    await program.methods
+      .createPool(
+        { mock: {} }, Array(32).fill(0),
+        "Deposit SOL", "dSOL", "",
+        7000, 8000, 500, 5000,
+        new BN("10000000000000000"),
+        new BN("50000000000000000"),
+        new BN("200000000000000000"),
+        new BN("800000000000000000"),
+        new BN(3600), 200
+      )
+      .accounts({
+        admin: admin.publicKey,
+        mint: NATIVE_MINT,
+        config: configPda,
+        dtokenMint: solDTokenMint.publicKey,
+        pool: solPool,
+        vault: solVault,
+        oracle: solOracle,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([solDTokenMint])
+      .rpc();
+    console.log(" SOL pool created");
+
+    const solUser = Keypair.generate();
+    const airdropSig = await provider.connection.requestAirdrop(solUser.publicKey, 10 * anchor.web3.LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(airdropSig);
+
+    const solUserWsolAta = getAssociatedTokenAddressSync(NATIVE_MINT, solUser.publicKey);
+    const solUserDTokenAta = getAssociatedTokenAddressSync(solDTokenMint.publicKey, solUser.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const [solUserPoolPosition] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user-pool-position"), solUser.publicKey.toBuffer(), solPool.toBuffer()],
+      program.programId
+    );
+    const [solUserPosition] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user-position"), configPda.toBuffer(), solUser.publicKey.toBuffer()],
+      program.programId
+    );
+
+    // lamports sent to the vault outside the program must not be credited
+    const strayLamports = 1_000_000;
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        SystemProgram.transfer({ fromPubkey: solUser.publicKey, toPubkey: solVault, lamports: strayLamports })
+      ),
+      [solUser]
+    );
+
+    // deposit 5 SOL straight from the wallet
+    const depositLamports = new BN(5 * anchor.web3.LAMPORTS_PER_SOL);
+    await program.methods
+      .depositSol(depositLamports)
+      .accounts({
+        deposit: {
+          user: solUser.publicKey,
+          underlyingMint: NATIVE_MINT,
+          dtokenMint: solDTokenMint.publicKey,
+          config: configPda,
+          pool: solPool,
+          vault: solVault,
+          userAta: solUserWsolAta,
+          userDtokenAta: solUserDTokenAta,
+          userPoolPosition: solUserPoolPosition,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        },
+      })
+      .signers([solUser])
+      .rpc();
+
+    const solVaultAccount = await getAccount(provider.connection, solVault);
+    const solDTokens = await getAccount(provider.connection, solUserDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+    assert.equal(
+      solVaultAccount.amount.toString(),
+      depositLamports.addn(strayLamports).toString(),
+      "Vault should hold the deposited and stray SOL as wSOL"
+    );
+    assert.equal(solDTokens.amount.toString(), depositLamports.toString(), "dTokens should be minted for the deposited SOL only");
+    const solPoolAfterDeposit = await program.account.pool.fetch(solPool);
+    assert.equal(solPoolAfterDeposit.totalLiquidity.toString(), depositLamports.toString(), "Stray lamports should not be credited");
+    assert.isNull(await provider.connection.getAccountInfo(solUserWsolAta), "No wSOL account should be left behind");
+    console.log(" Deposited", depositLamports.toString(), "lamports");
+
+    await program.methods
+      .updateMockOracle(new BN("10000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: solOracle,
+        config: configPda,
+      })
+      .rpc();
+    await program.methods
+      .updateDepositPosition()
+      .accounts({
+        user: solUser.publicKey,
+        underlyingMint: NATIVE_MINT,
+        config: configPda,
+        pool: solPool,
+        userPosition: solUserPosition,
+        userDtokenAta: solUserDTokenAta,
+        oracle: solOracle,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([solUser])
+      .rpc();
+
+    // borrow 1 SOL and receive it as native lamports
+    const borrowLamports = new BN(anchor.web3.LAMPORTS_PER_SOL);
+    const lamportsBeforeBorrow = await provider.connection.getBalance(solUser.publicKey);
+    await program.methods
+      .borrowSol(borrowLamports)
+      .accounts({
+        borrow: {
+          user: solUser.publicKey,
+          underlyingMint: NATIVE_MINT,
+          pool: solPool,
+          config: configPda,
+          userAta: solUserWsolAta,
+          userPoolPosition: solUserPoolPosition,
+          userPosition: solUserPosition,
+          vault: solVault,
+          oracle: solOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        },
+      })
+      .remainingAccounts(collateralAccounts(solUser.publicKey, solPool, solDTokenMint.publicKey, solOracle))
+      .signers([solUser])
+      .rpc();
+    const lamportsAfterBorrow = await provider.connection.getBalance(solUser.publicKey);
+    // the user pays the transaction fee and the user-pool-position rent out of the borrowed SOL
+    assert.ok(
+      lamportsAfterBorrow - lamportsBeforeBorrow > 0.99 * anchor.web3.LAMPORTS_PER_SOL,
+      "Borrowed SOL should arrive as native lamports"
+    );
+    assert.isNull(await provider.connection.getAccountInfo(solUserWsolAta), "Temporary wSOL account should be closed");
+    console.log(" Borrowed SOL delivered natively:", lamportsAfterBorrow - lamportsBeforeBorrow, "lamports net");
+
+    // withdraw 1 SOL worth of dTokens as native SOL
+    const lamportsBeforeWithdraw = await provider.connection.getBalance(solUser.publicKey);
+    await program.methods
+      .withdrawSol(new BN(anchor.web3.LAMPORTS_PER_SOL))
+      .accounts({
+        withdraw: {
+          user: solUser.publicKey,
+          mint: NATIVE_MINT,
+          mintDtoken: solDTokenMint.publicKey,
+          pool: solPool,
+          config: configPda,
+          vault: solVault,
+          userDtokenAta: solUserDTokenAta,
+          userTokenAta: solUserWsolAta,
+          userPoolPosition: solUserPoolPosition,
+          userPosition: solUserPosition,
+          oracle: solOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        },
+      })
+      .signers([solUser])
+      .rpc();
+    const lamportsAfterWithdraw = await provider.connection.getBalance(solUser.publicKey);
+    assert.ok(
+      lamportsAfterWithdraw - lamportsBeforeWithdraw > 0.99 * anchor.web3.LAMPORTS_PER_SOL,
+      "Withdrawn SOL should arrive as native lamports"
+    );
+    assert.isNull(await provider.connection.getAccountInfo(solUserWsolAta), "Temporary wSOL account should be closed");
+    console.log(" Withdrawn SOL delivered natively");
+  });
+
+  it("rejects underlying mints with a permanent delegate", async () => {
+    const delegateMint = await createToken2022Mint([ExtensionType.PermanentDelegate], (mint) => [
+      createInitializePermanentDelegateInstruction(mint, admin.publicKey, TOKEN_2022_PROGRAM_ID),
+    ]);
+    const delegateDTokenMint = Keypair.generate();
+    const [delegatePool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), configPda.toBuffer(), delegateMint.toBuffer()],
+      program.programId
+    );
+
+    try {
+      await program.methods
+        .createPool(
+          { mock: {} }, Array(32).fill(0),
+          "Deposit Delegate", "dDEL", "",
+          7000, 8000, 500, 5000,
+          new BN("10000000000000000"),
+          new BN("50000000000000000"),
+          new BN("200000000000000000"),
+          new BN("800000000000000000"),
+          new BN(3600), 200
+        )
+        .accounts({
+          admin: admin.publicKey,
+          mint: delegateMint,
+          config: configPda,
+          dtokenMint: delegateDTokenMint.publicKey,
+          pool: delegatePool,
+          vault: getAssociatedTokenAddressSync(delegateMint, delegatePool, true, TOKEN_2022_PROGRAM_ID),
+          oracle: mockOracle,
+          systemProgram: SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([delegateDTokenMint])
+        .rpc();
+      assert.fail("Should have rejected the permanent delegate mint");
+    } catch (error) {
+      assert.ok(error.toString().includes("UnsupportedMintExtension"));
+      console.log(" Correctly rejected permanent delegate mint");
+    }
+  });
+
+  it("rejects stale oracle prices using the pool's max price age", async () => {
+    console.log("\nTESTING ORACLE STALENESS");
+
+    const updateOracleConfig = (maxPriceAgeSecs: number, maxConfidenceBps: number) =>
+      program.methods
+        .updateOracleConfig(new BN(maxPriceAgeSecs), maxConfidenceBps)
+        .accounts({
+          admin: admin.publicKey,
+          config: configPda,
+          pool: poolPda,
+        })
+        .rpc();
+    const refreshAdminPosition = () =>
+      program.methods
+        .updateDepositPosition()
+        .accounts({
+          user: admin.publicKey,
+          underlyingMint: mintX,
+          config: configPda,
+          pool: poolPda,
+          userPosition: userPosition,
+          userDtokenAta: getAssociatedTokenAddressSync(dTokenMint.publicKey, admin.publicKey, false, TOKEN_2022_PROGRAM_ID),
+          oracle: mockOracle,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+
+    try {
+      await updateOracleConfig(60, 0);
+      assert.fail("Should have rejected a zero confidence limit");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidOracleConfig"));
+      console.log(" Correctly rejected invalid oracle config");
+    }
+
+    await updateOracleConfig(1, 200);
+    const poolAccount = await program.account.pool.fetch(poolPda);
+    assert.equal(poolAccount.maxPriceAgeSecs.toNumber(), 1);
+    assert.equal(poolAccount.maxConfidenceBps, 200);
+
+    await program.methods
+      .updateMockOracle(new BN("10000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: mockOracle,
+        config: configPda,
+      })
+      .rpc();
+    await new Promise(resolve => setTimeout(resolve, 3000));
+
+    try {
+      await refreshAdminPosition();
+      assert.fail("Should have rejected a stale price");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidOraclePrice"));
+      console.log(" Correctly rejected price older than max_price_age_secs");
+    }
+
+    await updateOracleConfig(3600, 200);
+    await refreshAdminPosition();
+    console.log(" Price accepted again after raising max_price_age_secs");
+  });
+
+  it("prices fixed-price pools without an oracle feed", async () => {
+    console.log("\nTESTING ORACLE SOURCES");
+
+    const stableMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    const stableDTokenMint = Keypair.generate();
+    const [stablePool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), configPda.toBuffer(), stableMint.toBuffer()],
+      program.programId
+    );
+    const stableVault = getAssociatedTokenAddressSync(stableMint, stablePool, true);
+    const createStablePool = (oracleSource: any, oracle: PublicKey) =>
+      program.methods
+        .createPool(
+          oracleSource, Array(32).fill(0),
+          "Deposit Stable", "dSTB", "",
+          7000, 8000, 500, 5000,
+          new BN("10000000000000000"),
+          new BN("50000000000000000"),
+          new BN("200000000000000000"),
+          new BN("800000000000000000"),
+          new BN(3600), 200
+        )
+        .accounts({
+          admin: admin.publicKey,
+          mint: stableMint,
+          config: configPda,
+          dtokenMint: stableDTokenMint.publicKey,
+          pool: stablePool,
+          vault: stableVault,
+          oracle: oracle,
+          systemProgram: SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([stableDTokenMint])
+        .rpc();
+
+    try {
+      await createStablePool({ pythPull: {} }, mockOracle);
+      assert.fail("Should have rejected a mock oracle as a Pyth feed");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidOracleAccount"));
+      console.log(" Correctly rejected oracle account of the wrong source");
+    }
+
+    // a spoofed price account has to be owned by the Pyth receiver
+    const spoofedOracle = Keypair.generate();
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: admin.publicKey,
+          newAccountPubkey: spoofedOracle.publicKey,
+          space: 134,
+          lamports: await provider.connection.getMinimumBalanceForRentExemption(134),
+          programId: program.programId,
+        })
+      ),
+      [spoofedOracle]
+    );
+    try {
+      await createStablePool({ pythPull: {} }, spoofedOracle.publicKey);
+      assert.fail("Should have rejected a spoofed Pyth account");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidOracleAccount"));
+      console.log(" Correctly rejected price account not owned by the Pyth receiver");
+    }
+
+    // the oracle account is never read for fixed prices, the mint works as a placeholder
+    await createStablePool({ fixedPrice: { priceUsd1e6: new BN(1_000_000) } }, stableMint);
+    const stablePoolAccount = await program.account.pool.fetch(stablePool);
+    assert.equal((stablePoolAccount.oracleSource as any).fixedPrice.priceUsd1e6.toString(), "1000000");
+
+    const adminStableAta = await createAssociatedTokenAccount(provider.connection, admin.payer, stableMint, admin.publicKey);
+    const adminStableDTokenAta = getAssociatedTokenAddressSync(stableDTokenMint.publicKey, admin.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    await mintTo(provider.connection, admin.payer, stableMint, adminStableAta, admin.payer, 500_000_000);
+    await program.methods
+      .depositTokens(new BN(500_000_000))
+      .accounts({
+        user: admin.publicKey,
+        underlyingMint: stableMint,
+        dtokenMint: stableDTokenMint.publicKey,
+        config: configPda,
+        pool: stablePool,
+        vault: stableVault,
+        userAta: adminStableAta,
+        userDtokenAta: adminStableDTokenAta,
+        userPoolPosition: PublicKey.findProgramAddressSync(
+          [Buffer.from("user-pool-position"), admin.publicKey.toBuffer(), stablePool.toBuffer()],
+          program.programId
+        )[0],
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    await program.methods
+      .updateDepositPosition()
+      .accounts({
+        user: admin.publicKey,
+        underlyingMint: stableMint,
+        config: configPda,
+        pool: stablePool,
+        userPosition: userPosition,
+        userDtokenAta: adminStableDTokenAta,
+        oracle: stableMint,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    const position = await program.account.userPosition.fetch(userPosition);
+    assert.equal(position.collateralValueUsd.toString(), "500000000", "500 tokens at a fixed $1 should be worth $500");
+    console.log(" Fixed-price collateral valued at", position.collateralValueUsd.toString());
+  });
+
+  it("falls back to the secondary oracle when the primary is stale", async () => {
+    console.log("\nTESTING ORACLE FALLBACK");
+
+    const updateSecondaryOracle = (source: any, maxDeviationBps: number, secondaryOracle: PublicKey | null) =>
+      program.methods
+        .updateSecondaryOracle(source, maxDeviationBps)
+        .accounts({
+          admin: admin.publicKey,
+          config: configPda,
+          pool: poolPda,
+          secondaryOracle: secondaryOracle,
+        })
+        .rpc();
+    const updateMaxPriceAge = (maxPriceAgeSecs: number) =>
+      program.methods
+        .updateOracleConfig(new BN(maxPriceAgeSecs), 200)
+        .accounts({
+          admin: admin.publicKey,
+          config: configPda,
+          pool: poolPda,
+        })
+        .rpc();
+    const refreshAdminPosition = (secondaryOracle: PublicKey | null) =>
+      program.methods
+        .updateDepositPosition()
+        .accounts({
+          user: admin.publicKey,
+          underlyingMint: mintX,
+          config: configPda,
+          pool: poolPda,
+          userPosition: userPosition,
+          userDtokenAta: getAssociatedTokenAddressSync(dTokenMint.publicKey, admin.publicKey, false, TOKEN_2022_PROGRAM_ID),
+          oracle: mockOracle,
+          secondaryOracle: secondaryOracle,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    const refreshMockOracle = () =>
+      program.methods
+        .updateMockOracle(new BN("10000000000"), -8)
+        .accounts({
+          admin: admin.publicKey,
+          mockOracle: mockOracle,
+          config: configPda,
+        })
+        .rpc();
+
+    // a fixed $200 fallback is 100% away from the $100 mock price
+    await updateSecondaryOracle({ fixedPrice: { priceUsd1e6: new BN(200_000_000) } }, 500, mintX);
+    await refreshMockOracle();
+    try {
+      await refreshAdminPosition(mintX);
+      assert.fail("Should have rejected deviating oracle prices");
+    } catch (error) {
+      assert.ok(error.toString().includes("OraclePriceDeviation"));
+      console.log(" Correctly rejected prices deviating beyond max_price_deviation_bps");
+    }
+
+    try {
+      await refreshAdminPosition(null);
+      assert.fail("Should have required the secondary oracle");
+    } catch (error) {
+      assert.ok(error.toString().includes("MissingSecondaryOracle"));
+      console.log(" Correctly required the secondary oracle");
+    }
+
+    // a secondary with a confidence band far beyond 2% cannot cross-check the
+    // fresh primary, which is then used alone
+    const noisyMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    const noisyOracle = await createMockOracle(noisyMint, new BN("10000000000"), new BN("5000000000"));
+    await updateSecondaryOracle({ mock: {} }, 500, noisyOracle);
+    await refreshAdminPosition(noisyOracle);
+    console.log(" Fresh primary priced the pool despite an unusable secondary");
+
+    // once the primary goes stale the fallback price is used
+    await updateSecondaryOracle({ fixedPrice: { priceUsd1e6: new BN(101_000_000) } }, 500, mintX);
+    await refreshAdminPosition(mintX);
+    const freshPosition = await program.account.userPosition.fetch(userPosition);
+
+    await updateMaxPriceAge(1);
+    await new Promise(resolve => setTimeout(resolve, 3000));
+    let fallbackEvents = 0;
+    const listener = program.addEventListener("oracleFallbackEvent", () => { fallbackEvents += 1; });
+    await refreshAdminPosition(mintX);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await program.removeEventListener(listener);
+    const fallbackPosition = await program.account.userPosition.fetch(userPosition);
+    assert.ok(
+      fallbackPosition.collateralValueUsd.gt(freshPosition.collateralValueUsd),
+      "Collateral should be valued at the $101 fallback price"
+    );
+    assert.equal(fallbackEvents, 1, "Fallback should emit an event");
+    console.log(" Stale primary fell back to the secondary oracle");
+
+    await updateMaxPriceAge(3600);
+    await updateSecondaryOracle(null, 0, null);
+    await refreshMockOracle();
+    await refreshAdminPosition(null);
+  });
+
+  it("liquidates a SOL-backed USDC loan when only the SOL price drops", async () => {
+    console.log("\nTESTING CROSS-POOL LIQUIDATION");
+
+    // each pool reads its own mock feed: "SOL" at $100 and "USDC" at $1
+    const sol = await createPricedPool(6, new BN("10000000000"), "MSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "MUSDC");
+    assert.notEqual(sol.oracle.toBase58(), usdc.oracle.toBase58());
+
+    // 1000 SOL ($100k) of collateral backing a $60k USDC loan
+    const borrowAmount = new BN(60_000_000_000);
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), borrowAmount);
+    console.log(" Borrowed $60k USDC against $100k SOL");
+
+    // SOL drops to $50 while USDC holds its peg: HF = $50k * 0.7 / $60k < 1
+    await program.methods
+      .updateMockOracle(new BN("5000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: sol.oracle,
+        config: configPda,
+      })
+      .rpc();
+    const usdcOracleAccount = await program.account.mockOracle.fetch(usdc.oracle);
+    assert.equal(usdcOracleAccount.price.toString(), "100000000", "USDC price should be unaffected");
+
+    await liquidateLoan(loan, sol, usdc, new BN(30_000_000_000)); // 50% close factor
+
+    // $30k repaid at $1 plus a 5% bonus, paid out in SOL at $50: 630 SOL
+    const liquidatorSol = await getAccount(provider.connection, getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey));
+    assert.equal(liquidatorSol.amount.toString(), "630000000", "Liquidator should receive 630 SOL");
+    const debtPosition = await program.account.userPoolPosition.fetch(loan.borrowerDebtPosition);
+    assert.ok(debtPosition.borrowedAmount.lt(borrowAmount), "USDC debt should be reduced by the repayment");
+    console.log(" Liquidator received", liquidatorSol.amount.toString(), "SOL base units");
+  });
+
+  it("values collateral at the lower of spot and TWAP in conservative mode", async () => {
+    console.log("\nTESTING CONSERVATIVE PRICING");
+
+    const updatePriceMode = (priceMode: any, twapWindowSecs: number) =>
+      program.methods
+        .updatePriceMode(priceMode, new BN(twapWindowSecs))
+        .accounts({
+          admin: admin.publicKey,
+          config: configPda,
+          pool: poolPda,
+        })
+        .rpc();
+    const setMockPrice = (price: BN) =>
+      program.methods
+        .updateMockOracle(price, -8)
+        .accounts({
+          admin: admin.publicKey,
+          mockOracle: mockOracle,
+          config: configPda,
+        })
+        .rpc();
+    const refreshAdminPosition = async () => {
+      await program.methods
+        .updateDepositPosition()
+        .accounts({
+          user: admin.publicKey,
+          underlyingMint: mintX,
+          config: configPda,
+          pool: poolPda,
+          userPosition: userPosition,
+          userDtokenAta: getAssociatedTokenAddressSync(dTokenMint.publicKey, admin.publicKey, false, TOKEN_2022_PROGRAM_ID),
+          oracle: mockOracle,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      return (await program.account.userPosition.fetch(userPosition)).collateralValueUsd;
+    };
+
+    await setMockPrice(new BN("10000000000"));
+    const spotCollateral = await refreshAdminPosition();
+
+    try {
+      await updatePriceMode({ conservative: {} }, 0);
+      assert.fail("Should have required a TWAP window");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidOracleConfig"));
+    }
+
+    await updatePriceMode({ conservative: {} }, 3600);
+    try {
+      await refreshAdminPosition();
+      assert.fail("Should have required a TWAP");
+    } catch (error) {
+      assert.ok(error.toString().includes("StaleTwap"));
+      console.log(" Correctly rejected valuation before the TWAP was seeded");
+    }
+
+    await program.methods
+      .refreshTwap()
+      .accounts({
+        config: configPda,
+        pool: poolPda,
+        oracle: mockOracle,
+      })
+      .rpc();
+    const pool = await program.account.pool.fetch(poolPda);
+    assert.equal(pool.twapPriceUsd1e6.toString(), "100000000", "TWAP should start at the spot price");
+
+    // a wick to $200 does not double the collateral
+    await setMockPrice(new BN("20000000000"));
+    const wickCollateral = await refreshAdminPosition();
+    assert.equal(wickCollateral.toString(), spotCollateral.toString(), "Collateral should stay at the $100 TWAP");
+    console.log(" Collateral held at the TWAP during a price wick");
+
+    await updatePriceMode({ spot: {} }, 3600);
+    await setMockPrice(new BN("10000000000"));
+    await refreshAdminPosition();
+  });
+
+  it("requires a TWAP window before a non-Pyth secondary backs a Pyth pool", async () => {
+    console.log("\nTESTING SECONDARY ORACLE SMOOTHING");
+
+    // tests/fixtures/pyth_sol_usd.json, loaded by the validator: a fully
+    // verified SOL/USD update that is stale for the whole run
+    const pythOracle = new PublicKey("3ihJdDjJnMSkcFHsPr6Pd3Z71iK9v2RqdyFAV53krQTn");
+    const solUsdFeedId = Array.from(Buffer.from("ef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d", "hex"));
+
+    const pythMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 9);
+    const pythDTokenMint = Keypair.generate();
+    const [pythPool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), configPda.toBuffer(), pythMint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .createPool(
+        { pythPull: {} }, solUsdFeedId,
+        "Deposit Pyth SOL", "dPSOL", "",
+        7000, 8000, 500, 5000,
+        new BN("10000000000000000"),
+        new BN("50000000000000000"),
+        new BN("200000000000000000"),
+        new BN("800000000000000000"),
+        new BN(3600), 200
+      )
+      .accounts({
+        admin: admin.publicKey,
+        mint: pythMint,
+        config: configPda,
+        dtokenMint: pythDTokenMint.publicKey,
+        pool: pythPool,
+        vault: getAssociatedTokenAddressSync(pythMint, pythPool, true),
+        oracle: pythOracle,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([pythDTokenMint])
+      .rpc();
+
+    const updatePriceMode = (priceMode: any, twapWindowSecs: number) =>
+      program.methods
+        .updatePriceMode(priceMode, new BN(twapWindowSecs))
+        .accounts({
+          admin: admin.publicKey,
+          config: configPda,
+          pool: pythPool,
+        })
+        .rpc();
+    // the oracle account is never read for fixed prices, the mint works as a placeholder
+    const setFixedSecondary = () =>
+      program.methods
+        .updateSecondaryOracle({ fixedPrice: { priceUsd1e6: new BN(150_000_000) } }, 500)
+        .accounts({
+          admin: admin.publicKey,
+          config: configPda,
+          pool: pythPool,
+          secondaryOracle: pythMint,
+        })
+        .rpc();
+
+    // a Pyth primary smooths with its own EMA and needs no TWAP window
+    await updatePriceMode({ conservative: {} }, 0);
+    try {
+      await setFixedSecondary();
+      assert.fail("Should have required a TWAP window for the secondary");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidOracleConfig"));
+      console.log(" Correctly rejected a fixed-price secondary without a TWAP window");
+    }
+
+    await updatePriceMode({ spot: {} }, 0);
+    await setFixedSecondary();
+    try {
+      await updatePriceMode({ conservative: {} }, 0);
+      assert.fail("Should have required a TWAP window for the secondary");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidOracleConfig"));
+      console.log(" Correctly rejected smoothing without a TWAP window for the secondary");
+    }
+
+    // the stale Pyth primary falls back to the secondary, which seeds the TWAP
+    await updatePriceMode({ conservative: {} }, 3600);
+    await program.methods
+      .refreshTwap()
+      .accounts({
+        config: configPda,
+        pool: pythPool,
+        oracle: pythOracle,
+        secondaryOracle: pythMint,
+      })
+      .rpc();
+    const pool = await program.account.pool.fetch(pythPool);
+    assert.equal(pool.twapPriceUsd1e6.toString(), "150000000", "TWAP should start at the $150 fallback price");
+    console.log(" Stale Pyth primary fell back to the secondary's TWAP");
+  });
+
+  it("scales the liquidation bonus with how far below 1.0 the health factor is", async () => {
+    console.log("\nTESTING DYNAMIC LIQUIDATION BONUS");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "DSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "DUSDC");
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+
+    // 5% at HF 1.0 rising to 15% at HF 0.5
+    await program.methods
+      .updateLiquidationBonus(500, 1500, 5000)
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        pool: sol.pool,
+      })
+      .rpc();
+    const solPool = await program.account.pool.fetch(sol.pool);
+    assert.equal(solPool.maxLiquidationBonusBps, 1500);
+    assert.equal(solPool.maxBonusHealthFactorBps, 5000);
+
+    // SOL at $75: HF = $75k * 0.7 / $60k = 0.875, a quarter of the way to the floor
+    await program.methods
+      .updateMockOracle(new BN("7500000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: sol.oracle,
+        config: configPda,
+      })
+      .rpc();
+
+    let bonusBps: number | undefined;
+    const listener = program.addEventListener("liquidateEvent", (event) => {
+      bonusBps = event.bonusBps.toNumber();
+    });
+    await liquidateLoan(loan, sol, usdc, new BN(30_000_000_000));
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await program.removeEventListener(listener);
+    assert.equal(bonusBps, 750, "Bonus should be 7.5%");
+
+    // $30k repaid plus a 7.5% bonus, paid out in SOL at $75: 430 SOL
+    const liquidatorSol = await getAccount(provider.connection, getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey));
+    assert.equal(liquidatorSol.amount.toString(), "430000000", "Liquidator should receive 430 SOL");
+    console.log(" Liquidator received", liquidatorSol.amount.toString(), "SOL base units at a", bonusBps, "bps bonus");
+  });
+
+  it("keeps the protocol share of the liquidation bonus as pool reserves", async () => {
+    console.log("\nTESTING PROTOCOL LIQUIDATION FEE");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "FSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "FUSDC");
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+
+    // half of the 5% bonus goes to the protocol
+    await program.methods
+      .updateProtocolLiquidationFee(5000)
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        pool: sol.pool,
+      })
+      .rpc();
+
+    await program.methods
+      .updateMockOracle(new BN("5000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: sol.oracle,
+        config: configPda,
+      })
+      .rpc();
+    await liquidateLoan(loan, sol, usdc, new BN(30_000_000_000));
+
+    // 630 SOL seized, 30 SOL of it bonus: 15 SOL to the protocol, 615 SOL to the liquidator
+    const liquidatorSol = await getAccount(provider.connection, getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey));
+    assert.equal(liquidatorSol.amount.toString(), "615000000", "Liquidator should receive 615 SOL");
+    let solPool = await program.account.pool.fetch(sol.pool);
+    assert.equal(solPool.totalReserves.toString(), "15000000", "Protocol should keep 15 SOL");
+
+    // only the fee authority can take the reserves out
+    const treasury = await createAssociatedTokenAccount(provider.connection, admin.payer, sol.mint, admin.publicKey);
+    try {
+      await program.methods
+        .withdrawReserves(new BN(15_000_000))
+        .accounts({
+          feeAuthority: loan.liquidator.publicKey,
+          config: configPda,
+          mint: sol.mint,
+          pool: sol.pool,
+          vault: sol.vault,
+          destination: getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey),
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([loan.liquidator])
+        .rpc();
+      assert.fail("Only the fee authority should withdraw reserves");
+    } catch (error) {
+      assert.ok(error.toString().includes("NotFeeAuthority"));
+    }
+    await program.methods
+      .withdrawReserves(new BN(15_000_000))
+      .accounts({
+        feeAuthority: admin.publicKey,
+        config: configPda,
+        mint: sol.mint,
+        pool: sol.pool,
+        vault: sol.vault,
+        destination: treasury,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    const treasuryAccount = await getAccount(provider.connection, treasury);
+    assert.equal(treasuryAccount.amount.toString(), "15000000");
+    solPool = await program.account.pool.fetch(sol.pool);
+    assert.equal(solPool.totalReserves.toString(), "0");
+    console.log(" Protocol collected", treasuryAccount.amount.toString(), "SOL base units");
+  });
+
+  it("pays the liquidator in dTokens without touching the collateral vault", async () => {
+    console.log("\nTESTING LIQUIDATION INTO DTOKENS");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "RSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "RUSDC");
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+    const liquidatorDTokenAta = await createAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      sol.dtokenMint,
+      loan.liquidator.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    await program.methods
+      .updateMockOracle(new BN("5000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: sol.oracle,
+        config: configPda,
+      })
+      .rpc();
+    // the collateral ATA is only for payouts in the underlying
+    try {
+      await program.methods
+        .liquidate(new BN(30_000_000_000), true)
+        .accounts({
+          ...liquidateAccounts(loan, sol, usdc, true),
+          liquidatorCollateralAta: getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey),
+        })
+        .signers([loan.liquidator])
+        .rpc();
+      assert.fail("Should have rejected both liquidator collateral accounts");
+    } catch (error) {
+      assert.ok(error.toString().includes("UnexpectedLiquidatorCollateralAccount"));
+    }
+
+    const vaultBefore = await getAccount(provider.connection, sol.vault);
+    await liquidateLoan(loan, sol, usdc, new BN(30_000_000_000), true);
+
+    // 630 SOL seized at a 1:1 exchange rate, handed over as dTokens
+    const liquidatorDTokens = await getAccount(provider.connection, liquidatorDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+    assert.equal(liquidatorDTokens.amount.toString(), "630000000", "Liquidator should receive 630 SOL of dTokens");
+    const liquidatorSol = await getAccount(provider.connection, getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey));
+    assert.equal(liquidatorSol.amount.toString(), "0", "No underlying should be paid out");
+    const vaultAfter = await getAccount(provider.connection, sol.vault);
+    assert.equal(vaultAfter.amount.toString(), vaultBefore.amount.toString(), "Vault cash should be untouched");
+    const borrowerDTokens = await getAccount(provider.connection, loan.borrowerCollateralDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+    assert.equal(borrowerDTokens.amount.toString(), "370000000");
+    console.log(" Liquidator received", liquidatorDTokens.amount.toString(), "dTokens");
+  });
+
+  it("lifts the close factor for positions below the critical health factor", async () => {
+    console.log("\nTESTING FULL LIQUIDATION BELOW CRITICAL HF");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "CSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "CUSDC");
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+
+    // 50% close factor, lifted below HF 0.9
+    await program.methods
+      .updateCloseFactor(5000, 9000, new BN(0))
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        pool: usdc.pool,
+      })
+      .rpc();
+
+    // SOL at $75: HF = $75k * 0.7 / $60k = 0.875
+    await program.methods
+      .updateMockOracle(new BN("7500000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: sol.oracle,
+        config: configPda,
+      })
+      .rpc();
+    // asks for more than the debt, the repayment is capped at what is owed
+    await liquidateLoan(loan, sol, usdc, new BN(61_000_000_000));
+
+    const debtPosition = await program.account.userPoolPosition.fetch(loan.borrowerDebtPosition);
+    assert.equal(debtPosition.borrowedAmount.toString(), "0", "Whole debt should be repaid in one call");
+    // $60k plus a 5% bonus at $75: at least 840 SOL
+    const liquidatorSol = await getAccount(provider.connection, getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey));
+    assert.ok(new BN(liquidatorSol.amount.toString()).gte(new BN(840_000_000)), "Liquidator should receive at least 840 SOL");
+    console.log(" Fully liquidated, liquidator received", liquidatorSol.amount.toString(), "SOL base units");
+  });
+
+  it("liquidates unhealthy borrowers in a batch and skips the rest", async () => {
+    console.log("\nTESTING BATCH LIQUIDATION");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "LSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "LUSDC");
+    // the same $60k loan against 1000 SOL and against 2000 SOL
+    const risky = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+    const safe = await openLoan(sol, usdc, new BN(2_000_000_000), new BN(60_000_000_000));
+    // a loan backed by another pool holds none of the SOL dTokens to seize
+    const otherSol = await createPricedPool(6, new BN("10000000000"), "LSOL2");
+    const stranded = await openLoan(otherSol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+    const strandedSolDTokenAta = await createAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      sol.dtokenMint,
+      stranded.borrower.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    // SOL at $50: HF 0.58 for the first loan, 1.17 for the second
+    await program.methods
+      .updateMockOracle(new BN("5000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: sol.oracle,
+        config: configPda,
+      })
+      .rpc();
+
+    const liquidator = risky.liquidator;
+    const liquidatorUsdcAta = getAssociatedTokenAddressSync(usdc.mint, liquidator.publicKey);
+    await mintTo(provider.connection, admin.payer, usdc.mint, liquidatorUsdcAta, admin.payer, 60_000_000_000);
+    const borrowerAccounts = (loan: Loan) => [
+      { pubkey: loan.borrower.publicKey, isSigner: false, isWritable: false },
+      { pubkey: loan.borrowerDebtPosition, isSigner: false, isWritable: true },
+      { pubkey: loan.borrowerPosition, isSigner: false, isWritable: true },
+      { pubkey: loan.borrowerCollateralDTokenAta, isSigner: false, isWritable: true },
+    ];
+    await program.methods
+      .liquidateBatch(new BN(30_000_000_000), false)
+      .accounts({
+        liquidator: liquidator.publicKey,
+        debtMint: usdc.mint,
+        debtPool: usdc.pool,
+        config: configPda,
+        debtPoolVault: usdc.vault,
+        liquidatorDebtAta: liquidatorUsdcAta,
+        collateralMint: sol.mint,
+        collateralPool: sol.pool,
+        collateralDtokenMint: sol.dtokenMint,
+        collateralPoolVault: sol.vault,
+        liquidatorCollateralAta: getAssociatedTokenAddressSync(sol.mint, liquidator.publicKey),
+        debtOracle: usdc.oracle,
+        collateralOracle: sol.oracle,
+        debtTokenProgram: TOKEN_PROGRAM_ID,
+        collateralTokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        ...borrowerAccounts({ ...stranded, borrowerCollateralDTokenAta: strandedSolDTokenAta }),
+        ...borrowerAccounts(risky),
+        ...borrowerAccounts(safe),
+      ])
+      .signers([liquidator])
+      .rpc();
+
+    // only the first loan is liquidated: $30k plus 5% at $50 is 630 SOL
+    const liquidatorSol = await getAccount(provider.connection, getAssociatedTokenAddressSync(sol.mint, liquidator.publicKey));
+    assert.equal(liquidatorSol.amount.toString(), "630000000", "Only the unhealthy loan should be liquidated");
+    const liquidatorUsdc = await getAccount(provider.connection, liquidatorUsdcAta);
+    assert.equal(liquidatorUsdc.amount.toString(), "30000000000", "Only one repayment should be taken");
+    const safeDebt = await program.account.userPoolPosition.fetch(safe.borrowerDebtPosition);
+    assert.ok(safeDebt.borrowedAmount.gte(new BN(60_000_000_000)), "Healthy loan should be untouched");
+    const strandedDebt = await program.account.userPoolPosition.fetch(stranded.borrowerDebtPosition);
+    assert.ok(strandedDebt.borrowedAmount.gte(new BN(60_000_000_000)), "Loan with nothing to seize should be skipped");
+    const riskyDebt = await program.account.userPoolPosition.fetch(risky.borrowerDebtPosition);
+    assert.ok(riskyDebt.borrowedAmount.lt(new BN(60_000_000_000)), "Unhealthy loan should be reduced");
+    console.log(" Liquidated one of two borrowers in a single transaction");
+  });
+
+  it("lends vault cash within a transaction and credits the flash loan fee", async () => {
+    console.log("\nTESTING FLASH LOANS");
+
+    const usdc = await createPricedPool(6, new BN("100000000"), "FLUSDC");
+    const lender = Keypair.generate();
+    const user = Keypair.generate();
+    for (const account of [lender, user]) {
+      const sig = await provider.connection.requestAirdrop(account.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL);
+      await provider.connection.confirmTransaction(sig);
+    }
+    await createAssociatedTokenAccount(provider.connection, lender, usdc.mint, lender.publicKey);
+    await depositInto(lender, usdc, new BN(100_000_000_000));
+
+    // 0.09% fee, a fifth of it to the protocol
+    await program.methods
+      .updateFlashLoanFee(9, 2000)
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        pool: usdc.pool,
+      })
+      .rpc();
+
+    // the user only holds the fee, the principal comes from the vault
+    const userAta = await createAssociatedTokenAccount(provider.connection, user, usdc.mint, user.publicKey);
+    await mintTo(provider.connection, admin.payer, usdc.mint, userAta, admin.payer, 45_000_000);
+    const amount = new BN(50_000_000_000);
+    const flashBorrowIx = await program.methods
+      .flashBorrow(amount)
+      .accounts({
+        user: user.publicKey,
+        config: configPda,
+        mint: usdc.mint,
+        pool: usdc.pool,
+        vault: usdc.vault,
+        destination: userAta,
+        instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+    const flashRepayIx = await program.methods
+      .flashRepay()
+      .accounts({
+        user: user.publicKey,
+        config: configPda,
+        mint: usdc.mint,
+        pool: usdc.pool,
+        vault: usdc.vault,
+        source: userAta,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+
+    try {
+      await provider.sendAndConfirm(new Transaction().add(flashBorrowIx), [user]);
+      assert.fail("Flash borrow without a repay should fail");
+    } catch (error) {
+      // raw transactions surface the anchor error name only in the logs
+      assert.ok(error.logs?.some((log: string) => log.includes("MissingSettlementInstruction")));
+    }
+
+    const poolBefore = await program.account.pool.fetch(usdc.pool);
+    await provider.sendAndConfirm(new Transaction().add(flashBorrowIx, flashRepayIx), [user]);
+
+    // 45 USDC fee: 9 to reserves, 36 to suppliers
+    const poolAfter = await program.account.pool.fetch(usdc.pool);
+    assert.equal(poolAfter.totalReserves.sub(poolBefore.totalReserves).toString(), "9000000");
+    assert.equal(poolAfter.totalLiquidity.sub(poolBefore.totalLiquidity).toString(), "36000000");
+    assert.equal(poolAfter.flashLoanAmount.toString(), "0");
+    const userAccount = await getAccount(provider.connection, userAta);
+    assert.equal(userAccount.amount.toString(), "0", "User should have paid the fee");
+    console.log(" Flash loan of", amount.toString(), "repaid with a 45 USDC fee");
+  });
+
+  it("flash liquidates without holding the debt asset up front", async () => {
+    console.log("\nTESTING FLASH LIQUIDATION");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "XSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "XUSDC");
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+    await program.methods
+      .updateMockOracle(new BN("5000000000"), -8)
+      .accounts({
+        admin: admin.publicKey,
+        mockOracle: sol.oracle,
+        config: configPda,
+      })
+      .rpc();
+
+    const repayAmount = new BN(30_000_000_000);
+    const liquidatorUsdcAta = getAssociatedTokenAddressSync(usdc.mint, loan.liquidator.publicKey);
+    const flashLiquidateIx = await program.methods
+      .flashLiquidate(repayAmount, false)
+      .accounts({
+        liquidate: liquidateAccounts(loan, sol, usdc),
+        instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      })
+      .instruction();
+    // stands in for swapping the seized SOL into USDC
+    const swapIx = createMintToInstruction(usdc.mint, liquidatorUsdcAta, admin.publicKey, repayAmount.toNumber());
+    const flashRepayIx = await program.methods
+      .flashRepay()
+      .accounts({
+        user: loan.liquidator.publicKey,
+        config: configPda,
+        mint: usdc.mint,
+        pool: usdc.pool,
+        vault: usdc.vault,
+        source: liquidatorUsdcAta,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+
+    try {
+      await provider.sendAndConfirm(new Transaction().add(flashLiquidateIx, swapIx), [loan.liquidator]);
+      assert.fail("Flash liquidation without settlement should fail");
+    } catch (error) {
+      assert.ok(error.logs?.some((log: string) => log.includes("MissingSettlementInstruction")));
+    }
+
+    const usdcBefore = await getAccount(provider.connection, liquidatorUsdcAta);
+    assert.equal(usdcBefore.amount.toString(), "0", "Liquidator starts without USDC");
+    await provider.sendAndConfirm(new Transaction().add(flashLiquidateIx, swapIx, flashRepayIx), [loan.liquidator]);
+
+    const liquidatorSol = await getAccount(provider.connection, getAssociatedTokenAddressSync(sol.mint, loan.liquidator.publicKey));
+    assert.equal(liquidatorSol.amount.toString(), "630000000", "Liquidator should keep the seized 630 SOL");
+    const liquidatorUsdc = await getAccount(provider.connection, liquidatorUsdcAta);
+    assert.equal(liquidatorUsdc.amount.toString(), "0", "Swap proceeds should settle the debt");
+    const usdcPool = await program.account.pool.fetch(usdc.pool);
+    assert.equal(usdcPool.flashLoanAmount.toString(), "0");
+    const debtPosition = await program.account.userPoolPosition.fetch(loan.borrowerDebtPosition);
+    assert.ok(debtPosition.borrowedAmount.lt(new BN(60_000_000_000)), "Debt should be reduced");
+    console.log(" Flash liquidation settled within one transaction");
+  });
+
+  it("enforces per-pool supply and borrow caps", async () => {
+    console.log("\nTESTING SUPPLY AND BORROW CAPS");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "KSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "KUSDC");
+    // 20k USDC supplied, 10k of it borrowed
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(10_000_000_000));
+
+    // cap both sides at what is already there
+    await program.methods
+      .updatePoolCaps(new BN(20_000_000_000), new BN(10_000_000_000))
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        pool: usdc.pool,
+      })
+      .rpc();
+
+    const depositor = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(depositor.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL);
+    await provider.connection.confirmTransaction(sig);
+    await createAssociatedTokenAccount(provider.connection, depositor, usdc.mint, depositor.publicKey);
+    try {
+      await depositInto(depositor, usdc, new BN(1_000_000));
+      assert.fail("Deposit above the supply cap should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("SupplyCapExceeded"));
+      console.log(" Rejected a deposit above the supply cap");
+    }
+
+    try {
+      await program.methods
+        .borrow(new BN(1_000_000))
+        .accounts({
+          user: loan.borrower.publicKey,
+          underlyingMint: usdc.mint,
+          pool: usdc.pool,
+          config: configPda,
+          userAta: getAssociatedTokenAddressSync(usdc.mint, loan.borrower.publicKey),
+          userPoolPosition: loan.borrowerDebtPosition,
+          userPosition: loan.borrowerPosition,
+          vault: usdc.vault,
+          oracle: usdc.oracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(collateralAccounts(loan.borrower.publicKey, sol.pool, sol.dtokenMint, sol.oracle))
+        .signers([loan.borrower])
+        .rpc();
+      assert.fail("Borrow above the borrow cap should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("BorrowCapExceeded"));
+      console.log(" Rejected a borrow above the borrow cap");
+    }
+
+    // lifting the supply cap lets deposits through again
+    await program.methods
+      .updatePoolCaps(new BN(0), new BN(10_000_000_000))
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        pool: usdc.pool,
+      })
+      .rpc();
+    await depositInto(depositor, usdc, new BN(1_000_000));
+    const usdcPool = await program.account.pool.fetch(usdc.pool);
+    assert.equal(usdcPool.supplyCap.toString(), "0");
+    assert.equal(usdcPool.borrowCap.toString(), "10000000000");
+  });
+
+  it("limits borrowing against isolated collateral to designated pools and a debt ceiling", async () => {
+    console.log("\nTESTING ISOLATION MODE");
+
+    const risky = await createPricedPool(6, new BN("1000000000"), "RISK");
+    const usdc = await createPricedPool(6, new BN("100000000"), "IUSDC");
+    const dai = await createPricedPool(6, new BN("100000000"), "IDAI");
+    // risky collateral may back up to $5k of debt, only from the USDC pool
+    await program.methods
+      .updateIsolationMode(true, new BN(5_000_000_000), false)
+      .accounts({ admin: admin.publicKey, config: configPda, pool: risky.pool })
+      .rpc();
+    await program.methods
+      .updateIsolationMode(false, new BN(0), true)
+      .accounts({ admin: admin.publicKey, config: configPda, pool: usdc.pool })
+      .rpc();
+
+    // $10k of collateral, $3k borrowed against the ceiling
+    const loan = await openLoan(risky, usdc, new BN(1_000_000_000), new BN(3_000_000_000));
+    let riskyPool = await program.account.pool.fetch(risky.pool);
+    let position = await program.account.userPosition.fetch(loan.borrowerPosition);
+    assert.equal(riskyPool.isolatedDebtUsd.toString(), "3000000000");
+    assert.equal(position.isolationPool.toBase58(), risky.pool.toBase58());
+    console.log(" Booked the loan against the isolated debt ceiling");
+
+    const borrowFrom = (asset: PricedPool, amount: BN) =>
+      program.methods
+        .borrow(amount)
+        .accounts({
+          user: loan.borrower.publicKey,
+          underlyingMint: asset.mint,
+          pool: asset.pool,
+          config: configPda,
+          userAta: getAssociatedTokenAddressSync(asset.mint, loan.borrower.publicKey),
+          userPoolPosition: PublicKey.findProgramAddressSync(
+            [Buffer.from("user-pool-position"), loan.borrower.publicKey.toBuffer(), asset.pool.toBuffer()],
+            program.programId
+          )[0],
+          userPosition: loan.borrowerPosition,
+          vault: asset.vault,
+          oracle: asset.oracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(collateralAccounts(loan.borrower.publicKey, risky.pool, risky.dtokenMint, risky.oracle))
+        .signers([loan.borrower])
+        .rpc();
+
+    // $3k + $2.5k stays within LTV but not within the ceiling
+    try {
+      await borrowFrom(usdc, new BN(2_500_000_000));
+      assert.fail("Borrow above the isolation debt ceiling should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("IsolationDebtCeilingExceeded"));
+      console.log(" Rejected a borrow above the debt ceiling");
+    }
+
+    await createAssociatedTokenAccount(provider.connection, loan.liquidator, dai.mint, loan.liquidator.publicKey);
+    await depositInto(loan.liquidator, dai, new BN(1_000_000_000));
+    try {
+      await borrowFrom(dai, new BN(100_000_000));
+      assert.fail("Borrow from a pool not designated for isolation should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("IsolationModeBorrowNotAllowed"));
+      console.log(" Rejected a borrow from a pool not designated for isolation");
+    }
+
+    // repaying frees the ceiling again
+    await program.methods
+      .repay(new BN(1_000_000_000))
+      .accounts({
+        user: loan.borrower.publicKey,
+        underlyingMint: usdc.mint,
+        pool: usdc.pool,
+        config: configPda,
+        userMintAta: getAssociatedTokenAddressSync(usdc.mint, loan.borrower.publicKey),
+        vault: usdc.vault,
+        userPosition: loan.borrowerPosition,
+        userPoolPosition: loan.borrowerDebtPosition,
+        isolationPool: risky.pool,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([loan.borrower])
+      .rpc();
+    riskyPool = await program.account.pool.fetch(risky.pool);
+    position = await program.account.userPosition.fetch(loan.borrowerPosition);
+    // a third of the debt repaid releases a third of its booked value; the
+    // interest accrued since borrowing makes that third slightly smaller
+    assert.approximately(riskyPool.isolatedDebtUsd.toNumber(), 2_000_000_000, 1_000);
+    assert.approximately(position.isolatedDebtUsd.toNumber(), 2_000_000_000, 1_000);
+    assert.approximately(position.debtValueUsd.toNumber(), 2_000_000_000, 1_000);
+    console.log(" Released repaid debt from the ceiling");
+  });
+
+  it("holds debt left backed by isolated collateral to isolation rules on withdraw", async () => {
+    console.log("\nTESTING ISOLATION ON WITHDRAW");
+
+    const risky = await createPricedPool(6, new BN("1000000000"), "WRISK");
+    const safe = await createPricedPool(6, new BN("100000000"), "WSAFE");
+    const usdc = await createPricedPool(6, new BN("100000000"), "WUSDC");
+    const dai = await createPricedPool(6, new BN("100000000"), "WDAI");
+    await program.methods
+      .updateIsolationMode(true, new BN(5_000_000_000), false)
+      .accounts({ admin: admin.publicKey, config: configPda, pool: risky.pool })
+      .rpc();
+    await program.methods
+      .updateIsolationMode(false, new BN(0), true)
+      .accounts({ admin: admin.publicKey, config: configPda, pool: dai.pool })
+      .rpc();
+
+    // $3k borrowed against $10k of ordinary collateral, then $10k of isolated
+    // collateral deposited next to it
+    const openMixedLoan = async (debt: PricedPool) => {
+      const loan = await openLoan(safe, debt, new BN(10_000_000_000), new BN(3_000_000_000));
+      await createAssociatedTokenAccount(provider.connection, loan.borrower, risky.mint, loan.borrower.publicKey);
+      await depositInto(loan.borrower, risky, new BN(1_000_000_000));
+      return loan;
+    };
+    // withdraws all the ordinary collateral, leaving the isolated collateral
+    const withdrawSafe = (loan: Loan) =>
+      program.methods
+        .withdraw(new BN(10_000_000_000))
+        .accounts({
+          user: loan.borrower.publicKey,
+          mint: safe.mint,
+          mintDtoken: safe.dtokenMint,
+          pool: safe.pool,
+          config: configPda,
+          vault: safe.vault,
+          userDtokenAta: loan.borrowerCollateralDTokenAta,
+          userTokenAta: getAssociatedTokenAddressSync(safe.mint, loan.borrower.publicKey),
+          userPoolPosition: PublicKey.findProgramAddressSync(
+            [Buffer.from("user-pool-position"), loan.borrower.publicKey.toBuffer(), safe.pool.toBuffer()],
+            program.programId
+          )[0],
+          userPosition: loan.borrowerPosition,
+          oracle: safe.oracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dtokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(collateralAccounts(loan.borrower.publicKey, risky.pool, risky.dtokenMint, risky.oracle))
+        .signers([loan.borrower])
+        .rpc();
+
+    // USDC may not be borrowed against isolated collateral
+    const usdcLoan = await openMixedLoan(usdc);
+    try {
+      await withdrawSafe(usdcLoan);
+      assert.fail("Should not leave USDC debt backed by isolated collateral");
+    } catch (error) {
+      assert.ok(error.toString().includes("IsolationModeBorrowNotAllowed"));
+      console.log(" Rejected leaving non-isolation debt on isolated collateral");
+    }
+
+    // DAI may, and the debt moves under the ceiling
+    const daiLoan = await openMixedLoan(dai);
+    await withdrawSafe(daiLoan);
+    const riskyPool = await program.account.pool.fetch(risky.pool);
+    const position = await program.account.userPosition.fetch(daiLoan.borrowerPosition);
+    assert.equal(riskyPool.isolatedDebtUsd.toString(), "3000000000");
+    assert.equal(position.isolatedDebtUsd.toString(), "3000000000");
+    assert.equal(position.isolationPool.toBase58(), risky.pool.toBase58());
+    console.log(" Booked the remaining debt against the isolated debt ceiling");
+  });
+
+  it("requires the isolated pool to liquidate isolated debt", async () => {
+    console.log("\nTESTING ISOLATED DEBT IN LIQUIDATION");
+
+    const risky = await createPricedPool(6, new BN("1000000000"), "LRISK");
+    const safe = await createPricedPool(6, new BN("100000000"), "LSAFE");
+    const dai = await createPricedPool(6, new BN("100000000"), "LDAI");
+    await program.methods
+      .updateIsolationMode(true, new BN(5_000_000_000), false)
+      .accounts({ admin: admin.publicKey, config: configPda, pool: risky.pool })
+      .rpc();
+    await program.methods
+      .updateIsolationMode(false, new BN(0), true)
+      .accounts({ admin: admin.publicKey, config: configPda, pool: dai.pool })
+      .rpc();
+
+    // $3k of DAI booked against the risky ceiling, plus $1k of ordinary
+    // collateral that alone is far below the debt
+    const loan = await openLoan(risky, dai, new BN(1_000_000_000), new BN(3_000_000_000));
+    await createAssociatedTokenAccount(provider.connection, loan.borrower, safe.mint, loan.borrower.publicKey);
+    await depositInto(loan.borrower, safe, new BN(1_000_000_000));
+    await createAssociatedTokenAccount(provider.connection, loan.liquidator, safe.mint, loan.liquidator.publicKey);
+    const liquidatorDaiAta = getAssociatedTokenAddressSync(dai.mint, loan.liquidator.publicKey);
+    await mintTo(provider.connection, admin.payer, dai.mint, liquidatorDaiAta, admin.payer, 500_000_000);
+
+    const accounts = {
+      ...liquidateAccounts(loan, safe, dai),
+      borrowerCollateralDtokenAta: getAssociatedTokenAddressSync(safe.dtokenMint, loan.borrower.publicKey, false, TOKEN_2022_PROGRAM_ID),
+    };
+    try {
+      await program.methods
+        .liquidate(new BN(500_000_000), false)
+        .accounts(accounts)
+        .signers([loan.liquidator])
+        .rpc();
+      assert.fail("Should have required the isolated pool");
+    } catch (error) {
+      assert.ok(error.toString().includes("MissingIsolationPool"));
+      console.log(" Rejected a liquidation without the isolated pool");
+    }
+
+    await program.methods
+      .liquidate(new BN(500_000_000), false)
+      .accounts({ ...accounts, borrowerIsolationPool: risky.pool })
+      .signers([loan.liquidator])
+      .rpc();
+    const riskyPool = await program.account.pool.fetch(risky.pool);
+    const position = await program.account.userPosition.fetch(loan.borrowerPosition);
+    assert.approximately(riskyPool.isolatedDebtUsd.toNumber(), 2_500_000_000, 1_000);
+    assert.equal(position.isolatedDebtUsd.toString(), riskyPool.isolatedDebtUsd.toString());
+    console.log(" Released the repaid debt from the isolated ceiling");
+  });
+
+  it("lends at e-mode parameters when every position is in the user's category", async () => {
+    console.log("\nTESTING E-MODE");
+
+    const usdc = await createPricedPool(6, new BN("100000000"), "EUSDC");
+    const usdt = await createPricedPool(6, new BN("100000000"), "EUSDT");
+    const [stablecoins] = PublicKey.findProgramAddressSync(
+      [Buffer.from("emode-category"), configPda.toBuffer(), Buffer.from([1])],
+      program.programId
+    );
+    await program.methods
+      .createEmodeCategory(1, "USD stablecoins", 9000, 9500, 200)
+      .accounts({
+        admin: admin.publicKey,
+        config: configPda,
+        emodeCategory: stablecoins,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    for (const asset of [usdc, usdt]) {
+      await program.methods
+        .setPoolEmodeCategory()
+        .accounts({ admin: admin.publicKey, config: configPda, pool: asset.pool, emodeCategory: stablecoins })
+        .rpc();
+    }
+
+    const lender = Keypair.generate();
+    const borrower = Keypair.generate();
+    for (const user of [lender, borrower]) {
+      const sig = await provider.connection.requestAirdrop(user.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL);
+      await provider.connection.confirmTransaction(sig);
+      for (const mint of [usdc.mint, usdt.mint]) {
+        await createAssociatedTokenAccount(provider.connection, user, mint, user.publicKey);
+      }
+    }
+    await depositInto(lender, usdt, new BN(10_000_000_000));
+    await depositInto(borrower, usdc, new BN(1_000_000_000));
+
+    const [borrowerPosition] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user-position"), configPda.toBuffer(), borrower.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .updateDepositPosition()
+      .accounts({
+        user: borrower.publicKey,
+        underlyingMint: usdc.mint,
+        config: configPda,
+        pool: usdc.pool,
+        userPosition: borrowerPosition,
+        userDtokenAta: getAssociatedTokenAddressSync(usdc.dtokenMint, borrower.publicKey, false, TOKEN_2022_PROGRAM_ID),
+        oracle: usdc.oracle,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([borrower])
+      .rpc();
+    await program.methods
+      .setUserEmode()
+      .accounts({ user: borrower.publicKey, config: configPda, userPosition: borrowerPosition, emodeCategory: stablecoins })
+      .signers([borrower])
+      .rpc();
+
+    // $850 against $1,000 is past the pools' 70% LTV but within the category's 90%
+    await program.methods
+      .borrow(new BN(850_000_000))
+      .accounts({
+        user: borrower.publicKey,
+        underlyingMint: usdt.mint,
+        pool: usdt.pool,
+        config: configPda,
+        userAta: getAssociatedTokenAddressSync(usdt.mint, borrower.publicKey),
+        userPoolPosition: PublicKey.findProgramAddressSync(
+          [Buffer.from("user-pool-position"), borrower.publicKey.toBuffer(), usdt.pool.toBuffer()],
+          program.programId
+        )[0],
+        userPosition: borrowerPosition,
+        emodeCategory: stablecoins,
+        vault: usdt.vault,
+        oracle: usdt.oracle,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(collateralAccounts(borrower.publicKey, usdc.pool, usdc.dtokenMint, usdc.oracle))
+      .signers([borrower])
+      .rpc();
+    const position = await program.account.userPosition.fetch(borrowerPosition);
+    assert.equal(position.emodeCategory, 1);
+    assert.equal(position.openBorrows, 1);
+    assert.equal(position.debtValueUsd.toString(), "850000000");
+    console.log(" Borrowed at the category's LTV");
+
+    try {
+      await program.methods
+        .setUserEmode()
+        .accounts({ user: borrower.publicKey, config: configPda, userPosition: borrowerPosition, emodeCategory: null })
+        .signers([borrower])
+        .rpc();
+      assert.fail("Leaving e-mode with open borrows should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("EModeChangeWithOpenBorrows"));
+      console.log(" Rejected leaving e-mode with open borrows");
+    }
+  });
+
+  it("enforces borrow-only and collateral-only pool flags", async () => {
+    console.log("\nTESTING POOL USAGE FLAGS");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "FSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "FUSDC");
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(1_000_000_000));
+
+    const borrowMore = () =>
+      program.methods
+        .borrow(new BN(1_000_000))
+        .accounts({
+          user: loan.borrower.publicKey,
+          underlyingMint: usdc.mint,
+          pool: usdc.pool,
+          config: configPda,
+          userAta: getAssociatedTokenAddressSync(usdc.mint, loan.borrower.publicKey),
+          userPoolPosition: loan.borrowerDebtPosition,
+          userPosition: loan.borrowerPosition,
+          vault: usdc.vault,
+          oracle: usdc.oracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(collateralAccounts(loan.borrower.publicKey, sol.pool, sol.dtokenMint, sol.oracle))
+        .signers([loan.borrower])
+        .rpc();
+    const updateFlags = (pool: PublicKey, borrowingEnabled: boolean, collateralEnabled: boolean) =>
+      program.methods
+        .updatePoolFlags(borrowingEnabled, collateralEnabled)
+        .accounts({ admin: admin.publicKey, config: configPda, pool })
+        .rpc();
+
+    // collateral-only USDC
+    await updateFlags(usdc.pool, false, true);
+    try {
+      await borrowMore();
+      assert.fail("Borrow from a collateral-only pool should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("BorrowingDisabled"));
+      console.log(" Rejected a borrow from a collateral-only pool");
+    }
+
+    // borrow-only SOL backs the existing loan but no new borrows
+    await updateFlags(usdc.pool, true, true);
+    await updateFlags(sol.pool, true, false);
+    try {
+      await borrowMore();
+      assert.fail("Borrow against borrow-only collateral should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("ExceedsLTV"));
+      console.log(" Gave borrow-only collateral no borrowing power");
+    }
+
+    await updateFlags(sol.pool, true, true);
+    await borrowMore();
+    const solPool = await program.account.pool.fetch(sol.pool);
+    assert.ok(solPool.borrowingEnabled && solPool.collateralEnabled);
+  });
+
+  it("freezes and deprecates a pool to wind down its market", async () => {
+    console.log("\nTESTING POOL FREEZE AND DEPRECATION");
+
+    const sol = await createPricedPool(6, new BN("10000000000"), "WSOL");
+    const usdc = await createPricedPool(6, new BN("100000000"), "WUSDC");
+    // $10k against $100k of SOL, far from liquidation
+    const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(10_000_000_000));
+    const updateStatus = (status: object) =>
+      program.methods
+        .updatePoolStatus(status as any)
+        .accounts({ admin: admin.publicKey, config: configPda, pool: usdc.pool })
+        .rpc();
+
+    await updateStatus({ frozen: {} });
+    try {
+      await depositInto(loan.liquidator, usdc, new BN(1_000_000));
+      assert.fail("Deposit into a frozen pool should fail");
+    } catch (error) {
+      assert.ok(error.toString().includes("PoolNotActive"));
+      console.log(" Rejected a deposit into a frozen pool");
+    }
+    try {
+      await liquidateLoan(loan, sol, usdc, new BN(1_000_000_000));
+      assert.fail("Healthy positions in a frozen pool should not be liquidatable");
+    } catch (error) {
+      assert.ok(error.toString().includes("NotLiquidatable"));
+    }
+
+    // deprecated: the healthy loan can be closed in full
+    await updateStatus({ deprecated: {} });
+    await liquidateLoan(loan, sol, usdc, new BN(10_000_000_000));
+    const debtPosition = await program.account.userPoolPosition.fetch(loan.borrowerDebtPosition);
+    assert.equal(debtPosition.borrowedAmount.toString(), "0");
+    console.log(" Closed a healthy position in a deprecated pool");
+
+    try {
+      await updateStatus({ active: {} });
+      assert.fail("Deprecated pools should not be reactivated");
+    } catch (error) {
+      assert.ok(error.toString().includes("InvalidPoolStatusTransition"));
+    }
+  });
+
+  it("runs a second market for the same admin with its own pool per mint", async () => {
+    console.log("\nTESTING MULTIPLE MARKETS");
+
+    const [longTailConfig] = PublicKey.findProgramAddressSync(
+      [Buffer.from("config"), admin.publicKey.toBuffer(), marketSeed(1)],
+      program.programId
+    );
+    await program.methods
+      .initConfig(1)
+      .accounts({
+        initializer: admin.publicKey,
+        config: longTailConfig,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    // mintX already has a pool in the main market
+    const [oracle] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mock-oracle"), longTailConfig.toBuffer(), mintX.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .createMockOracle(new BN("10000000000"), new BN(50000), -8)
+      .accounts({
+        admin: admin.publicKey,
+        config: longTailConfig,
+        mint: mintX,
+        mockOracle: oracle,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    const [longTailPool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), longTailConfig.toBuffer(), mintX.toBuffer()],
+      program.programId
+    );
+    const dtokenMint = Keypair.generate();
+    await program.methods
+      .createPool(
+        { mock: {} }, Array(32).fill(0),
+        "Deposit X long tail", "dXLT", "",
+        5000, 4000, 1000, 5000,
+        new BN("10000000000000000"),
+        new BN("50000000000000000"),
+        new BN("200000000000000000"),
+        new BN("800000000000000000"),
+        new BN(3600), 200
+      )
+      .accounts({
+        admin: admin.publicKey,
+        mint: mintX,
+        config: longTailConfig,
+        dtokenMint: dtokenMint.publicKey,
+        pool: longTailPool,
+        vault: getAssociatedTokenAddressSync(mintX, longTailPool, true),
+        oracle: oracle,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dtokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([dtokenMint])
+      .rpc();
+
+    const config = await program.account.config.fetch(longTailConfig);
+    const pool = await program.account.pool.fetch(longTailPool);
+    const mainPool = await program.account.pool.fetch(poolPda);
+    assert.equal(config.marketId, 1);
+    assert.notEqual(longTailPool.toBase58(), poolPda.toBase58());
+    assert.equal(pool.ltvBps, 4000);
+    assert.notEqual(mainPool.ltvBps, pool.ltvBps);
+    console.log(" Created an independent pool for the same mint in a second market");
+  });
+
+  // the write-off needs every pool of the market, so each test gets a market
+  // of its own that only holds the pools it creates
+  describe("bad debt", () => {
+    let mainConfigPda: PublicKey;
+    let nextMarketId = 2;
+
+    before(() => {
+      mainConfigPda = configPda;
+    });
+
+    beforeEach(async () => {
+      const marketId = nextMarketId++;
+      [configPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("config"), admin.publicKey.toBuffer(), marketSeed(marketId)],
+        program.programId
+      );
+      await program.methods
+        .initConfig(marketId)
+        .accounts({
+          initializer: admin.publicKey,
+          config: configPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    });
+
+    after(() => {
+      configPda = mainConfigPda;
+    });
+
+    it("writes off debt left behind once a borrower's collateral is gone", async () => {
+      console.log("\nTESTING BAD DEBT WRITE-OFF");
+
+      const sol = await createPricedPool(6, new BN("10000000000"), "BSOL");
+      const usdc = await createPricedPool(6, new BN("100000000"), "BUSDC");
+      const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+
+      // SOL crashes to $20: the 1000 SOL left is worth $20k against $60k of debt
+      await program.methods
+        .updateMockOracle(new BN("2000000000"), -8)
+        .accounts({
+          admin: admin.publicKey,
+          mockOracle: sol.oracle,
+          config: configPda,
+        })
+        .rpc();
+
+      const usdcBefore = await program.account.pool.fetch(usdc.pool);
+      // $20k repaid would seize 1050 SOL, so the seizure is capped at all 1000
+      await liquidateLoan(loan, sol, usdc, new BN(20_000_000_000));
+      let debtPosition = await program.account.userPoolPosition.fetch(loan.borrowerDebtPosition);
+      assert.ok(debtPosition.borrowedAmount.gte(new BN(40_000_000_000)), "Liquidation should leave the shortfall in place");
+
+      // the borrower never held USDC dTokens, so that ATA does not exist
+      try {
+        await program.methods
+          .writeOffBadDebt()
+          .accounts(writeOffAccounts(loan, usdc))
+          .remainingAccounts(marketPoolAccounts(loan.borrower.publicKey, [sol]))
+          .rpc();
+        assert.fail("Should have required every pool of the market");
+      } catch (error) {
+        assert.ok(error.toString().includes("MissingCollateralPools"));
+      }
+
+      let badDebt: BN | undefined;
+      let socialized: BN | undefined;
+      const listener = program.addEventListener("badDebtEvent", (event) => {
+        badDebt = event.badDebt;
+        socialized = event.socialized;
+      });
+      await program.methods
+        .writeOffBadDebt()
+        .accounts(writeOffAccounts(loan, usdc))
+        .remainingAccounts(marketPoolAccounts(loan.borrower.publicKey, [sol, usdc]))
+        .rpc();
+      await new Promise(resolve => setTimeout(resolve, 1000));
+      await program.removeEventListener(listener);
+
+      assert.ok(badDebt && badDebt.gte(new BN(40_000_000_000)), "Remaining $40k should be bad debt");
+      assert.ok(socialized.eq(badDebt), "Without reserves all of it is socialized");
+      debtPosition = await program.account.userPoolPosition.fetch(loan.borrowerDebtPosition);
+      assert.equal(debtPosition.borrowedAmount.toString(), "0", "Borrower debt should be cleared");
+      const usdcAfter = await program.account.pool.fetch(usdc.pool);
+      assert.equal(
+        usdcAfter.totalLiquidity.toString(),
+        usdcBefore.totalLiquidity.sub(badDebt).toString(),
+        "Suppliers should absorb the loss through the exchange rate"
+      );
+      assert.ok(usdcAfter.totalBorrowed.lt(usdcBefore.totalBorrowed.sub(badDebt)), "Total borrowed should drop by the repayment and the write-off");
+      console.log(" Wrote off", badDebt.toString(), "USDC base units of bad debt");
+    });
+
+    it("keeps the debt of a borrower who still has other collateral", async () => {
+      console.log("\nTESTING NO WRITE-OFF WITH OTHER COLLATERAL");
+
+      const sol = await createPricedPool(6, new BN("10000000000"), "KSOL");
+      const eth = await createPricedPool(6, new BN("10000000000"), "KETH");
+      const usdc = await createPricedPool(6, new BN("100000000"), "KUSDC");
+      const loan = await openLoan(sol, usdc, new BN(1_000_000_000), new BN(60_000_000_000));
+      await createAssociatedTokenAccount(provider.connection, loan.borrower, eth.mint, loan.borrower.publicKey);
+      await depositInto(loan.borrower, eth, new BN(100_000_000));
+
+      await program.methods
+        .updateMockOracle(new BN("2000000000"), -8)
+        .accounts({
+          admin: admin.publicKey,
+          mockOracle: sol.oracle,
+          config: configPda,
+        })
+        .rpc();
+
+      // every SOL dToken is seized, but the 100 ETH still backs the debt
+      await liquidateLoan(loan, sol, usdc, new BN(20_000_000_000));
+      const solDTokens = await getAccount(provider.connection, loan.borrowerCollateralDTokenAta, undefined, TOKEN_2022_PROGRAM_ID);
+      assert.equal(solDTokens.amount.toString(), "0");
+
+      // leaving the ETH pool out cannot hide the collateral in it
+      try {
+        await program.methods
+          .writeOffBadDebt()
+          .accounts(writeOffAccounts(loan, usdc))
+          .remainingAccounts(marketPoolAccounts(loan.borrower.publicKey, [sol, usdc]))
+          .rpc();
+        assert.fail("Should have required the ETH pool");
+      } catch (error) {
+        assert.ok(error.toString().includes("MissingCollateralPools"));
+      }
+
+      try {
+        await program.methods
+          .writeOffBadDebt()
+          .accounts(writeOffAccounts(loan, usdc))
+          .remainingAccounts(marketPoolAccounts(loan.borrower.publicKey, [sol, eth, usdc]))
+          .rpc();
+        assert.fail("Should not write off debt the borrower's ETH still backs");
+      } catch (error) {
+        assert.ok(error.toString().includes("BorrowerHasCollateral"));
+      }
+      const debtPosition = await program.account.userPoolPosition.fetch(loan.borrowerDebtPosition);
+      assert.ok(debtPosition.borrowedAmount.gte(new BN(40_000_000_000)), "Borrower should still owe the rest");
+      console.log(" Debt kept:", debtPosition.borrowedAmount.toString());
+    });
+  });
+});